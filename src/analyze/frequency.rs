@@ -0,0 +1,115 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+
+use super::{emoji, sender_key, words};
+use crate::error::AppError;
+use crate::export::MessageRecord;
+
+/// Common English words left out of word counts
+const STOPWORDS: &[&str] = &[
+    "a", "about", "after", "again", "all", "am", "an", "and", "any", "are", "as", "at", "be", "because", "been",
+    "before", "but", "by", "can", "could", "did", "do", "does", "doing", "for", "from", "get", "got", "had", "has",
+    "have", "he", "her", "here", "him", "his", "how", "i", "i'm", "if", "in", "into", "is", "it", "it's", "its",
+    "just", "me", "my", "of", "on", "or", "our", "out", "she", "so", "than", "that", "that's", "the", "their",
+    "them", "then", "there", "they", "this", "to", "too", "u", "up", "was", "we", "were", "what", "when", "where",
+    "which", "who", "why", "will", "with", "would", "you", "you're", "your",
+];
+
+/// What is counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Term {
+    Word,
+    Emoji,
+}
+
+impl Term {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Term::Word => "word",
+            Term::Emoji => "emoji",
+        }
+    }
+
+    fn terms(&self, text: &str) -> Vec<String> {
+        match self {
+            // Links would otherwise count as "https", "www" and so on
+            Term::Word => text
+                .split_whitespace()
+                .filter(|token| !token.contains("://") && !token.starts_with("www."))
+                .flat_map(words)
+                .filter(|w| !STOPWORDS.contains(&w.as_str()) && !w.chars().all(|c| c.is_ascii_digit()))
+                .collect(),
+            Term::Emoji => emoji(text),
+        }
+    }
+}
+
+/// A word or emoji and how often it was used
+#[derive(Debug, Clone, Serialize)]
+pub struct TermCount {
+    pub term: String,
+    pub count: usize,
+}
+
+/// The most used words or emoji overall and by each sender (`me` for the user)
+#[derive(Debug, Clone, Serialize)]
+pub struct FrequencyReport {
+    pub overall: Vec<TermCount>,
+    pub contacts: BTreeMap<String, Vec<TermCount>>,
+}
+
+/// The `top` terms used at least `min_count` times, most used first
+fn ranked(counts: HashMap<String, usize>, top: usize, min_count: usize) -> Vec<TermCount> {
+    let mut ranked: Vec<TermCount> = counts
+        .into_iter()
+        .filter(|(_, count)| *count >= min_count)
+        .map(|(term, count)| TermCount { term, count })
+        .collect();
+    ranked.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.term.cmp(&b.term)));
+    ranked.truncate(top);
+    ranked
+}
+
+/// Count the words (without stopwords) or emoji in `records`
+pub fn frequency(records: &[MessageRecord], term: Term, top: usize, min_count: usize) -> FrequencyReport {
+    let mut overall: HashMap<String, usize> = HashMap::new();
+    let mut contacts: HashMap<String, HashMap<String, usize>> = HashMap::new();
+    for record in records {
+        let Some(text) = record.text.as_deref() else {
+            continue;
+        };
+        let contact = contacts.entry(sender_key(record)).or_default();
+        for found in term.terms(text) {
+            *contact.entry(found.clone()).or_default() += 1;
+            *overall.entry(found).or_default() += 1;
+        }
+    }
+    FrequencyReport {
+        overall: ranked(overall, top, min_count),
+        contacts: contacts
+            .into_iter()
+            .map(|(contact, counts)| (contact, ranked(counts, top, min_count)))
+            .filter(|(_, counts)| !counts.is_empty())
+            .collect(),
+    }
+}
+
+/// Write frequency reports as CSV rows of kind, contact (`all` for overall), rank, term and count
+pub fn write_csv<W: Write>(out: W, reports: &[(Term, &FrequencyReport)]) -> Result<(), AppError> {
+    let mut writer = csv::Writer::from_writer(out);
+    writer.write_record(["kind", "contact", "rank", "term", "count"])?;
+    for (term, report) in reports {
+        let tables = std::iter::once(("all", &report.overall))
+            .chain(report.contacts.iter().map(|(contact, counts)| (contact.as_str(), counts)));
+        for (contact, counts) in tables {
+            for (rank, count) in counts.iter().enumerate() {
+                let rank = (rank + 1).to_string();
+                let total = count.count.to_string();
+                writer.write_record([term.as_str(), contact, &rank, &count.term, &total])?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
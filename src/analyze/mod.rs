@@ -0,0 +1,91 @@
+//! Offline analyses of exported messages, for the `analyze` command
+
+use crate::export::MessageRecord;
+
+pub mod frequency;
+pub mod response;
+pub mod sentiment;
+
+/// Who a message is attributed to in per-contact reports: its sender's
+/// handle, or `me` for the user's own messages
+pub fn sender_key(record: &MessageRecord) -> String {
+    if record.from_me {
+        "me".to_string()
+    } else {
+        record.from.clone().unwrap_or_else(|| "unknown".to_string())
+    }
+}
+
+/// The words of `text`, lowercased, with apostrophes kept so "don't" stays one word
+pub fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '\'' || c == '’'))
+        .map(|word| word.trim_matches(['\'', '’']).to_lowercase().replace('’', "'"))
+        .filter(|word| !word.is_empty())
+}
+
+/// Whether `c` starts an emoji: pictographs, symbols and regional indicator letters
+fn is_emoji_base(c: char) -> bool {
+    matches!(c as u32,
+        0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B00..=0x2BFF | 0x2300..=0x23FF
+        | 0x3030 | 0x303D | 0x3297 | 0x3299 | 0x203C | 0x2049)
+        && !is_skin_tone(c)
+}
+
+fn is_skin_tone(c: char) -> bool {
+    matches!(c as u32, 0x1F3FB..=0x1F3FF)
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    matches!(c as u32, 0x1F1E6..=0x1F1FF)
+}
+
+/// Whether `text` is nothing but emoji, and whitespace between them
+pub fn is_only_emoji(text: &str) -> bool {
+    let mut any = false;
+    for c in text.chars() {
+        if is_emoji_base(c) {
+            any = true;
+        } else if !(c.is_whitespace() || c == '\u{FE0F}' || c == '\u{200D}' || is_skin_tone(c)) {
+            return false;
+        }
+    }
+    any
+}
+
+/// The emoji in `text`, each with its skin tone, variation selector and
+/// zero-width-joined parts, so 👍🏽 and 👨‍👩‍👧 count as one emoji each
+pub fn emoji(text: &str) -> Vec<String> {
+    let mut found = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if !is_emoji_base(c) {
+            continue;
+        }
+        let mut emoji = c.to_string();
+        // Flags are pairs of regional indicators
+        if is_regional_indicator(c) {
+            if let Some(&next) = chars.peek().filter(|n| is_regional_indicator(**n)) {
+                emoji.push(next);
+                chars.next();
+            }
+            found.push(emoji);
+            continue;
+        }
+        while let Some(&next) = chars.peek() {
+            if next == '\u{FE0F}' || is_skin_tone(next) {
+                emoji.push(next);
+                chars.next();
+            } else if next == '\u{200D}' {
+                emoji.push(next);
+                chars.next();
+                if let Some(joined) = chars.next_if(|n| is_emoji_base(*n)) {
+                    emoji.push(joined);
+                }
+            } else {
+                break;
+            }
+        }
+        found.push(emoji);
+    }
+    found
+}
@@ -0,0 +1,131 @@
+use chrono::Timelike;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::export::MessageRecord;
+
+/// How long replies took, in seconds
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ResponseStats {
+    pub replies: usize,
+    pub median: i64,
+    pub p25: i64,
+    pub p75: i64,
+    pub p90: i64,
+}
+
+impl ResponseStats {
+    /// The nearest-rank percentiles of `seconds`
+    fn from_seconds(seconds: &mut [i64]) -> Self {
+        if seconds.is_empty() {
+            return ResponseStats::default();
+        }
+        seconds.sort_unstable();
+        let percentile = |p: usize| seconds[(p * seconds.len()).div_ceil(100).max(1) - 1];
+        ResponseStats { replies: seconds.len(), median: percentile(50), p25: percentile(25), p75: percentile(75), p90: percentile(90) }
+    }
+}
+
+/// Reply times in one direction, overall, by the hour of day the message
+/// being answered arrived, and by `YYYY-MM`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DirectionStats {
+    #[serde(flatten)]
+    pub overall: ResponseStats,
+    pub by_hour: BTreeMap<u32, ResponseStats>,
+    pub by_month: BTreeMap<String, ResponseStats>,
+}
+
+/// How fast the user answers a contact, and how fast the contact answers back
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ContactResponseTimes {
+    /// The user replying to the contact
+    pub me: DirectionStats,
+    /// The contact replying to the user
+    pub them: DirectionStats,
+}
+
+/// Reply times for every one-on-one conversation, overall and per contact
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ResponseReport {
+    pub overall: ContactResponseTimes,
+    pub contacts: BTreeMap<String, ContactResponseTimes>,
+}
+
+/// Each reply's delay, kept until percentiles are taken
+#[derive(Default)]
+struct Samples {
+    overall: Vec<i64>,
+    by_hour: BTreeMap<u32, Vec<i64>>,
+    by_month: BTreeMap<String, Vec<i64>>,
+}
+
+impl Samples {
+    fn add(&mut self, asked: &MessageRecord, seconds: i64) {
+        self.overall.push(seconds);
+        self.by_hour.entry(asked.date.hour()).or_default().push(seconds);
+        self.by_month.entry(asked.date.format("%Y-%m").to_string()).or_default().push(seconds);
+    }
+
+    fn stats(mut self) -> DirectionStats {
+        DirectionStats {
+            overall: ResponseStats::from_seconds(&mut self.overall),
+            by_hour: self.by_hour.into_iter().map(|(k, mut v)| (k, ResponseStats::from_seconds(&mut v))).collect(),
+            by_month: self.by_month.into_iter().map(|(k, mut v)| (k, ResponseStats::from_seconds(&mut v))).collect(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ContactSamples {
+    me: Samples,
+    them: Samples,
+}
+
+impl ContactSamples {
+    fn stats(self) -> ContactResponseTimes {
+        ContactResponseTimes { me: self.me.stats(), them: self.them.stats() }
+    }
+}
+
+/// Measure reply times in `records`
+///
+/// A reply is the first message after the other side's turn, timed from the
+/// first message of that turn, so a burst of texts answered at once counts
+/// as one reply. Group chats are left out, since who's answering whom can't
+/// be told apart.
+pub fn analyze(records: &[MessageRecord]) -> ResponseReport {
+    let mut chats: BTreeMap<i32, Vec<&MessageRecord>> = BTreeMap::new();
+    for record in records {
+        if let Some(chat_id) = record.chat_id.filter(|_| record.participants.len() <= 1) {
+            chats.entry(chat_id).or_default().push(record);
+        }
+    }
+
+    let mut overall = ContactSamples::default();
+    let mut contacts: BTreeMap<String, ContactSamples> = BTreeMap::new();
+    for messages in chats.values_mut() {
+        messages.sort_by_key(|m| m.date);
+        let mut turn_start: Option<&MessageRecord> = None;
+        for &message in messages.iter() {
+            match turn_start {
+                Some(asked) if asked.from_me == message.from_me => continue,
+                Some(asked) => {
+                    let contact = if message.from_me { &asked.from } else { &message.from };
+                    let contact = contact.clone().unwrap_or_else(|| "unknown".to_string());
+                    let seconds = (message.date - asked.date).num_seconds();
+                    for samples in [&mut overall, contacts.entry(contact).or_default()] {
+                        if message.from_me { &mut samples.me } else { &mut samples.them }.add(asked, seconds);
+                    }
+                }
+                None => {}
+            }
+            turn_start = Some(message);
+        }
+    }
+
+    ResponseReport {
+        overall: overall.stats(),
+        contacts: contacts.into_iter().map(|(contact, samples)| (contact, samples.stats())).collect(),
+    }
+}
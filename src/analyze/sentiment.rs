@@ -0,0 +1,172 @@
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use super::{sender_key, words};
+use crate::export::MessageRecord;
+
+/// Words and how positive (up to 5) or negative (down to -5) they are, in the
+/// style of the AFINN lexicon
+const LEXICON: &[(&str, i32)] = &[
+    ("abandon", -2), ("abandoned", -2), ("abuse", -3), ("accept", 1), ("accident", -2), ("ache", -2),
+    ("adore", 3), ("afraid", -2), ("agree", 1), ("alone", -2), ("amazing", 4), ("angry", -3),
+    ("annoyed", -2), ("annoying", -2), ("anxious", -2), ("apologize", -1), ("appreciate", 2),
+    ("awesome", 4), ("awful", -3), ("bad", -3), ("beautiful", 3), ("best", 3), ("better", 2),
+    ("blessed", 3), ("bored", -2), ("boring", -3), ("brilliant", 4), ("broke", -1), ("broken", -1),
+    ("calm", 2), ("care", 2), ("celebrate", 3), ("cheer", 2), ("congrats", 2), ("congratulations", 2),
+    ("cool", 1), ("crap", -3), ("crazy", -2), ("cry", -1), ("crying", -2), ("cute", 2), ("damn", -2),
+    ("dead", -3), ("delighted", 3), ("depressed", -2), ("disappointed", -2), ("disappointing", -2),
+    ("disaster", -2), ("disgusting", -3), ("dislike", -2), ("dumb", -3), ("easy", 1), ("excellent", 3),
+    ("excited", 3), ("exciting", 3), ("fail", -2), ("failed", -2), ("fantastic", 4), ("fear", -2),
+    ("fine", 2), ("fun", 4), ("funny", 4), ("glad", 3), ("good", 3), ("gorgeous", 3), ("grateful", 3),
+    ("great", 3), ("grief", -2), ("happy", 3), ("hate", -3), ("hated", -3), ("headache", -2),
+    ("hell", -4), ("help", 2), ("helpful", 2), ("hope", 2), ("horrible", -3), ("hurt", -2), ("ill", -2),
+    ("impressed", 3), ("jealous", -2), ("joy", 3), ("kind", 2), ("kiss", 2), ("lame", -2), ("laugh", 1),
+    ("lol", 3), ("lonely", -2), ("lost", -3), ("love", 3), ("loved", 3), ("lovely", 3), ("lucky", 3),
+    ("mad", -3), ("mess", -2), ("miserable", -3), ("miss", -2), ("nervous", -2), ("nice", 3),
+    ("pain", -2), ("perfect", 3), ("pissed", -4), ("pleased", 3), ("poor", -2),
+    ("pretty", 1), ("problem", -2), ("proud", 2), ("relieved", 2), ("sad", -2), ("scared", -2),
+    ("shit", -4), ("sick", -2), ("smile", 2), ("sorry", -1), ("stressed", -2), ("stupid", -2),
+    ("super", 3), ("sweet", 2), ("terrible", -3), ("thank", 2), ("thanks", 2), ("tired", -2),
+    ("ugh", -2), ("ugly", -3), ("unfortunately", -2), ("upset", -2), ("welcome", 2), ("win", 4),
+    ("wonderful", 4), ("worried", -3), ("worse", -3), ("worst", -3), ("wow", 4), ("wrong", -2),
+    ("yay", 3), ("yes", 1),
+];
+
+/// Emoji and how positive or negative they are
+const EMOJI: &[(char, i32)] = &[
+    ('😀', 2), ('😃', 2), ('😄', 2), ('😁', 2), ('😂', 3), ('🤣', 3), ('😊', 2), ('😍', 3), ('🥰', 3),
+    ('😘', 3), ('❤', 3), ('💕', 3), ('👍', 2), ('🎉', 3), ('🙏', 1), ('😢', -2), ('😭', -2), ('😞', -2),
+    ('😠', -3), ('😡', -3), ('👎', -2), ('💔', -3), ('😩', -2), ('😫', -2), ('🙄', -1), ('😒', -2),
+];
+
+/// Words that flip the score of the word after them
+const NEGATIONS: &[&str] = &["not", "no", "never", "don't", "dont", "isn't", "wasn't", "can't", "cant", "won't", "didn't"];
+
+/// Words that strengthen the score of the word after them
+const INTENSIFIERS: &[&str] = &["very", "so", "really", "super", "extremely", "totally"];
+
+/// A message's sentiment
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageSentiment {
+    pub id: i64,
+    pub date: DateTime<FixedOffset>,
+    /// The sender's handle, or `me`
+    pub from: String,
+    /// The sum of the scores of its words and emoji
+    pub score: i32,
+    /// `score` per word, comparable between short and long messages
+    pub comparative: f64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub positive: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub negative: Vec<String>,
+}
+
+/// Score `text` against the lexicon, returning the score, the number of
+/// words, and the positive and negative words found
+pub fn score(text: &str) -> (i32, usize, Vec<String>, Vec<String>) {
+    let mut total = 0;
+    let mut count = 0;
+    let (mut positive, mut negative) = (Vec::new(), Vec::new());
+    let mut negated = false;
+    let mut intensified = false;
+    for word in words(text) {
+        count += 1;
+        let found = LEXICON.iter().find(|(w, _)| *w == word).map(|(_, value)| *value);
+        match found {
+            Some(mut value) => {
+                if intensified {
+                    value += value.signum();
+                }
+                if negated {
+                    value = -value;
+                }
+                total += value;
+                if value > 0 { &mut positive } else { &mut negative }.push(word);
+                negated = false;
+                intensified = false;
+            }
+            _ => {
+                negated = NEGATIONS.contains(&word.as_str());
+                intensified = INTENSIFIERS.contains(&word.as_str());
+            }
+        }
+    }
+    for c in text.chars() {
+        if let Some((emoji, value)) = EMOJI.iter().find(|(e, _)| *e == c) {
+            total += value;
+            if *value > 0 { &mut positive } else { &mut negative }.push(emoji.to_string());
+        }
+    }
+    (total, count, positive, negative)
+}
+
+/// The sentiment of one message; `None` if it has no text
+pub fn message_sentiment(record: &MessageRecord) -> Option<MessageSentiment> {
+    let text = record.text.as_deref().filter(|t| !t.trim().is_empty())?;
+    let (score, words, positive, negative) = score(text);
+    Some(MessageSentiment {
+        id: record.id,
+        date: record.date,
+        from: sender_key(record),
+        score,
+        comparative: if words == 0 { score as f64 } else { score as f64 / words as f64 },
+        positive,
+        negative,
+    })
+}
+
+/// Average sentiment over a set of messages
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SentimentSummary {
+    pub messages: usize,
+    pub positive: usize,
+    pub negative: usize,
+    pub neutral: usize,
+    /// Mean of the messages' scores
+    pub average_score: f64,
+}
+
+impl SentimentSummary {
+    fn add(&mut self, sentiment: &MessageSentiment) {
+        self.average_score = (self.average_score * self.messages as f64 + sentiment.score as f64) / (self.messages + 1) as f64;
+        self.messages += 1;
+        match sentiment.score.signum() {
+            1 => self.positive += 1,
+            -1 => self.negative += 1,
+            _ => self.neutral += 1,
+        }
+    }
+}
+
+/// How one contact's messages score, overall and month by month
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ContactSentiment {
+    #[serde(flatten)]
+    pub overall: SentimentSummary,
+    /// By `YYYY-MM`
+    pub by_month: BTreeMap<String, SentimentSummary>,
+}
+
+/// Per-message scores, and a summary for each sender (`me` for the user)
+#[derive(Debug, Clone, Serialize)]
+pub struct SentimentReport {
+    pub overall: SentimentSummary,
+    pub contacts: BTreeMap<String, ContactSentiment>,
+    pub messages: Vec<MessageSentiment>,
+}
+
+/// Score every message in `records` that has text
+pub fn analyze(records: &[MessageRecord]) -> SentimentReport {
+    let mut report =
+        SentimentReport { overall: SentimentSummary::default(), contacts: BTreeMap::new(), messages: Vec::new() };
+    for sentiment in records.iter().filter_map(message_sentiment) {
+        report.overall.add(&sentiment);
+        let contact = report.contacts.entry(sentiment.from.clone()).or_default();
+        contact.overall.add(&sentiment);
+        contact.by_month.entry(sentiment.date.format("%Y-%m").to_string()).or_default().add(&sentiment);
+        report.messages.push(sentiment);
+    }
+    report
+}
@@ -0,0 +1,199 @@
+use clap::ValueEnum;
+use hmac::{Hmac, Mac};
+use imessage_database::util::dirs::home;
+use regex::{Captures, Regex};
+use sha2::Sha256;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::contacts::handle_key;
+use crate::error::AppError;
+use crate::export::MessageRecord;
+
+/// Kinds of personal details `--redact` can scrub from message text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Redaction {
+    /// Email addresses
+    Emails,
+    /// Credit and debit card numbers that pass the Luhn check
+    Cards,
+    /// Phone numbers
+    Phones,
+    /// Street addresses like "221 Baker Street"
+    Addresses,
+}
+
+impl Redaction {
+    /// What a match is replaced with
+    fn placeholder(&self) -> &'static str {
+        match self {
+            Redaction::Emails => "[email]",
+            Redaction::Cards => "[card]",
+            Redaction::Phones => "[phone]",
+            Redaction::Addresses => "[address]",
+        }
+    }
+
+    fn pattern(&self) -> &'static str {
+        match self {
+            Redaction::Emails => r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}",
+            Redaction::Cards => r"\b\d(?:[ -]?\d){12,18}\b",
+            Redaction::Phones => r"(?:\+\d{1,3}[\s.-]?)?(?:\(\d{3}\)\s?|\b\d{3}[\s.-]?|\b)\d{3}[\s.-]?\d{4}\b",
+            Redaction::Addresses => {
+                r"(?i)\b\d{1,6}(?:\s+[A-Za-z0-9.'-]+){1,4}\s+(?:street|st|avenue|ave|road|rd|boulevard|blvd|lane|ln|drive|dr|court|ct|way|place|pl|terrace|parkway|pkwy|highway|hwy)\b\.?"
+            }
+        }
+    }
+}
+
+/// Whether a string of digits passes the Luhn checksum used by card numbers
+fn luhn(digits: &str) -> bool {
+    let sum: u32 = digits
+        .chars()
+        .rev()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 1 { if d * 2 > 9 { d * 2 - 9 } else { d * 2 } } else { d })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// Makes an export safe to share: handles become stable pseudonyms and
+/// personal details are scrubbed from message text
+#[derive(Debug, Clone)]
+pub struct Anonymizer {
+    /// Secret for the pseudonym HMAC; `None` leaves handles alone
+    key: Option<Vec<u8>>,
+    /// Applied in order, so emails go before the digits inside them are taken for phone numbers
+    patterns: Vec<(Redaction, Regex)>,
+}
+
+impl Anonymizer {
+    pub fn new(key: Option<Vec<u8>>, redactions: &[Redaction]) -> Self {
+        let mut redactions = redactions.to_vec();
+        redactions.sort();
+        redactions.dedup();
+        let patterns = redactions
+            .into_iter()
+            .map(|r| (r, Regex::new(r.pattern()).expect("redaction patterns are valid")))
+            .collect();
+        Anonymizer { key, patterns }
+    }
+
+    /// `~/.imessage-blaster/anonymize.key`
+    pub fn default_key_path() -> PathBuf {
+        PathBuf::from(home()).join(".imessage-blaster").join("anonymize.key")
+    }
+
+    /// Read the pseudonym key at `path`, creating a random one the first time
+    /// so pseudonyms stay the same from one export to the next
+    pub fn load_key(path: &Path) -> Result<Vec<u8>, AppError> {
+        if path.exists() {
+            return Ok(fs::read(path)?);
+        }
+        let mut key = vec![0u8; 32];
+        fs::File::open("/dev/urandom")?.read_exact(&mut key)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Anyone with the key can check a guess at who a pseudonym is
+        let mut file = fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?;
+        file.write_all(&key)?;
+        Ok(key)
+    }
+
+    /// The pseudonym for a handle, like `person-3f9a1c0b7d2e`. Handles are
+    /// matched loosely (see [`handle_key`]), so every format of a number gets the same one.
+    pub fn pseudonym(&self, handle: &str) -> String {
+        let Some(key) = &self.key else {
+            return handle.to_string();
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+        mac.update(handle_key(handle).as_bytes());
+        let digest = mac.finalize().into_bytes();
+        let hex: String = digest[..6].iter().map(|b| format!("{:02x}", b)).collect();
+        format!("person-{}", hex)
+    }
+
+    /// Scrub the configured kinds of personal details from `text`
+    pub fn redact(&self, text: &str) -> String {
+        self.patterns.iter().fold(text.to_string(), |text, (redaction, regex)| {
+            regex
+                .replace_all(&text, |caps: &Captures| {
+                    let matched = &caps[0];
+                    let digits: String = matched.chars().filter(|c| c.is_ascii_digit()).collect();
+                    if *redaction == Redaction::Cards && !luhn(&digits) {
+                        matched.to_string()
+                    } else {
+                        redaction.placeholder().to_string()
+                    }
+                })
+                .into_owned()
+        })
+    }
+
+    /// Anonymize a record in place. Contact and chat names are dropped, as
+    /// they would identify people as surely as their handles.
+    pub fn apply(&self, record: &mut MessageRecord) {
+        if self.key.is_some() {
+            let pseudonym = |handle: &mut Option<String>| {
+                if let Some(h) = handle {
+                    *h = self.pseudonym(h);
+                }
+            };
+            pseudonym(&mut record.from);
+            pseudonym(&mut record.to);
+            pseudonym(&mut record.account);
+            for participant in &mut record.participants {
+                *participant = self.pseudonym(participant);
+            }
+            for reaction in &mut record.reactions {
+                pseudonym(&mut reaction.from);
+            }
+            // An event's text names who was added or removed
+            if let Some(participant) = record.event_participant.take() {
+                let pseudonym = self.pseudonym(&participant);
+                record.text = record.text.as_deref().map(|t| t.replace(&participant, &pseudonym));
+                record.event_participant = Some(pseudonym);
+            }
+            // A new chat name is dropped with the chat's own
+            if record.event_name.take().is_some() {
+                record.text = Some("renamed the conversation".to_string());
+            }
+            record.handle_info = std::mem::take(&mut record.handle_info)
+                .into_iter()
+                .map(|(handle, info)| (self.pseudonym(&handle), info))
+                .collect();
+            record.from_name = None;
+            record.to_name = None;
+            record.chat_name = None;
+        }
+        if !self.patterns.is_empty() {
+            record.text = record.text.as_deref().map(|t| self.redact(t));
+            if let Some(payload) = &mut record.app_payload {
+                for field in [&mut payload.title, &mut payload.subtitle, &mut payload.caption] {
+                    *field = field.as_deref().map(|t| self.redact(t));
+                }
+                for value in payload.details.values_mut() {
+                    *value = self.redact(value);
+                }
+            }
+            for edit in &mut record.edit_history {
+                edit.text = edit.text.as_deref().map(|t| self.redact(t));
+            }
+            for link in &mut record.links {
+                *link = self.redact(link);
+            }
+            if let Some(preview) = &mut record.link_preview {
+                for field in [&mut preview.url, &mut preview.original_url, &mut preview.title, &mut preview.summary] {
+                    *field = field.as_deref().map(|t| self.redact(t));
+                }
+            }
+            for attachment in &mut record.attachments {
+                attachment.transcript = attachment.transcript.as_deref().map(|t| self.redact(t));
+            }
+        }
+    }
+}
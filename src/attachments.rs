@@ -0,0 +1,184 @@
+use chrono::{DateTime, FixedOffset};
+use imessage_database::{tables::attachment::Attachment, util::platform::Platform};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::error::AppError;
+use crate::export::MessageRecord;
+use crate::verify::AttachmentStatus;
+
+/// An attachment as it appears in the output
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct AttachmentRecord {
+    pub filename: Option<String>,
+    pub mime_type: Option<String>,
+    /// The exported copy when `--attachments-dir` is set, otherwise the file in
+    /// `~/Library/Messages/Attachments`. `None` if the file isn't on disk.
+    pub path: Option<String>,
+    /// The copy in its Apple-only format, with `--convert-media --keep-originals`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_path: Option<String>,
+    pub size: i64,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    #[schemars(default)]
+    pub is_sticker: bool,
+    /// What was said in an audio message, with `--transcribe-audio`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transcript: Option<String>,
+    /// A small preview of an image or video, relative to the transcripts'
+    /// directory, with `--thumbnails`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
+    /// Whether the file is on disk, with `--verify-attachments`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<AttachmentStatus>,
+    /// SHA-256 of the file, with `--verify-attachments`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// An attachment and the message it came in, for `--attachments-only`
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct AttachmentEntry {
+    pub message_id: i64,
+    pub date: DateTime<FixedOffset>,
+    pub from: Option<String>,
+    pub from_name: Option<String>,
+    pub from_me: bool,
+    pub chat_id: Option<i32>,
+    pub chat_name: Option<String>,
+    pub filename: Option<String>,
+    pub mime_type: Option<String>,
+    /// Where the file is on disk, or the exported copy with `--attachments-dir`
+    pub path: Option<String>,
+    pub size: i64,
+    pub is_sticker: bool,
+    /// Whether the file is on disk, with `--verify-attachments`
+    pub status: Option<AttachmentStatus>,
+    /// SHA-256 of the file, with `--verify-attachments`
+    pub sha256: Option<String>,
+}
+
+impl AttachmentEntry {
+    /// Names of the serialized fields, in order, for CSV columns
+    pub const FIELDS: &'static [&'static str] = &[
+        "message_id", "date", "from", "from_name", "from_me", "chat_id", "chat_name", "filename", "mime_type",
+        "path", "size", "is_sticker", "status", "sha256",
+    ];
+
+    /// One entry per attachment of `record`
+    pub fn from_record(record: &MessageRecord) -> Vec<AttachmentEntry> {
+        record
+            .attachments
+            .iter()
+            .map(|attachment| AttachmentEntry {
+                message_id: record.id,
+                date: record.date,
+                from: record.from.clone(),
+                from_name: record.from_name.clone(),
+                from_me: record.from_me,
+                chat_id: record.chat_id,
+                chat_name: record.chat_name.clone(),
+                filename: attachment.filename.clone(),
+                mime_type: attachment.mime_type.clone(),
+                path: attachment.path.clone(),
+                size: attachment.size,
+                is_sticker: attachment.is_sticker,
+                status: attachment.status,
+                sha256: attachment.sha256.clone(),
+            })
+            .collect()
+    }
+}
+
+/// SHA-256 of a file's contents as lowercase hex
+pub fn hash_file(path: &Path) -> Result<String, AppError> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Copies attachments into an output directory, storing each distinct file once
+pub struct AttachmentCopier {
+    dir: PathBuf,
+    copied: HashMap<String, PathBuf>,
+}
+
+impl AttachmentCopier {
+    pub fn new(dir: &Path) -> Result<Self, AppError> {
+        fs::create_dir_all(dir)?;
+        Ok(AttachmentCopier {
+            dir: dir.to_path_buf(),
+            copied: HashMap::new(),
+        })
+    }
+
+    /// Copy `source` into the output directory, returning the path of the copy.
+    /// Files whose contents were already copied reuse the earlier copy.
+    pub fn copy(&mut self, source: &Path, filename: &str) -> Result<PathBuf, AppError> {
+        let hash = hash_file(source)?;
+        if let Some(existing) = self.copied.get(&hash) {
+            return Ok(existing.clone());
+        }
+
+        let dest = self.dir.join(format!("{}-{}", &hash[..12], filename));
+        if !dest.exists() {
+            fs::copy(source, &dest)?;
+        }
+        self.copied.insert(hash, dest.clone());
+        Ok(dest)
+    }
+}
+
+/// Build the output record for an attachment, copying it out if a copier is given
+pub fn to_record(
+    attachment: &Attachment,
+    platform: &Platform,
+    db_path: &Path,
+    copier: Option<&mut AttachmentCopier>,
+) -> Result<AttachmentRecord, AppError> {
+    let filename = attachment.filename().map(|name| {
+        Path::new(name)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| name.to_string())
+    });
+    let source = attachment
+        .resolved_attachment_path(platform, db_path, None)
+        .map(PathBuf::from)
+        .filter(|path| path.is_file());
+
+    let path = match (source, copier) {
+        (Some(source), Some(copier)) => {
+            let name = filename.clone().unwrap_or_else(|| "attachment".to_string());
+            Some(copier.copy(&source, &name)?)
+        }
+        (source, _) => source,
+    };
+
+    Ok(AttachmentRecord {
+        filename,
+        mime_type: attachment.mime_type.clone(),
+        path: path.map(|p| p.to_string_lossy().to_string()),
+        original_path: None,
+        size: attachment.total_bytes,
+        is_sticker: attachment.is_sticker,
+        transcript: None,
+        thumbnail: None,
+        status: None,
+        sha256: None,
+    })
+}
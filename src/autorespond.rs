@@ -0,0 +1,235 @@
+use chrono::{DateTime, Duration, Utc};
+use imessage_database::util::dirs::home;
+use regex::Regex;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::resolve_recipient;
+use crate::contacts::handle_key;
+use crate::error::AppError;
+use crate::export::MessageRecord;
+use crate::send::OutgoingMessage;
+use crate::template::{MessageTemplate, Recipient};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS replies (
+    key TEXT PRIMARY KEY,
+    handle TEXT NOT NULL,
+    rule TEXT NOT NULL,
+    replied_at INTEGER NOT NULL
+);
+";
+
+/// Seconds before the same sender gets another automatic reply, when the
+/// rules file doesn't say
+pub const DEFAULT_COOLDOWN_SECS: u64 = 60 * 60;
+
+fn default_cooldown() -> u64 {
+    DEFAULT_COOLDOWN_SECS
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default = "default_cooldown")]
+    cooldown: u64,
+    rules: Vec<RuleSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    name: Option<String>,
+    /// Phone numbers, emails or alias names; any sender when empty
+    #[serde(default)]
+    from: Vec<String>,
+    /// Regular expression the text has to match; any text when missing
+    #[serde(rename = "match")]
+    pattern: Option<String>,
+    reply: String,
+    cooldown: Option<u64>,
+}
+
+/// When to reply automatically, and with what
+pub struct Rule {
+    pub name: String,
+    /// Handle keys of the senders it applies to; everyone when empty
+    from: Vec<String>,
+    pattern: Option<Regex>,
+    template: MessageTemplate,
+    cooldown: Duration,
+}
+
+impl Rule {
+    /// The pattern's captures if this rule replies to `text` from `from`
+    fn matches(&self, from: &str, text: &str) -> Option<HashMap<String, String>> {
+        if !self.from.is_empty() && !self.from.contains(&handle_key(from)) {
+            return None;
+        }
+        let Some(pattern) = &self.pattern else {
+            return Some(HashMap::new());
+        };
+        let captures = pattern.captures(text)?;
+        Some(
+            pattern
+                .capture_names()
+                .enumerate()
+                .filter_map(|(i, name)| {
+                    let value = captures.get(i)?.as_str().to_string();
+                    Some((name.map_or_else(|| i.to_string(), String::from), value))
+                })
+                .collect(),
+        )
+    }
+}
+
+/// A reply the autoresponder sent, or would send with `--dry-run`
+#[derive(Debug, Clone, Serialize)]
+pub struct AutoReply {
+    pub rule: String,
+    /// The message being replied to
+    pub message_id: i64,
+    pub recipient: String,
+    pub text: String,
+}
+
+impl AutoReply {
+    pub fn outgoing(&self) -> OutgoingMessage {
+        OutgoingMessage { recipient: self.recipient.clone(), text: self.text.clone(), attachments: Vec::new(), account: None }
+    }
+}
+
+/// Read autoresponder rules from a `.yaml`/`.yml` or `.toml` file, like
+///
+/// ```yaml
+/// cooldown: 3600
+/// rules:
+///   - name: away
+///     match: "(?i)are you (free|around)"
+///     reply: "Hi {{ from_name | default(from) }}, I'm away until Monday."
+/// ```
+///
+/// Rules are tried in order and the first match replies. Replies are
+/// templates with the message's `from`, `from_name`, `text` and `chat_name`,
+/// and the pattern's captures by name or number. `from` takes alias names.
+pub fn load_rules(path: &Path, aliases: &HashMap<String, String>) -> Result<Vec<Rule>, AppError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| AppError::Config(format!("Couldn't read {}: {}", path.display(), e)))?;
+    let error = |e: &dyn std::fmt::Display| AppError::Config(format!("{}: {}", path.display(), e));
+    let file: RulesFile = match path.extension().and_then(|e| e.to_str()) {
+        Some("yaml" | "yml") => serde_yaml::from_str(&contents).map_err(|e| error(&e))?,
+        _ => toml::from_str(&contents).map_err(|e| error(&e))?,
+    };
+    if file.rules.is_empty() {
+        return Err(error(&"no rules"));
+    }
+    let seconds = |secs: u64| Duration::seconds(i64::try_from(secs).unwrap_or(i64::MAX));
+    file.rules
+        .into_iter()
+        .enumerate()
+        .map(|(i, spec)| {
+            let name = spec.name.unwrap_or_else(|| format!("rule {}", i + 1));
+            let from = spec
+                .from
+                .iter()
+                .map(|f| resolve_recipient(f, aliases).map(|h| handle_key(&h)))
+                .collect::<Result<_, _>>()?;
+            let pattern = spec
+                .pattern
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .map_err(|e| error(&format!("{}: {}", name, e)))?;
+            Ok(Rule {
+                template: MessageTemplate::new(&spec.reply)?,
+                cooldown: seconds(spec.cooldown.unwrap_or(file.cooldown)),
+                name,
+                from,
+                pattern,
+            })
+        })
+        .collect()
+}
+
+/// Replies to incoming messages by rule, at most once per cooldown per sender
+///
+/// When each sender was last replied to is kept in SQLite, so a restart
+/// doesn't reset the cooldowns, and two autoresponders answering each other
+/// stop after one reply each.
+pub struct Responder {
+    rules: Vec<Rule>,
+    db: Connection,
+}
+
+impl Responder {
+    /// `~/.imessage-blaster/autorespond.sqlite`
+    pub fn default_path() -> PathBuf {
+        PathBuf::from(home()).join(".imessage-blaster").join("autorespond.sqlite")
+    }
+
+    pub fn open(rules: Vec<Rule>, path: &Path) -> Result<Self, AppError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let db = Connection::open(path)?;
+        db.execute_batch(SCHEMA)?;
+        Ok(Responder { rules, db })
+    }
+
+    fn last_reply(&self, handle: &str) -> Result<Option<DateTime<Utc>>, AppError> {
+        let replied_at: Option<i64> = self
+            .db
+            .query_row("SELECT replied_at FROM replies WHERE key = ?1", [handle_key(handle)], |row| row.get(0))
+            .optional()?;
+        Ok(replied_at.and_then(DateTime::from_timestamp_millis))
+    }
+
+    /// The reply `record` gets, if it's an incoming one-on-one message that
+    /// matches a rule and its sender isn't cooling down
+    pub fn reply_to(&self, record: &MessageRecord, now: DateTime<Utc>) -> Result<Option<AutoReply>, AppError> {
+        // Never answer the user's own messages, and only answer one person at a time
+        let (false, Some(from)) = (record.from_me, &record.from) else {
+            return Ok(None);
+        };
+        if record.participants.len() > 1 {
+            return Ok(None);
+        }
+        let text = record.text.as_deref().unwrap_or_default();
+        let Some((rule, captures)) = self.rules.iter().find_map(|rule| Some((rule, rule.matches(from, text)?))) else {
+            return Ok(None);
+        };
+        if self.last_reply(from)?.is_some_and(|last| now - last < rule.cooldown) {
+            return Ok(None);
+        }
+
+        let mut recipient = Recipient::from_handle(from);
+        recipient.fields.insert("from".to_string(), from.clone());
+        recipient.fields.insert("text".to_string(), text.to_string());
+        let optional = [("from_name", &record.from_name), ("chat_name", &record.chat_name)];
+        for (field, value) in optional {
+            if let Some(value) = value {
+                recipient.fields.insert(field.to_string(), value.clone());
+            }
+        }
+        recipient.fields.extend(captures);
+        Ok(Some(AutoReply {
+            rule: rule.name.clone(),
+            message_id: record.id,
+            recipient: from.clone(),
+            text: rule.template.render(&recipient)?,
+        }))
+    }
+
+    /// Start `reply.recipient`'s cooldown
+    pub fn record(&self, reply: &AutoReply, at: DateTime<Utc>) -> Result<(), AppError> {
+        self.db.execute(
+            "INSERT INTO replies (key, handle, rule, replied_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(key) DO UPDATE SET handle = ?2, rule = ?3, replied_at = ?4",
+            params![handle_key(&reply.recipient), reply.recipient, reply.rule, at.timestamp_millis()],
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+use imessage_database::message_types::app::AppMessage;
+use imessage_database::message_types::url::URLMessage;
+use imessage_database::message_types::variants::{BalloonProvider, CustomBalloon, URLOverride, Variant};
+use imessage_database::tables::messages::Message;
+use imessage_database::util::plist::parse_ns_keyed_archiver;
+use rusqlite::Connection;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// What an iMessage app balloon (Apple Pay, a shared location, a game, a poll)
+/// carried, decoded from its payload
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct AppPayload {
+    /// `apple_pay`, `location`, `music`, `app_store`, `collaboration`, `fitness`,
+    /// `slideshow`, `check_in`, `find_my` or `app` for any other app
+    pub kind: String,
+    /// The sending app's bundle ID, like `com.apple.messages.MSMessageExtensionBalloonPlugin:...`
+    pub bundle_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subtitle: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Anything else the payload carries, like a place's address or a game's state
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[schemars(default)]
+    pub details: BTreeMap<String, String>,
+}
+
+fn owned(value: Option<&str>) -> Option<String> {
+    value.map(String::from)
+}
+
+impl AppPayload {
+    fn new(kind: &str, msg: &Message) -> Self {
+        AppPayload { kind: kind.to_string(), bundle_id: msg.balloon_bundle_id.clone(), ..AppPayload::default() }
+    }
+
+    fn detail(&mut self, key: &str, value: Option<&str>) {
+        if let Some(value) = value.filter(|v| !v.is_empty()) {
+            self.details.insert(key.to_string(), value.to_string());
+        }
+    }
+
+    /// A line of text standing in for the balloon, for messages that have none
+    pub fn summary(&self) -> Option<String> {
+        if let Some(text) = self.details.get("text") {
+            return Some(text.clone());
+        }
+        let parts: Vec<&str> = [&self.title, &self.subtitle, &self.caption]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .filter(|p| !p.is_empty())
+            .collect();
+        if !parts.is_empty() {
+            return Some(parts.join(" — "));
+        }
+        self.app_name.clone().or_else(|| self.url.clone())
+    }
+
+    /// Macro-style app balloons: Apple Pay, Fitness, games, polls and so on
+    fn from_app(kind: &str, msg: &Message, app: Option<AppMessage>) -> Self {
+        let mut decoded = AppPayload::new(kind, msg);
+        if let Some(app) = app {
+            decoded.app_name = owned(app.app_name);
+            decoded.title = owned(app.title);
+            decoded.subtitle = owned(app.subtitle);
+            decoded.caption = owned(app.caption);
+            decoded.url = owned(app.url).filter(|u| !u.starts_with('?'));
+            decoded.detail("text", app.ldtext);
+            decoded.detail("subcaption", app.subcaption);
+            decoded.detail("trailing_caption", app.trailing_caption);
+            decoded.detail("trailing_subcaption", app.trailing_subcaption);
+            for (key, value) in app.parse_query_string() {
+                decoded.detail(key, Some(value));
+            }
+        }
+        decoded
+    }
+}
+
+/// Decode an app balloon's payload. Plain link previews, handwriting and
+/// Digital Touch aren't app payloads and give `None`.
+pub fn decode(msg: &Message, db: &Connection) -> Option<AppPayload> {
+    let Variant::App(balloon) = msg.variant() else {
+        return None;
+    };
+    let payload = || msg.payload_data(db).and_then(|p| parse_ns_keyed_archiver(&p).ok());
+    match balloon {
+        CustomBalloon::URL => {
+            let payload = payload()?;
+            let decoded = match URLMessage::get_url_message_override(&payload).ok()? {
+                URLOverride::Normal(_) => return None,
+                URLOverride::SharedPlacemark(place) => {
+                    let mut decoded = AppPayload::new("location", msg);
+                    decoded.title = owned(place.place_name.or(place.placemark.name));
+                    decoded.subtitle = owned(place.placemark.address);
+                    decoded.url = owned(place.url.or(place.original_url));
+                    decoded.detail("street", place.placemark.street);
+                    decoded.detail("city", place.placemark.city);
+                    decoded.detail("state", place.placemark.state);
+                    decoded.detail("postal_code", place.placemark.postal_code);
+                    decoded.detail("country", place.placemark.country);
+                    decoded
+                }
+                URLOverride::AppleMusic(music) => {
+                    let mut decoded = AppPayload::new("music", msg);
+                    decoded.title = owned(music.track_name);
+                    decoded.subtitle = owned(music.artist);
+                    decoded.url = owned(music.url);
+                    decoded.detail("album", music.album);
+                    decoded
+                }
+                URLOverride::AppStore(app) => {
+                    let mut decoded = AppPayload::new("app_store", msg);
+                    decoded.title = owned(app.app_name);
+                    decoded.caption = owned(app.description);
+                    decoded.url = owned(app.url.or(app.original_url));
+                    decoded.detail("genre", app.genre);
+                    decoded.detail("platform", app.platform);
+                    decoded
+                }
+                URLOverride::Collaboration(collaboration) => {
+                    let mut decoded = AppPayload::new("collaboration", msg);
+                    decoded.title = owned(collaboration.title);
+                    decoded.app_name = owned(collaboration.app_name);
+                    decoded.url = owned(collaboration.url.or(collaboration.original_url));
+                    decoded
+                }
+            };
+            Some(decoded)
+        }
+        CustomBalloon::Handwriting | CustomBalloon::DigitalTouch => None,
+        app => {
+            let kind = match app {
+                CustomBalloon::ApplePay => "apple_pay",
+                CustomBalloon::Fitness => "fitness",
+                CustomBalloon::Slideshow => "slideshow",
+                CustomBalloon::CheckIn => "check_in",
+                CustomBalloon::FindMy => "find_my",
+                _ => "app",
+            };
+            let payload = payload();
+            let app = payload.as_ref().and_then(|p| AppMessage::from_map(p).ok());
+            Some(AppPayload::from_app(kind, msg, app))
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use imessage_database::tables::messages::Message;
+use rusqlite::Connection;
+
+/// Placeholder character macOS puts in the text where an attachment sits
+const OBJECT_REPLACEMENT: char = '\u{FFFC}';
+
+/// Pull the first NSString out of an `attributedBody` typedstream without
+/// parsing the rest of the archive.
+///
+/// `Message::generate_text` does a full typedstream parse; this is the last
+/// resort for blobs it can't handle, so newer messages with `text = NULL`
+/// still export with their content.
+pub fn scan_attributed_body(blob: &[u8]) -> Option<String> {
+    let class = blob.windows(8).position(|w| w == b"NSString")?;
+    // The string's type encoding is `+`, followed by its length and UTF-8 bytes
+    let rest = &blob[class + 8..];
+    let plus = rest.iter().take(16).position(|&b| b == b'+')?;
+    let rest = &rest[plus + 1..];
+
+    let (len, start) = match *rest.first()? {
+        0x81 => (u16::from_le_bytes([*rest.get(1)?, *rest.get(2)?]) as usize, 3),
+        0x82 => (
+            u32::from_le_bytes([*rest.get(1)?, *rest.get(2)?, *rest.get(3)?, *rest.get(4)?])
+                as usize,
+            5,
+        ),
+        n => (n as usize, 1),
+    };
+
+    let bytes = rest.get(start..start + len)?;
+    Some(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Drop attachment placeholders and treat whitespace-only text as no text
+pub fn clean_text(text: &str) -> Option<String> {
+    let cleaned: String = text.chars().filter(|&c| c != OBJECT_REPLACEMENT).collect();
+    if cleaned.trim().is_empty() {
+        None
+    } else {
+        Some(cleaned)
+    }
+}
+
+/// Decode the text of a message, reading `attributedBody` when `text` is NULL
+pub fn message_text(msg: &mut Message, db: &Connection) -> Option<String> {
+    if let Ok(text) = msg.generate_text(db) {
+        return clean_text(text);
+    }
+    let blob = msg.attributed_body(db)?;
+    clean_text(&scan_attributed_body(&blob)?)
+}
+
+/// Verbs older clients and SMS use to spell out a tapback as plain text
+const REACTION_VERBS: &[&str] = &[
+    "Loved ", "Liked ", "Disliked ", "Laughed at ", "Emphasized ", "Questioned ", "Removed a ",
+];
+
+/// Whether `text` is a tapback sent as plain text, like `Loved “see you soon”`
+/// or `Emphasized an image`
+pub fn is_reaction_placeholder(text: &str) -> bool {
+    let Some(rest) = REACTION_VERBS.iter().find_map(|verb| text.strip_prefix(verb)) else {
+        return false;
+    };
+    // A removal reads `Removed a heart from “...”`
+    let rest = rest.split_once(" from ").map_or(rest, |(_, target)| target);
+    let quoted = (rest.starts_with('“') && rest.ends_with('”')) || (rest.starts_with('"') && rest.ends_with('"'));
+    quoted
+        || matches!(
+            rest,
+            "an image" | "a photo" | "a video" | "a movie" | "an attachment" | "an audio message" | "a sticker"
+        )
+}
@@ -0,0 +1,772 @@
+use chrono::{DateTime, Utc};
+use imessage_database::util::dirs::home;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+use crate::body::{clean_text, is_reaction_placeholder, scan_attributed_body};
+use crate::contacts::handle_key;
+use crate::error::AppError;
+use crate::export::{from_imessage_ns, open_database, to_imessage_ns};
+use crate::optout::is_stop_request;
+use crate::queue::QueuedSend;
+use crate::send::SendResult;
+use crate::template::MessageTemplate;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS campaigns (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    started_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS campaign_recipients (
+    campaign_id INTEGER NOT NULL REFERENCES campaigns(id),
+    recipient TEXT NOT NULL,
+    status TEXT NOT NULL,
+    error TEXT,
+    sent_at INTEGER,
+    message_id INTEGER,
+    delivered_at INTEGER,
+    variant TEXT,
+    replied_at INTEGER,
+    queue TEXT,
+    queue_job_id INTEGER
+);
+CREATE INDEX IF NOT EXISTS campaign_recipients_campaign ON campaign_recipients(campaign_id, status);
+CREATE TABLE IF NOT EXISTS campaign_replies (
+    campaign_id INTEGER NOT NULL REFERENCES campaigns(id),
+    recipient TEXT NOT NULL,
+    message_id INTEGER NOT NULL,
+    date INTEGER NOT NULL,
+    text TEXT,
+    kind TEXT NOT NULL,
+    UNIQUE (campaign_id, message_id)
+);
+";
+
+/// How often chat.db is checked while waiting for deliveries
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// One recipient's outcome in a campaign
+#[derive(Debug, Clone, Serialize)]
+pub struct CampaignRecipient {
+    pub recipient: String,
+    /// `suppressed`, `queued` (held for quiet hours, for the daemon to
+    /// send), `failed`, `sent` (handed to Messages but not yet confirmed) or
+    /// `delivered`
+    pub status: String,
+    pub error: Option<String>,
+    pub sent_at: Option<DateTime<Utc>>,
+    /// ROWID of the outgoing message in chat.db, once it's been found
+    pub message_id: Option<i64>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    /// Which of the campaign's message variants they were sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    /// When they first wrote back after their message was sent
+    pub replied_at: Option<DateTime<Utc>>,
+}
+
+/// Words a reply asking something starts with, when it has no question mark
+const QUESTION_WORDS: &[&str] = &[
+    "who", "what", "when", "where", "why", "how", "which", "can", "could", "do", "does", "is", "are", "will", "would",
+];
+
+/// What a recipient's reply to a campaign amounts to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplyKind {
+    /// An opt-out like `STOP`
+    Stop,
+    /// Asks something, so someone should answer it
+    Question,
+    Reply,
+}
+
+impl ReplyKind {
+    /// Classify a reply by its text; replies with none, like a photo, are plain replies
+    pub fn classify(text: Option<&str>) -> Self {
+        let Some(text) = text else {
+            return ReplyKind::Reply;
+        };
+        if is_stop_request(text) {
+            return ReplyKind::Stop;
+        }
+        let first = text.split_whitespace().next().unwrap_or_default().to_lowercase();
+        if text.contains('?') || QUESTION_WORDS.contains(&first.trim_end_matches(',')) {
+            ReplyKind::Question
+        } else {
+            ReplyKind::Reply
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            ReplyKind::Stop => "stop",
+            ReplyKind::Question => "question",
+            ReplyKind::Reply => "reply",
+        }
+    }
+
+    fn parse(kind: &str) -> Self {
+        match kind {
+            "stop" => ReplyKind::Stop,
+            "question" => ReplyKind::Question,
+            _ => ReplyKind::Reply,
+        }
+    }
+}
+
+/// A message a recipient sent after their campaign message
+#[derive(Debug, Clone, Serialize)]
+pub struct CampaignReply {
+    pub recipient: String,
+    /// ROWID of the incoming message in chat.db
+    pub message_id: i64,
+    pub date: DateTime<Utc>,
+    pub text: Option<String>,
+    pub kind: ReplyKind,
+}
+
+/// How one message variant did, for comparing them
+#[derive(Debug, Clone, Serialize)]
+pub struct VariantReport {
+    pub name: String,
+    pub total: usize,
+    pub sent: usize,
+    pub delivered: usize,
+    pub failed: usize,
+    pub replied: usize,
+    /// Delivered, out of those sent
+    pub delivery_rate: f64,
+    /// Replied, out of those sent
+    pub reply_rate: f64,
+}
+
+impl VariantReport {
+    fn new(name: &str, recipients: &[&CampaignRecipient]) -> Self {
+        let count = |status: &str| recipients.iter().filter(|r| r.status == status).count();
+        let sent = recipients.iter().filter(|r| r.sent_at.is_some()).count();
+        let delivered = count("delivered");
+        let replied = recipients.iter().filter(|r| r.replied_at.is_some()).count();
+        let rate = |n: usize| if sent == 0 { 0.0 } else { n as f64 / sent as f64 };
+        VariantReport {
+            name: name.to_string(),
+            total: recipients.len(),
+            sent,
+            delivered,
+            failed: count("failed"),
+            replied,
+            delivery_rate: rate(delivered),
+            reply_rate: rate(replied),
+        }
+    }
+}
+
+/// Totals and per-recipient outcomes for a campaign
+#[derive(Debug, Clone, Serialize)]
+pub struct CampaignReport {
+    pub id: i64,
+    pub name: String,
+    pub started_at: DateTime<Utc>,
+    pub total: usize,
+    /// Handed to Messages, whether or not delivery was confirmed
+    pub sent: usize,
+    pub delivered: usize,
+    /// Failed to send, or reported as failed by Messages
+    pub failed: usize,
+    pub suppressed: usize,
+    /// Held for quiet hours and not yet sent by the daemon
+    pub queued: usize,
+    /// Sent but neither delivered nor failed yet
+    pub unconfirmed: usize,
+    /// Wrote back after their message was sent
+    pub replied: usize,
+    /// Replied asking to opt out
+    pub stopped: usize,
+    /// Replied with a question
+    pub questions: usize,
+    /// Outcomes by message variant, for campaigns sent with several
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<VariantReport>,
+    pub recipients: Vec<CampaignRecipient>,
+    /// Every reply found so far, oldest first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub replies: Vec<CampaignReply>,
+}
+
+/// A record of every bulk send and what became of each message, stored in SQLite
+pub struct CampaignLog {
+    db: Connection,
+}
+
+/// One version of a campaign's message, sent to a `weight` share of recipients
+pub struct Variant {
+    pub name: String,
+    pub weight: u32,
+    pub template: MessageTemplate,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// A variant as written in a variants file, with its text or the file it's in
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VariantDefinition {
+    name: String,
+    #[serde(default = "default_weight")]
+    weight: u32,
+    message: Option<String>,
+    template_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VariantsFile {
+    variants: Vec<VariantDefinition>,
+}
+
+/// The message variants of an A/B test, read from a TOML file like
+///
+/// ```toml
+/// [[variants]]
+/// name = "short"
+/// weight = 3
+/// message = "Hi {{ first_name }}, 20% off today only"
+///
+/// [[variants]]
+/// name = "long"
+/// template_file = "long.txt"
+/// ```
+///
+/// Each recipient always gets the same variant of a campaign.
+pub struct Variants {
+    variants: Vec<Variant>,
+    total_weight: u64,
+}
+
+impl Variants {
+    /// Read variants from `path`; a `template_file` is relative to it
+    pub fn load(path: &Path) -> Result<Self, AppError> {
+        let error = |e: &dyn std::fmt::Display| AppError::Config(format!("{}: {}", path.display(), e));
+        let contents = fs::read_to_string(path).map_err(|e| error(&e))?;
+        let file: VariantsFile = toml::from_str(&contents).map_err(|e| error(&e))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let mut variants: Vec<Variant> = Vec::new();
+        for definition in file.variants {
+            if variants.iter().any(|v| v.name == definition.name) {
+                return Err(error(&format!("more than one variant is named {}", definition.name)));
+            }
+            let source = match (definition.message, &definition.template_file) {
+                (Some(message), None) => message,
+                (None, Some(file)) => fs::read_to_string(dir.join(file)).map_err(|e| error(&e))?,
+                _ => return Err(error(&format!("variant {} needs one of message or template_file", definition.name))),
+            };
+            variants.push(Variant { name: definition.name, weight: definition.weight, template: MessageTemplate::new(&source)? });
+        }
+        let total_weight = variants.iter().map(|v| u64::from(v.weight)).sum();
+        if total_weight == 0 {
+            return Err(error(&"no variant has a weight above 0"));
+        }
+        Ok(Variants { variants, total_weight })
+    }
+
+    /// The variant `recipient` gets in `campaign`: the same one every time,
+    /// however the recipient's number is written, with each variant's share
+    /// of recipients in proportion to its weight
+    pub fn pick(&self, campaign: &str, recipient: &str) -> &Variant {
+        let digest = Sha256::digest(format!("{}\n{}", campaign, handle_key(recipient)).as_bytes());
+        let bytes: [u8; 8] = digest[..8].try_into().expect("SHA-256 is 32 bytes");
+        let mut point = u64::from_be_bytes(bytes) % self.total_weight;
+        for variant in &self.variants {
+            if point < u64::from(variant.weight) {
+                return variant;
+            }
+            point -= u64::from(variant.weight);
+        }
+        unreachable!("the point is below the total weight")
+    }
+}
+
+/// Every handle in chat.db by its loose [`handle_key`], to the ROWIDs it has
+fn handle_ids(chat_db: &Connection) -> Result<HashMap<String, Vec<i64>>, AppError> {
+    let mut handles: HashMap<String, Vec<i64>> = HashMap::new();
+    let mut statement = chat_db.prepare("SELECT ROWID, id FROM handle")?;
+    for row in statement.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))? {
+        let (rowid, id) = row?;
+        handles.entry(handle_key(&id)).or_default().push(rowid);
+    }
+    Ok(handles)
+}
+
+fn timestamp(seconds: Option<i64>) -> Option<DateTime<Utc>> {
+    seconds.and_then(|s| DateTime::from_timestamp(s, 0))
+}
+
+impl CampaignLog {
+    /// `~/.imessage-blaster/campaigns.sqlite`
+    pub fn default_path() -> PathBuf {
+        PathBuf::from(home()).join(".imessage-blaster").join("campaigns.sqlite")
+    }
+
+    pub fn open(path: &Path) -> Result<Self, AppError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let db = Connection::open(path)?;
+        db.execute_batch(SCHEMA)?;
+        // Logs from before A/B variants, reply tracking and quiet hours don't have these
+        let columns = [("variant", "TEXT"), ("replied_at", "INTEGER"), ("queue", "TEXT"), ("queue_job_id", "INTEGER")];
+        for (column, definition) in columns {
+            let query = format!("SELECT 1 FROM pragma_table_info('campaign_recipients') WHERE name = '{}'", column);
+            if !db.prepare(&query)?.exists([])? {
+                db.execute(&format!("ALTER TABLE campaign_recipients ADD COLUMN {} {}", column, definition), [])?;
+            }
+        }
+        Ok(CampaignLog { db })
+    }
+
+    /// Open the log at its default location
+    pub fn open_default() -> Result<Self, AppError> {
+        Self::open(&Self::default_path())
+    }
+
+    /// Start a campaign, returning its id. Call this just before sending, as
+    /// only messages sent after it starts count towards it.
+    pub fn start(&self, name: &str) -> Result<i64, AppError> {
+        self.db.execute(
+            "INSERT INTO campaigns (name, started_at) VALUES (?1, ?2)",
+            params![name, Utc::now().timestamp()],
+        )?;
+        Ok(self.db.last_insert_rowid())
+    }
+
+    /// The most recent campaign with this name
+    pub fn find(&self, name: &str) -> Result<Option<i64>, AppError> {
+        Ok(self
+            .db
+            .query_row("SELECT id FROM campaigns WHERE name = ?1 ORDER BY id DESC LIMIT 1", [name], |row| row.get(0))
+            .optional()?)
+    }
+
+    /// A campaign by its id, or else the most recent with this name
+    pub fn resolve(&self, id_or_name: &str) -> Result<Option<i64>, AppError> {
+        if let Ok(id) = id_or_name.parse::<i64>() {
+            let exists = self.db.prepare("SELECT 1 FROM campaigns WHERE id = ?1")?.exists([id])?;
+            if exists {
+                return Ok(Some(id));
+            }
+        }
+        self.find(id_or_name)
+    }
+
+    /// Record the outcome of sending `variant` of the message to one recipient
+    pub fn record(&self, campaign: i64, result: &SendResult, variant: Option<&str>) -> Result<(), AppError> {
+        let error = result.error.clone().or_else(|| result.attachments.iter().find_map(|a| a.error.clone()));
+        let status = if result.success { "sent" } else { "failed" };
+        self.db.execute(
+            "INSERT INTO campaign_recipients (campaign_id, recipient, status, error, sent_at, variant)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![campaign, result.recipient, status, error, result.success.then(|| Utc::now().timestamp()), variant],
+        )?;
+        Ok(())
+    }
+
+    /// Record a recipient who was skipped because they opted out
+    pub fn record_suppressed(&self, campaign: i64, result: &SendResult, variant: Option<&str>) -> Result<(), AppError> {
+        self.db.execute(
+            "INSERT INTO campaign_recipients (campaign_id, recipient, status, error, variant)
+             VALUES (?1, ?2, 'suppressed', ?3, ?4)",
+            params![campaign, result.recipient, result.error, variant],
+        )?;
+        Ok(())
+    }
+
+    /// Record a recipient whose message was held for quiet hours as job
+    /// `job` in the send queue at `queue`, for [`finish_queued`](Self::finish_queued)
+    /// to update when the daemon sends it
+    pub fn record_queued(
+        &self,
+        campaign: i64,
+        job: &QueuedSend,
+        queue: &Path,
+        variant: Option<&str>,
+    ) -> Result<(), AppError> {
+        self.db.execute(
+            "INSERT INTO campaign_recipients (campaign_id, recipient, status, variant, queue, queue_job_id)
+             VALUES (?1, ?2, 'queued', ?3, ?4, ?5)",
+            params![campaign, job.recipient, variant, queue_key(queue), job.id],
+        )?;
+        Ok(())
+    }
+
+    /// Record what became of a send the daemon took from the queue at
+    /// `queue`, if a campaign was waiting on it. Returns whether one was.
+    pub fn finish_queued(&self, queue: &Path, job: i64, result: &SendResult) -> Result<bool, AppError> {
+        let error = result.error.clone().or_else(|| result.attachments.iter().find_map(|a| a.error.clone()));
+        let status = if result.success { "sent" } else { "failed" };
+        let updated = self.db.execute(
+            "UPDATE campaign_recipients SET status = ?1, error = ?2, sent_at = ?3
+             WHERE queue = ?4 AND queue_job_id = ?5 AND status = 'queued'",
+            params![status, error, result.success.then(|| Utc::now().timestamp()), queue_key(queue), job],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Look each unconfirmed message up in chat.db once, marking it delivered
+    /// or failed if Messages says so. Returns how many are still unconfirmed.
+    pub fn check_delivery(&self, campaign: i64, chat_db: &Connection) -> Result<usize, AppError> {
+        let started_at: i64 =
+            self.db.query_row("SELECT started_at FROM campaigns WHERE id = ?1", [campaign], |row| row.get(0))?;
+        let since = to_imessage_ns(DateTime::from_timestamp(started_at, 0).unwrap_or_default());
+
+        // Recipients are matched loosely, like opt-outs, so `(555) 123-4567` finds `+15551234567`
+        let handles = handle_ids(chat_db)?;
+
+        let mut statement =
+            self.db.prepare("SELECT rowid, recipient FROM campaign_recipients WHERE campaign_id = ?1 AND status = 'sent'")?;
+        let pending = statement
+            .query_map([campaign], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut unconfirmed = 0;
+        for (rowid, recipient) in pending {
+            let Some(handle_ids) = handles.get(&handle_key(&recipient)) else {
+                unconfirmed += 1;
+                continue;
+            };
+            let ids = handle_ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(",");
+            // The first message to them since the campaign started is the one it sent
+            let message = chat_db
+                .query_row(
+                    &format!(
+                        "SELECT ROWID, is_delivered, error, date_delivered FROM message
+                         WHERE is_from_me = 1 AND handle_id IN ({}) AND date >= ?1
+                         ORDER BY date LIMIT 1",
+                        ids
+                    ),
+                    [since],
+                    |row| {
+                        Ok((row.get::<_, i64>(0)?, row.get::<_, bool>(1)?, row.get::<_, i64>(2)?, row.get::<_, i64>(3)?))
+                    },
+                )
+                .optional()?;
+
+            match message {
+                Some((message_id, _, error, _)) if error != 0 => {
+                    debug!(%recipient, message_id, error, "message failed to deliver");
+                    self.db.execute(
+                        "UPDATE campaign_recipients SET status = 'failed', error = ?1, message_id = ?2 WHERE rowid = ?3",
+                        params![format!("Messages reported error {}", error), message_id, rowid],
+                    )?;
+                }
+                Some((message_id, true, _, delivered)) => {
+                    debug!(%recipient, message_id, "message delivered");
+                    let delivered_at = (delivered != 0).then(|| from_imessage_ns(delivered).timestamp());
+                    self.db.execute(
+                        "UPDATE campaign_recipients SET status = 'delivered', message_id = ?1, delivered_at = ?2 WHERE rowid = ?3",
+                        params![message_id, delivered_at, rowid],
+                    )?;
+                }
+                Some((message_id, false, _, _)) => {
+                    self.db.execute(
+                        "UPDATE campaign_recipients SET message_id = ?1 WHERE rowid = ?2",
+                        params![message_id, rowid],
+                    )?;
+                    unconfirmed += 1;
+                }
+                None => unconfirmed += 1,
+            }
+        }
+        Ok(unconfirmed)
+    }
+
+    /// Look for messages recipients sent after their campaign message,
+    /// storing each classified as a reply, STOP or question and marking when
+    /// they first replied. Tapbacks don't count. Returns how many new replies
+    /// were found.
+    pub fn check_replies(&self, campaign: i64, chat_db: &Connection) -> Result<usize, AppError> {
+        let handles = handle_ids(chat_db)?;
+        let mut statement = self.db.prepare(
+            "SELECT rowid, recipient, sent_at FROM campaign_recipients
+             WHERE campaign_id = ?1 AND sent_at IS NOT NULL",
+        )?;
+        let sent = statement
+            .query_map([campaign], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut found = 0;
+        for (rowid, recipient, sent_at) in sent {
+            let Some(handle_ids) = handles.get(&handle_key(&recipient)) else {
+                continue;
+            };
+            let ids = handle_ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(",");
+            let since = to_imessage_ns(DateTime::from_timestamp(sent_at, 0).unwrap_or_default());
+            let mut messages = chat_db.prepare(&format!(
+                "SELECT ROWID, date, text, attributedBody FROM message
+                 WHERE is_from_me = 0 AND handle_id IN ({}) AND date >= ?1
+                   AND associated_message_type = 0 AND item_type = 0
+                 ORDER BY date",
+                ids
+            ))?;
+            let replies = messages
+                .query_map([since], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                        row.get::<_, Option<Vec<u8>>>(3)?,
+                    ))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            for (message_id, date, text, body) in replies {
+                let text = text.or_else(|| body.as_deref().and_then(scan_attributed_body)).as_deref().and_then(clean_text);
+                // SMS tapbacks arrive as text like `Liked “...”`
+                if text.as_deref().is_some_and(is_reaction_placeholder) {
+                    continue;
+                }
+                let date = from_imessage_ns(date).timestamp();
+                let kind = ReplyKind::classify(text.as_deref());
+                let added = self.db.execute(
+                    "INSERT OR IGNORE INTO campaign_replies (campaign_id, recipient, message_id, date, text, kind)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![campaign, recipient, message_id, date, text, kind.as_str()],
+                )?;
+                if added > 0 {
+                    debug!(%recipient, kind = kind.as_str(), "recipient replied");
+                    found += 1;
+                }
+                self.db.execute(
+                    "UPDATE campaign_recipients SET replied_at = ?1
+                     WHERE rowid = ?2 AND (replied_at IS NULL OR replied_at > ?1)",
+                    params![date, rowid],
+                )?;
+            }
+        }
+        Ok(found)
+    }
+
+    /// [`check_replies`](Self::check_replies) against the chat.db at `db_path`
+    pub fn scan_replies(&self, campaign: i64, db_path: &Path) -> Result<usize, AppError> {
+        let (chat_db, _) = open_database(db_path)?;
+        self.check_replies(campaign, &chat_db)
+    }
+
+    /// Poll the chat.db at `db_path` until every sent message is delivered or
+    /// failed, or `timeout` passes, then look for replies
+    pub fn wait_for_delivery(&self, campaign: i64, db_path: &Path, timeout: Duration) -> Result<(), AppError> {
+        let (chat_db, _) = open_database(db_path)?;
+        let started = Instant::now();
+        loop {
+            let unconfirmed = self.check_delivery(campaign, &chat_db)?;
+            if unconfirmed == 0 || started.elapsed() >= timeout {
+                let replies = self.check_replies(campaign, &chat_db)?;
+                info!(unconfirmed, replies, elapsed = ?started.elapsed(), "finished checking delivery");
+                return Ok(());
+            }
+            thread::sleep(POLL_INTERVAL.min(timeout.saturating_sub(started.elapsed())));
+        }
+    }
+
+    pub fn report(&self, campaign: i64) -> Result<CampaignReport, AppError> {
+        let (name, started_at): (String, i64) = self.db.query_row(
+            "SELECT name, started_at FROM campaigns WHERE id = ?1",
+            [campaign],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let mut statement = self.db.prepare(
+            "SELECT recipient, status, error, sent_at, message_id, delivered_at, variant, replied_at FROM campaign_recipients
+             WHERE campaign_id = ?1 ORDER BY rowid",
+        )?;
+        let recipients = statement
+            .query_map([campaign], |row| {
+                Ok(CampaignRecipient {
+                    recipient: row.get(0)?,
+                    status: row.get(1)?,
+                    error: row.get(2)?,
+                    sent_at: timestamp(row.get(3)?),
+                    message_id: row.get(4)?,
+                    delivered_at: timestamp(row.get(5)?),
+                    variant: row.get(6)?,
+                    replied_at: timestamp(row.get(7)?),
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let count = |status: &str| recipients.iter().filter(|r| r.status == status).count();
+        let mut by_variant: BTreeMap<&str, Vec<&CampaignRecipient>> = BTreeMap::new();
+        for recipient in &recipients {
+            if let Some(variant) = &recipient.variant {
+                by_variant.entry(variant).or_default().push(recipient);
+            }
+        }
+        let variants = by_variant.iter().map(|(name, recipients)| VariantReport::new(name, recipients)).collect();
+
+        let mut statement = self.db.prepare(
+            "SELECT recipient, message_id, date, text, kind FROM campaign_replies
+             WHERE campaign_id = ?1 ORDER BY date, message_id",
+        )?;
+        let replies = statement
+            .query_map([campaign], |row| {
+                Ok(CampaignReply {
+                    recipient: row.get(0)?,
+                    message_id: row.get(1)?,
+                    date: timestamp(row.get(2)?).unwrap_or_default(),
+                    text: row.get(3)?,
+                    kind: ReplyKind::parse(&row.get::<_, String>(4)?),
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        // Recipients, not messages, so a STOP sent twice counts once
+        let replied_with = |kind: ReplyKind| {
+            replies.iter().filter(|r| r.kind == kind).map(|r| handle_key(&r.recipient)).collect::<HashSet<_>>().len()
+        };
+        Ok(CampaignReport {
+            id: campaign,
+            name,
+            started_at: DateTime::from_timestamp(started_at, 0).unwrap_or_default(),
+            total: recipients.len(),
+            sent: recipients.iter().filter(|r| r.sent_at.is_some()).count(),
+            delivered: count("delivered"),
+            failed: count("failed"),
+            suppressed: count("suppressed"),
+            queued: count("queued"),
+            unconfirmed: count("sent"),
+            replied: recipients.iter().filter(|r| r.replied_at.is_some()).count(),
+            stopped: replied_with(ReplyKind::Stop),
+            questions: replied_with(ReplyKind::Question),
+            variants,
+            recipients,
+            replies,
+        })
+    }
+}
+
+/// How a queue's path is stored, so the same file is recognized however it was given
+fn queue_key(queue: &Path) -> String {
+    fs::canonicalize(queue).unwrap_or_else(|_| queue.to_path_buf()).to_string_lossy().into_owned()
+}
+
+/// Columns `write_receipts` adds to the recipient CSV
+pub const RECEIPT_COLUMNS: [&str; 4] = ["status", "sent_at", "message_rowid", "error"];
+
+/// Copy the recipient CSV at `source` to `dest` with each row's outcome in
+/// `report` added on the end, as [`RECEIPT_COLUMNS`], so results can be
+/// reconciled in the spreadsheet the campaign came from. Columns of those
+/// names already in the CSV, from an earlier run, are filled in again.
+/// Returns how many rows were matched to a recipient.
+pub fn write_receipts(report: &CampaignReport, source: &Path, handle_column: &str, dest: &Path) -> Result<usize, AppError> {
+    let mut reader = csv::Reader::from_path(source)?;
+    let mut headers: Vec<String> = reader.headers()?.iter().map(str::to_string).collect();
+    let handle_index = headers.iter().position(|h| h == handle_column).ok_or_else(|| {
+        AppError::Args(format!("{} has no `{}` column", source.display(), handle_column))
+    })?;
+    let receipt_indexes: Vec<usize> = RECEIPT_COLUMNS
+        .iter()
+        .map(|column| {
+            headers.iter().position(|h| h == column).unwrap_or_else(|| {
+                headers.push(column.to_string());
+                headers.len() - 1
+            })
+        })
+        .collect();
+
+    // The same handle on two rows was sent to twice, so each row takes the next outcome for it
+    let mut outcomes: HashMap<String, VecDeque<&CampaignRecipient>> = HashMap::new();
+    for recipient in &report.recipients {
+        outcomes.entry(handle_key(&recipient.recipient)).or_default().push_back(recipient);
+    }
+
+    let mut writer = csv::Writer::from_path(dest)?;
+    writer.write_record(&headers)?;
+    let mut matched = 0;
+    for row in reader.records() {
+        let mut row: Vec<String> = row?.iter().map(str::to_string).collect();
+        row.resize(headers.len(), String::new());
+        let handle = row[handle_index].trim();
+        let outcome =
+            if handle.is_empty() { None } else { outcomes.get_mut(&handle_key(handle)).and_then(VecDeque::pop_front) };
+        matched += outcome.is_some() as usize;
+        let values = match outcome {
+            Some(r) => [
+                r.status.clone(),
+                r.sent_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+                r.message_id.map(|id| id.to_string()).unwrap_or_default(),
+                r.error.clone().unwrap_or_default(),
+            ],
+            None => Default::default(),
+        };
+        for (index, value) in receipt_indexes.iter().zip(values) {
+            row[*index] = value;
+        }
+        writer.write_record(&row)?;
+    }
+    writer.flush()?;
+    Ok(matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variants(weights: &[(&str, u32)]) -> Variants {
+        let variants: Vec<Variant> = weights
+            .iter()
+            .map(|(name, weight)| Variant {
+                name: name.to_string(),
+                weight: *weight,
+                template: MessageTemplate::new(name).unwrap(),
+            })
+            .collect();
+        let total_weight = variants.iter().map(|v| u64::from(v.weight)).sum();
+        Variants { variants, total_weight }
+    }
+
+    fn recipients(count: usize) -> impl Iterator<Item = String> {
+        (0..count).map(|i| format!("+1555{:07}", i))
+    }
+
+    #[test]
+    fn recipients_keep_their_variant() {
+        let variants = variants(&[("short", 1), ("long", 1)]);
+        for recipient in recipients(200) {
+            let first = &variants.pick("spring-sale", &recipient).name;
+            assert_eq!(&variants.pick("spring-sale", &recipient).name, first);
+        }
+        // However the number is written
+        let picked = &variants.pick("spring-sale", "+15550000042").name;
+        for written in ["5550000042", "(555) 000-0042", "+1 555-000-0042"] {
+            assert_eq!(&variants.pick("spring-sale", written).name, picked, "{written}");
+        }
+    }
+
+    #[test]
+    fn recipients_split_by_weight() {
+        let variants = variants(&[("short", 3), ("long", 1)]);
+        let short = recipients(4000).filter(|r| variants.pick("spring-sale", r).name == "short").count();
+        assert!((2800..3200).contains(&short), "{short} of 4000 got short");
+    }
+
+    #[test]
+    fn zero_weight_variants_are_never_picked() {
+        let variants = variants(&[("paused", 0), ("live", 1)]);
+        assert!(recipients(500).all(|r| variants.pick("spring-sale", &r).name == "live"));
+    }
+
+    #[test]
+    fn each_campaign_splits_recipients_afresh() {
+        let variants = variants(&[("short", 1), ("long", 1)]);
+        let moved = recipients(500)
+            .filter(|r| variants.pick("spring-sale", r).name != variants.pick("fall-sale", r).name)
+            .count();
+        assert!((150..350).contains(&moved), "{moved} of 500 moved");
+    }
+}
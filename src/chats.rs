@@ -0,0 +1,155 @@
+use chrono::{DateTime, FixedOffset};
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::error::AppError;
+use crate::export::{from_imessage_ns, open_database};
+use crate::timezone::Zone;
+
+/// A conversation and how active it is
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatSummary {
+    pub id: i32,
+    /// The group's name, or the other participants' handles
+    pub name: String,
+    /// The phone number, email or group ID Messages knows the chat by
+    pub identifier: String,
+    /// The chat's ID in Messages' AppleScript, like `iMessage;+;chat123456`
+    pub guid: String,
+    pub service: Option<String>,
+    /// Handles of everyone in the chat other than the user
+    pub participants: Vec<String>,
+    pub message_count: i64,
+    /// Messages the user sent
+    pub sent_count: i64,
+    pub received_count: i64,
+    /// Files sent either way
+    pub attachment_count: i64,
+    pub first_message_date: Option<DateTime<FixedOffset>>,
+    pub last_message_date: Option<DateTime<FixedOffset>>,
+    /// Messages a day on average from the first message to the last, or
+    /// over one day if they're less than a day apart
+    pub messages_per_day: f64,
+}
+
+/// Handles in each chat, by chat ROWID
+fn participants(db: &Connection) -> Result<HashMap<i32, Vec<String>>, AppError> {
+    let mut statement = db.prepare(
+        "SELECT j.chat_id, h.id FROM chat_handle_join j JOIN handle h ON h.ROWID = j.handle_id ORDER BY h.ROWID",
+    )?;
+    let mut participants: HashMap<i32, Vec<String>> = HashMap::new();
+    for row in statement.query_map([], |row| Ok((row.get::<_, i32>(0)?, row.get::<_, String>(1)?)))? {
+        let (chat, handle) = row?;
+        participants.entry(chat).or_default().push(handle);
+    }
+    Ok(participants)
+}
+
+/// Every chat in the database at `db_path`, most recently active first
+pub fn list_chats(db_path: &Path, timezone: Zone) -> Result<Vec<ChatSummary>, AppError> {
+    let (db, _) = open_database(db_path)?;
+    let mut participants = participants(&db)?;
+    // Every statistic in one pass, with attachments counted per message first
+    // so a message with several doesn't count more than once elsewhere
+    let mut statement = db.prepare(
+        "SELECT c.ROWID, c.chat_identifier, c.display_name, c.service_name, c.guid,
+                COUNT(m.ROWID), COALESCE(SUM(m.is_from_me = 1), 0), COALESCE(SUM(m.is_from_me = 0), 0),
+                COALESCE(SUM(a.count), 0), MIN(m.date), MAX(m.date)
+         FROM chat c
+         LEFT JOIN chat_message_join j ON j.chat_id = c.ROWID
+         LEFT JOIN message m ON m.ROWID = j.message_id
+         LEFT JOIN (
+             SELECT message_id, COUNT(*) AS count FROM message_attachment_join GROUP BY message_id
+         ) a ON a.message_id = m.ROWID
+         GROUP BY c.ROWID
+         ORDER BY MAX(m.date) DESC",
+    )?;
+    let rows = statement.query_map([], |row| {
+        Ok((
+            (
+                row.get::<_, i32>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+            ),
+            (row.get::<_, i64>(5)?, row.get::<_, i64>(6)?, row.get::<_, i64>(7)?, row.get::<_, i64>(8)?),
+            (row.get::<_, Option<i64>>(9)?, row.get::<_, Option<i64>>(10)?),
+        ))
+    })?;
+
+    let mut chats = Vec::new();
+    for row in rows {
+        let (
+            (id, identifier, display_name, service, guid),
+            (message_count, sent_count, received_count, attachment_count),
+            (first_date, last_date),
+        ) = row?;
+        let identifier = identifier.unwrap_or_default();
+        let participants = participants.remove(&id).unwrap_or_default();
+        let name = match display_name.filter(|n| !n.is_empty()) {
+            Some(name) => name,
+            None if !participants.is_empty() => participants.join(", "),
+            None => identifier.clone(),
+        };
+        let first_date = first_date.map(from_imessage_ns);
+        let last_date = last_date.map(from_imessage_ns);
+        let days = match (first_date, last_date) {
+            (Some(first), Some(last)) => ((last - first).num_seconds() as f64 / 86_400.0).max(1.0),
+            _ => 1.0,
+        };
+        chats.push(ChatSummary {
+            id,
+            name,
+            identifier,
+            guid: guid.unwrap_or_default(),
+            service,
+            participants,
+            message_count,
+            sent_count,
+            received_count,
+            attachment_count,
+            first_message_date: first_date.map(|date| timezone.localize(date)),
+            last_message_date: last_date.map(|date| timezone.localize(date)),
+            messages_per_day: (message_count as f64 / days * 100.0).round() / 100.0,
+        });
+    }
+    Ok(chats)
+}
+
+/// The ROWIDs of the chats with these GUIDs, like `iMessage;+;chat123456`
+/// for a group with no name. A GUID no chat has is an error.
+pub fn chat_ids_for_guids(db_path: &Path, guids: &[String]) -> Result<Vec<i32>, AppError> {
+    let (db, _) = open_database(db_path)?;
+    let mut statement = db.prepare("SELECT ROWID FROM chat WHERE guid = ?1")?;
+    guids
+        .iter()
+        .map(|guid| {
+            statement
+                .query_row([guid], |row| row.get(0))
+                .optional()?
+                .ok_or_else(|| AppError::Args(format!("No chat has the GUID {}; the chats command lists them", guid)))
+        })
+        .collect()
+}
+
+/// The chat `query` names: its GUID or identifier, or otherwise its name,
+/// ignoring case. A name shared by several chats is an error, listing them.
+pub fn find_chat(db_path: &Path, query: &str) -> Result<ChatSummary, AppError> {
+    let chats = list_chats(db_path, Zone::Local)?;
+    let mut matches: Vec<&ChatSummary> = chats.iter().filter(|c| c.guid == query || c.identifier == query).collect();
+    if matches.is_empty() {
+        matches = chats.iter().filter(|c| c.name.eq_ignore_ascii_case(query)).collect();
+    }
+    match matches.as_slice() {
+        [chat] => Ok((*chat).clone()),
+        [] => Err(AppError::Args(format!("No chat named {}; the chats command lists them", query))),
+        several => Err(AppError::Args(format!(
+            "More than one chat is named {}; give one of their GUIDs instead: {}",
+            query,
+            several.iter().map(|c| c.guid.as_str()).collect::<Vec<_>>().join(", ")
+        ))),
+    }
+}
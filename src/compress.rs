@@ -0,0 +1,30 @@
+use clap::ValueEnum;
+use flate2::write::GzEncoder;
+
+use crate::encrypt::Output;
+use crate::error::AppError;
+
+/// How output is compressed before it's written (and encrypted, if it is)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The extension compressed files are given, after the format's own
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+
+    /// Compress whatever is written to `output`
+    pub fn wrap(&self, output: Output) -> Result<Output, AppError> {
+        Ok(match self {
+            Compression::Gzip => Output::Gzip(Box::new(GzEncoder::new(output, flate2::Compression::default()))),
+            Compression::Zstd => Output::Zstd(Box::new(zstd::Encoder::new(output, zstd::DEFAULT_COMPRESSION_LEVEL)?)),
+        })
+    }
+}
@@ -0,0 +1,121 @@
+use imessage_database::util::dirs::home;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::AppError;
+
+/// Defaults read from `~/.config/imessage-blaster/config.toml`. Flags given on
+/// the command line take precedence over anything set here.
+///
+/// ```toml
+/// format = "ndjson"
+/// timezone = "America/Chicago"
+/// webhook_url = "https://example.com/hook"
+/// quiet_hours = "21:00-08:00"
+///
+/// [aliases]
+/// "+15551234567" = "Mom"
+/// ```
+///
+/// Aliases can also live in their own YAML or TOML file, named by
+/// `aliases_file` or `--aliases`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Output format, as given to `--format`
+    pub format: Option<String>,
+    pub timezone: Option<String>,
+    pub db_path: Option<PathBuf>,
+    pub output_file: Option<String>,
+    pub columns: Option<Vec<String>>,
+    pub resolve_contacts: Option<bool>,
+    pub webhook_url: Option<String>,
+    pub webhook_retries: Option<u32>,
+    /// Hours no one is sent messages in, in their own timezone, like "21:00-08:00"
+    pub quiet_hours: Option<String>,
+    /// Names to show for phone numbers and emails, ahead of the AddressBook's
+    pub aliases: HashMap<String, String>,
+    /// A YAML or TOML file of more aliases
+    pub aliases_file: Option<PathBuf>,
+}
+
+/// An alias file: either `aliases:` holding the mapping, or the mapping itself
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum AliasFile {
+    Wrapped { aliases: HashMap<String, String> },
+    Bare(HashMap<String, String>),
+}
+
+/// Read handle-to-name aliases from a `.yaml`/`.yml` or `.toml` file
+pub fn load_aliases(path: &Path) -> Result<HashMap<String, String>, AppError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| AppError::Config(format!("Couldn't read {}: {}", path.display(), e)))?;
+    let error = |e: &dyn std::fmt::Display| AppError::Config(format!("{}: {}", path.display(), e));
+    let file: AliasFile = match path.extension().and_then(|e| e.to_str()) {
+        Some("yaml" | "yml") => serde_yaml::from_str(&contents).map_err(|e| error(&e))?,
+        _ => toml::from_str(&contents).map_err(|e| error(&e))?,
+    };
+    Ok(match file {
+        AliasFile::Wrapped { aliases } | AliasFile::Bare(aliases) => aliases,
+    })
+}
+
+/// The handle a send recipient stands for: phone numbers and emails as
+/// given, anything else looked up by alias name, ignoring case
+pub fn resolve_recipient(recipient: &str, aliases: &HashMap<String, String>) -> Result<String, AppError> {
+    if recipient.contains('@') || recipient.chars().any(|c| c.is_ascii_digit()) {
+        return Ok(recipient.to_string());
+    }
+    let mut handles: Vec<&String> = aliases
+        .iter()
+        .filter(|(_, name)| name.eq_ignore_ascii_case(recipient))
+        .map(|(handle, _)| handle)
+        .collect();
+    handles.sort();
+    match handles.as_slice() {
+        [handle] => Ok(handle.to_string()),
+        [] => Err(AppError::Args(format!("{} isn't a phone number, email or alias", recipient))),
+        several => Err(AppError::Args(format!(
+            "{} is an alias for more than one handle ({}); use one of those instead",
+            recipient,
+            several.iter().map(|h| h.as_str()).collect::<Vec<_>>().join(", ")
+        ))),
+    }
+}
+
+impl Config {
+    /// `~/.config/imessage-blaster/config.toml`
+    pub fn default_path() -> PathBuf {
+        PathBuf::from(home()).join(".config").join("imessage-blaster").join("config.toml")
+    }
+
+    /// Read the config file at `path`
+    pub fn load(path: &Path) -> Result<Self, AppError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| AppError::Config(format!("Couldn't read {}: {}", path.display(), e)))?;
+        toml::from_str(&contents).map_err(|e| AppError::Config(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Merge in the aliases from `path`, or from `aliases_file` when no path
+    /// is given. The file's names win over the config's.
+    pub fn load_aliases_file(&mut self, path: Option<&Path>) -> Result<(), AppError> {
+        if let Some(path) = path.or(self.aliases_file.as_deref()) {
+            let aliases = load_aliases(path)?;
+            self.aliases.extend(aliases);
+        }
+        Ok(())
+    }
+
+    /// Read the config file at its default location, if there is one
+    pub fn load_default() -> Result<Self, AppError> {
+        let path = Self::default_path();
+        if path.exists() {
+            Self::load(&path)
+        } else {
+            Ok(Config::default())
+        }
+    }
+}
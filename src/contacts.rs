@@ -0,0 +1,134 @@
+use imessage_database::{tables::table::get_connection, util::dirs::home};
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::AppError;
+
+/// Where macOS keeps the AddressBook databases, relative to the home directory
+const ADDRESS_BOOK_DIR: &str = "Library/Application Support/AddressBook";
+
+/// Maps phone numbers and emails to the names in the macOS AddressBook
+#[derive(Debug, Default, Clone)]
+pub struct ContactBook {
+    names: HashMap<String, String>,
+    /// The handle keys of each contact card, for linking a person's aliases
+    people: Vec<Vec<String>>,
+}
+
+/// Reduce a handle to the form used as a lookup key: lowercased emails, and
+/// phone numbers stripped to their last 10 digits so `+1 (555) 123-4567` and
+/// `5551234567` match
+pub fn handle_key(handle: &str) -> String {
+    if handle.contains('@') {
+        return handle.trim().to_lowercase();
+    }
+    let digits: String = handle.chars().filter(|c| c.is_ascii_digit()).collect();
+    if digits.is_empty() {
+        handle.trim().to_lowercase()
+    } else if digits.len() > 10 {
+        digits[digits.len() - 10..].to_string()
+    } else {
+        digits
+    }
+}
+
+/// Find every `.abcddb` file under `dir`, including the per-account `Sources/*` copies
+fn find_databases(dir: &Path, found: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            find_databases(&path, found);
+        } else if path.extension().is_some_and(|ext| ext == "abcddb") {
+            found.push(path);
+        }
+    }
+}
+
+impl ContactBook {
+    /// Load every AddressBook database for the current user
+    pub fn load() -> Result<Self, AppError> {
+        let mut paths = Vec::new();
+        find_databases(&Path::new(&home()).join(ADDRESS_BOOK_DIR), &mut paths);
+        Self::load_from(&paths)
+    }
+
+    /// Load the given AddressBook databases, earlier paths taking precedence
+    pub fn load_from(paths: &[PathBuf]) -> Result<Self, AppError> {
+        let mut book = ContactBook::default();
+        for path in paths {
+            let db = get_connection(path)?;
+            book.read_database(&db)?;
+        }
+        Ok(book)
+    }
+
+    fn read_database(&mut self, db: &Connection) -> Result<(), AppError> {
+        let queries = [
+            "SELECT p.ZFULLNUMBER, r.ZFIRSTNAME, r.ZLASTNAME, r.ZORGANIZATION, r.Z_PK
+             FROM ZABCDPHONENUMBER p JOIN ZABCDRECORD r ON p.ZOWNER = r.Z_PK
+             WHERE p.ZFULLNUMBER IS NOT NULL",
+            "SELECT e.ZADDRESS, r.ZFIRSTNAME, r.ZLASTNAME, r.ZORGANIZATION, r.Z_PK
+             FROM ZABCDEMAILADDRESS e JOIN ZABCDRECORD r ON e.ZOWNER = r.Z_PK
+             WHERE e.ZADDRESS IS NOT NULL",
+        ];
+        let mut people: HashMap<i64, Vec<String>> = HashMap::new();
+
+        for sql in queries {
+            let mut statement = db.prepare(sql)?;
+            let rows = statement.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, i64>(4)?,
+                ))
+            })?;
+
+            for (handle, first, last, organization, record) in rows.flatten() {
+                people.entry(record).or_default().push(handle_key(&handle));
+                let name = [first, last]
+                    .into_iter()
+                    .flatten()
+                    .filter(|part| !part.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let name = if name.is_empty() {
+                    match organization {
+                        Some(org) if !org.is_empty() => org,
+                        _ => continue,
+                    }
+                } else {
+                    name
+                };
+                self.names.entry(handle_key(&handle)).or_insert(name);
+            }
+        }
+        self.people.extend(people.into_values().filter(|keys| keys.len() > 1));
+        Ok(())
+    }
+
+    /// The handle keys of every contact with more than one phone number or
+    /// email, i.e. the handles known to belong to the same person
+    pub fn linked_handles(&self) -> impl Iterator<Item = &[String]> {
+        self.people.iter().map(Vec::as_slice)
+    }
+
+    /// Look up the contact name for a phone number or email
+    pub fn name_for(&self, handle: &str) -> Option<&str> {
+        self.names.get(&handle_key(handle)).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
@@ -0,0 +1,228 @@
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Utc, Weekday};
+
+use crate::error::AppError;
+use crate::timezone::Zone;
+
+fn invalid(input: &str) -> AppError {
+    AppError::Args(format!(
+        "Invalid date: {}. Expected YYYY-MM-DD, today, yesterday, 7d, 12h, \"3 weeks ago\" or \"last monday\"",
+        input
+    ))
+}
+
+/// Length of `count` units, for units like `d`, `days` or `hours`
+fn span(count: i64, unit: &str) -> Option<Duration> {
+    match unit {
+        "s" | "sec" | "secs" | "second" | "seconds" => Some(Duration::seconds(count)),
+        "m" | "min" | "mins" | "minute" | "minutes" => Some(Duration::minutes(count)),
+        "h" | "hr" | "hrs" | "hour" | "hours" => Some(Duration::hours(count)),
+        "d" | "day" | "days" => Some(Duration::days(count)),
+        "w" | "wk" | "wks" | "week" | "weeks" => Some(Duration::weeks(count)),
+        _ => None,
+    }
+}
+
+/// `7d`, `12h`, `7 days`, `3 weeks ago`
+fn parse_ago(input: &str) -> Option<Duration> {
+    let input = input.strip_suffix("ago").unwrap_or(input).trim_end();
+    let digits = input.find(|c: char| !c.is_ascii_digit())?;
+    let count: i64 = input[..digits].parse().ok()?;
+    span(count, input[digits..].trim_start())
+}
+
+/// The most recent `weekday` strictly before `today`
+fn last_weekday(today: NaiveDate, weekday: Weekday) -> NaiveDate {
+    let back = (today.weekday().num_days_from_monday() + 7 - weekday.num_days_from_monday() - 1) % 7 + 1;
+    today - Duration::days(back as i64)
+}
+
+/// Parse an absolute (`2024-03-01`) or relative (`yesterday`, `7d`,
+/// `last monday`) date, with calendar days taken in `zone`
+pub fn parse_date(input: &str, zone: Zone, now: DateTime<Utc>) -> Result<DateTime<Utc>, AppError> {
+    let normalized = input.trim().to_lowercase();
+    if let Ok(date) = NaiveDate::parse_from_str(&normalized, "%Y-%m-%d") {
+        return Ok(zone.start_of_day(date));
+    }
+
+    let today = zone.localize(now).date_naive();
+    match normalized.as_str() {
+        "now" => return Ok(now),
+        "today" => return Ok(zone.start_of_day(today)),
+        "yesterday" => return Ok(zone.start_of_day(today - Duration::days(1))),
+        "tomorrow" => return Ok(zone.start_of_day(today + Duration::days(1))),
+        _ => {}
+    }
+
+    if let Some(ago) = parse_ago(&normalized) {
+        return Ok(now - ago);
+    }
+
+    let day = normalized.strip_prefix("last ").unwrap_or(&normalized);
+    match day.parse::<Weekday>() {
+        Ok(weekday) => Ok(zone.start_of_day(last_weekday(today, weekday))),
+        Err(_) => Err(invalid(input)),
+    }
+}
+
+/// Like [`parse_date`], but also accepts a time of day (`2024-12-25 09:00`)
+/// and times from now (`in 2h`)
+pub fn parse_datetime(input: &str, zone: Zone, now: DateTime<Utc>) -> Result<DateTime<Utc>, AppError> {
+    let trimmed = input.trim();
+    if let Some(ahead) = trimmed.to_lowercase().strip_prefix("in ").and_then(parse_ago) {
+        return Ok(now + ahead);
+    }
+    for format in ["%Y-%m-%d %H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(trimmed, format) {
+            return Ok(zone.from_local(naive));
+        }
+    }
+    parse_date(input, zone, now)
+}
+
+/// Times of day and days of the week, like business hours, that exports
+/// and scheduled sends can be kept to
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TimeWindow {
+    /// From and until, in local time. Until is exclusive, and before from
+    /// for a window that runs past midnight, like `22:00-06:00`.
+    hours: Option<(NaiveTime, NaiveTime)>,
+    /// Every day when empty
+    days: Vec<Weekday>,
+}
+
+impl TimeWindow {
+    /// Parse `--between 09:00-17:00` and `--days mon,tue,wed`; `None` if neither is given
+    pub fn new(between: Option<&str>, days: &[String]) -> Result<Option<Self>, AppError> {
+        if between.is_none() && days.is_empty() {
+            return Ok(None);
+        }
+        let hours = between
+            .map(|range| {
+                let invalid = || AppError::Args(format!("Invalid --between: {}. Expected HH:MM-HH:MM, like 09:00-17:00", range));
+                let (from, until) = range.split_once('-').ok_or_else(invalid)?;
+                let time = |t: &str| NaiveTime::parse_from_str(t.trim(), "%H:%M").map_err(|_| invalid());
+                let (from, until) = (time(from)?, time(until)?);
+                if from == until {
+                    return Err(invalid());
+                }
+                Ok((from, until))
+            })
+            .transpose()?;
+        let days = days
+            .iter()
+            .map(|day| {
+                day.trim().parse::<Weekday>().map_err(|_| {
+                    AppError::Args(format!("Invalid day: {}. Expected mon, tue, wed, thu, fri, sat or sun", day))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Some(TimeWindow { hours, days }))
+    }
+
+    /// Every day from `from` until `until`
+    pub fn daily(from: NaiveTime, until: NaiveTime) -> Self {
+        TimeWindow { hours: Some((from, until)), days: Vec::new() }
+    }
+
+    /// Whether a local time falls in the window
+    pub fn contains(&self, at: DateTime<FixedOffset>) -> bool {
+        if !self.days.is_empty() && !self.days.contains(&at.weekday()) {
+            return false;
+        }
+        let Some((from, until)) = self.hours else {
+            return true;
+        };
+        let time = at.time().with_nanosecond(0).unwrap_or(at.time());
+        if from < until {
+            from <= time && time < until
+        } else {
+            time >= from || time < until
+        }
+    }
+
+    /// The first instant at or after `at` that's in the window, in `zone`
+    pub fn next_open(&self, at: DateTime<Utc>, zone: Zone) -> DateTime<Utc> {
+        if self.contains(zone.localize(at)) {
+            return at;
+        }
+        let from = self.hours.map_or(NaiveTime::MIN, |(from, _)| from);
+        let today = zone.localize(at).date_naive();
+        // A window opens at `from` on one of the next eight days
+        (0..=8)
+            .map(|days| zone.from_local((today + Duration::days(days)).and_time(from)))
+            .find(|open| *open > at && self.contains(zone.localize(*open)))
+            .unwrap_or(at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use chrono_tz::America::New_York;
+
+    const NEW_YORK: Zone = Zone::Named(New_York);
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    /// Friday 2024-03-15, 08:00 in New York, five days after clocks went forward
+    fn now() -> DateTime<Utc> {
+        utc(2024, 3, 15, 12, 0)
+    }
+
+    #[test]
+    fn ago_counts_back_from_now() {
+        assert_eq!(parse_date("7d", NEW_YORK, now()).unwrap(), utc(2024, 3, 8, 12, 0));
+        assert_eq!(parse_date("12h", NEW_YORK, now()).unwrap(), utc(2024, 3, 15, 0, 0));
+        assert_eq!(parse_date("3 weeks ago", NEW_YORK, now()).unwrap(), utc(2024, 2, 23, 12, 0));
+        assert_eq!(parse_date("90 Minutes", NEW_YORK, now()).unwrap(), utc(2024, 3, 15, 10, 30));
+        assert_eq!(parse_ago("7 fortnights"), None);
+        assert_eq!(parse_ago("d"), None);
+    }
+
+    #[test]
+    fn named_days_start_at_local_midnight() {
+        assert_eq!(parse_date("now", NEW_YORK, now()).unwrap(), now());
+        assert_eq!(parse_date("today", NEW_YORK, now()).unwrap(), utc(2024, 3, 15, 4, 0));
+        assert_eq!(parse_date(" Yesterday ", NEW_YORK, now()).unwrap(), utc(2024, 3, 14, 4, 0));
+        assert_eq!(parse_date("tomorrow", NEW_YORK, now()).unwrap(), utc(2024, 3, 16, 4, 0));
+        assert_eq!(parse_date("2024-03-01", NEW_YORK, now()).unwrap(), utc(2024, 3, 1, 5, 0));
+    }
+
+    #[test]
+    fn days_across_daylight_saving_keep_their_own_offset() {
+        // Midnight on the 10th was still EST; clocks went forward at 02:00
+        assert_eq!(parse_date("2024-03-10", NEW_YORK, now()).unwrap(), utc(2024, 3, 10, 5, 0));
+        assert_eq!(parse_date("2024-03-11", NEW_YORK, now()).unwrap(), utc(2024, 3, 11, 4, 0));
+        assert_eq!(parse_date("last friday", NEW_YORK, now()).unwrap(), utc(2024, 3, 8, 5, 0));
+    }
+
+    #[test]
+    fn last_weekday_is_strictly_before_today() {
+        let friday = NaiveDate::from_ymd_opt(2024, 3, 15).unwrap();
+        assert_eq!(last_weekday(friday, Weekday::Fri), NaiveDate::from_ymd_opt(2024, 3, 8).unwrap());
+        assert_eq!(last_weekday(friday, Weekday::Thu), NaiveDate::from_ymd_opt(2024, 3, 14).unwrap());
+        assert_eq!(last_weekday(friday, Weekday::Sat), NaiveDate::from_ymd_opt(2024, 3, 9).unwrap());
+        assert_eq!(parse_date("monday", NEW_YORK, now()).unwrap(), utc(2024, 3, 11, 4, 0));
+    }
+
+    #[test]
+    fn today_is_the_zones_day_not_utcs() {
+        // Friday 23:00 in New York, but already Saturday in UTC
+        let late = utc(2024, 3, 16, 3, 0);
+        assert_eq!(parse_date("today", NEW_YORK, late).unwrap(), utc(2024, 3, 15, 4, 0));
+        assert_eq!(parse_date("last friday", NEW_YORK, late).unwrap(), utc(2024, 3, 8, 5, 0));
+        let utc_zone = Zone::Named(chrono_tz::UTC);
+        assert_eq!(parse_date("today", utc_zone, late).unwrap(), utc(2024, 3, 16, 0, 0));
+        assert_eq!(parse_date("last friday", utc_zone, late).unwrap(), utc(2024, 3, 15, 0, 0));
+    }
+
+    #[test]
+    fn rejects_what_it_cant_read() {
+        for input in ["", "last", "next friday", "2024-13-01", "-7d"] {
+            assert!(matches!(parse_date(input, NEW_YORK, now()), Err(AppError::Args(_))), "{input}");
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use imessage_database::tables::messages::Message;
+use std::collections::{HashMap, HashSet};
+
+use crate::export::imessage_ns;
+
+/// How many seconds apart the same text still counts as a copy, by default
+pub const DEFAULT_WINDOW_SECONDS: u64 = 5;
+
+/// Drops the copies of messages that restoring or merging backups leaves in chat.db
+///
+/// A copy is either a second row with a guid already seen, or a message with
+/// the same text from the same sender in the same conversation, sent within
+/// `window_ns` of the other. Conversations are matched by chat identifier
+/// rather than chat row, as Messages in iCloud can file a conversation under
+/// one chat per account, each with its own copy of the messages. Every guid
+/// and text seen is remembered, so memory grows with the export.
+#[derive(Debug, Clone)]
+pub struct Deduplicator {
+    window_ns: i64,
+    guids: HashSet<String>,
+    /// When each (conversation, sender, text) was last seen
+    texts: HashMap<(Option<String>, Option<i32>, bool, String), i64>,
+}
+
+impl Deduplicator {
+    /// Treat same-text messages up to `window_seconds` apart as copies
+    pub fn new(window_seconds: u64) -> Self {
+        Deduplicator {
+            window_ns: i64::try_from(window_seconds).unwrap_or(i64::MAX).saturating_mul(1_000_000_000),
+            guids: HashSet::new(),
+            texts: HashMap::new(),
+        }
+    }
+
+    /// Whether `msg`, in the chat with identifier `conversation`, copies one
+    /// already seen. A message that isn't is remembered, so later copies of
+    /// it are caught.
+    pub fn is_duplicate(&mut self, msg: &Message, text: Option<&str>, conversation: Option<&str>) -> bool {
+        if !self.guids.insert(msg.guid.clone()) {
+            return true;
+        }
+        // Messages without text, like bare attachments, are only matched by guid
+        let Some(text) = text.filter(|t| !t.is_empty()) else {
+            return false;
+        };
+        let key = (conversation.map(String::from), msg.handle_id, msg.is_from_me, text.to_string());
+        // Compared against the last message kept, so a run of the same short
+        // reply isn't collapsed into one
+        let date = imessage_ns(msg.date);
+        match self.texts.get(&key) {
+            Some(&kept) if date.abs_diff(kept) <= self.window_ns.unsigned_abs() => true,
+            _ => {
+                self.texts.insert(key, date);
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use chrono::{DateTime, FixedOffset};
+use imessage_database::message_types::edited::{EditStatus, EditedMessage};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// One version of an edited message, the original included
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct EditRecord {
+    pub date: DateTime<FixedOffset>,
+    pub text: Option<String>,
+}
+
+/// Every version of the edited parts of a message, oldest first
+pub fn edit_history(
+    edited: Option<&EditedMessage>,
+    date_of: impl Fn(i64) -> DateTime<FixedOffset>,
+) -> Vec<EditRecord> {
+    let Some(edited) = edited else {
+        return Vec::new();
+    };
+    let mut history: Vec<EditRecord> = edited
+        .parts
+        .iter()
+        .filter(|part| part.status == EditStatus::Edited)
+        .flat_map(|part| &part.edit_history)
+        .map(|event| EditRecord { date: date_of(event.date), text: event.text.clone() })
+        .collect();
+    history.sort_by_key(|edit| edit.date);
+    history
+}
+
+/// Whether the message, or any part of it, was unsent
+pub fn was_unsent(edited: Option<&EditedMessage>) -> bool {
+    edited.is_some_and(|edited| edited.parts.iter().any(|part| part.status == EditStatus::Unsent))
+}
@@ -0,0 +1,146 @@
+use age::stream::StreamWriter;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::thread::{self, JoinHandle};
+
+use crate::error::AppError;
+
+/// How output is encrypted before it's written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Encryption {
+    /// age, to these `age1...` public keys
+    Age(Vec<String>),
+    /// GnuPG's `gpg`, to these key IDs or emails from the user's keyring
+    Gpg(Vec<String>),
+}
+
+/// A destination for output: either written straight through, or encrypted
+/// or compressed on the way. Call [`Output::finish`] once done, as encrypted
+/// and compressed output isn't complete until then.
+pub enum Output {
+    Plain(Box<dyn Write>),
+    Age(StreamWriter<Box<dyn Write>>),
+    /// `stderr` is read on its own thread as gpg runs, so a long run of
+    /// warnings can't fill the pipe and stall it
+    Gpg { child: Child, stdin: ChildStdin, stderr: JoinHandle<Vec<u8>> },
+    /// Compressed, then written to the inner output
+    Gzip(Box<GzEncoder<Output>>),
+    Zstd(Box<zstd::Encoder<'static, Output>>),
+}
+
+/// Open `path` for writing, or stdout when there isn't one
+fn open_plain(path: Option<&str>) -> Result<Box<dyn Write>, AppError> {
+    Ok(match path {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    })
+}
+
+impl Encryption {
+    /// Start writing encrypted output to `path`, or stdout when there isn't one.
+    /// Plaintext only ever exists in memory.
+    pub fn open(&self, path: Option<&str>) -> Result<Output, AppError> {
+        match self {
+            Encryption::Age(recipients) => {
+                let recipients = recipients
+                    .iter()
+                    .map(|r| {
+                        r.parse::<age::x25519::Recipient>()
+                            .map_err(|e| AppError::Encrypt(format!("Invalid age recipient {}: {}", r, e)))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let encryptor =
+                    age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))
+                        .map_err(|e| AppError::Encrypt(e.to_string()))?;
+                Ok(Output::Age(encryptor.wrap_output(open_plain(path)?)?))
+            }
+            Encryption::Gpg(recipients) => {
+                // Check the keys up front; once gpg is running, a bad one only shows as a broken pipe
+                for recipient in recipients {
+                    let found = Command::new("gpg")
+                        .args(["--batch", "--list-keys", recipient])
+                        .stdout(Stdio::null())
+                        .stderr(Stdio::null())
+                        .status()
+                        .map_err(|e| AppError::Encrypt(format!("Couldn't run gpg: {}", e)))?;
+                    if !found.success() {
+                        return Err(AppError::Encrypt(format!("No public key for {} in the gpg keyring", recipient)));
+                    }
+                }
+                let mut command = Command::new("gpg");
+                command.args(["--batch", "--yes", "--encrypt"]);
+                for recipient in recipients {
+                    command.args(["--recipient", recipient]);
+                }
+                // gpg writes the file itself, so it never passes through us unencrypted
+                match path {
+                    Some(path) => command.args(["--output", path]).stdout(Stdio::null()),
+                    None => command.stdout(Stdio::inherit()),
+                };
+                let mut child = command
+                    .stdin(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                    .map_err(|e| AppError::Encrypt(format!("Couldn't run gpg: {}", e)))?;
+                let stdin = child.stdin.take().unwrap();
+                let mut err = child.stderr.take().unwrap();
+                let stderr = thread::spawn(move || {
+                    let mut stderr = Vec::new();
+                    // What was read before an error is still worth showing
+                    let _ = err.read_to_end(&mut stderr);
+                    stderr
+                });
+                Ok(Output::Gpg { child, stdin, stderr })
+            }
+        }
+    }
+}
+
+impl Output {
+    /// Write any buffered data and, for encrypted or compressed output, the final block
+    pub fn finish(self) -> Result<(), AppError> {
+        match self {
+            Output::Plain(mut out) => Ok(out.flush()?),
+            Output::Gzip(encoder) => encoder.finish()?.finish(),
+            Output::Zstd(encoder) => encoder.finish()?.finish(),
+            Output::Age(stream) => Ok(stream.finish()?.flush()?),
+            Output::Gpg { mut child, stdin, stderr } => {
+                drop(stdin);
+                let status = child.wait()?;
+                let stderr = stderr.join().unwrap_or_default();
+                if status.success() {
+                    return Ok(());
+                }
+                let stderr = String::from_utf8_lossy(&stderr);
+                Err(AppError::Encrypt(match stderr.trim() {
+                    "" => format!("gpg exited with {}", status),
+                    message => message.to_string(),
+                }))
+            }
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(out) => out.write(buf),
+            Output::Age(stream) => stream.write(buf),
+            Output::Gpg { stdin, .. } => stdin.write(buf),
+            Output::Gzip(encoder) => encoder.write(buf),
+            Output::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(out) => out.flush(),
+            Output::Age(stream) => stream.flush(),
+            Output::Gpg { stdin, .. } => stdin.flush(),
+            Output::Gzip(encoder) => encoder.flush(),
+            Output::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use imessage_database::error::table::{TableConnectError, TableError};
+use serde_json::json;
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+/// Exit code for errors that don't have their own
+pub const EXIT_FAILURE: u8 = 1;
+/// Exit code for an export that failed after some messages were written
+pub const EXIT_PARTIAL: u8 = 3;
+/// Exit code for bad command-line arguments (`EX_USAGE` from sysexits.h)
+pub const EXIT_USAGE: u8 = 64;
+/// Exit code for a missing or unreadable chat.db (`EX_NOINPUT` from sysexits.h)
+pub const EXIT_NO_INPUT: u8 = 66;
+/// Exit code for a missing Full Disk Access grant (`EX_NOPERM` from sysexits.h)
+pub const EXIT_NO_PERMISSION: u8 = 77;
+/// Exit code for a bad config, aliases or rules file (`EX_CONFIG` from sysexits.h)
+pub const EXIT_CONFIG: u8 = 78;
+
+#[derive(Debug)]
+pub enum AppError {
+    Table(TableError),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Csv(csv::Error),
+    Args(String),
+    Send(String),
+    Template(String),
+    Http(String),
+    Parquet(String),
+    /// The PostgreSQL `--sink` refused a connection or query
+    Postgres(String),
+    Transcribe(String),
+    Encrypt(String),
+    Config(String),
+    /// An image or video couldn't be converted
+    Media(String),
+    /// launchctl couldn't load or unload a LaunchAgent
+    Launchd(String),
+    /// The `--mqtt` broker refused a connection or publish
+    Mqtt(String),
+    /// A request to one of the servers had a body over this many bytes
+    BodyTooLarge(u64),
+    /// chat.db exists but macOS won't let this process read it
+    FullDiskAccess(PathBuf),
+    /// chat.db has a write-ahead log that a read-only connection can't read
+    /// where it is, so recent messages would be missing
+    WalUnreadable(PathBuf),
+    /// The export failed after this many messages were exported, so the
+    /// output is incomplete
+    Partial { exported: u64, error: Box<AppError> },
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Table(e) => write!(f, "Database error: {}", e),
+            AppError::Io(e) => write!(f, "IO error: {}", e),
+            AppError::Json(e) => write!(f, "JSON error: {}", e),
+            AppError::Csv(e) => write!(f, "CSV error: {}", e),
+            AppError::Args(e) => write!(f, "Argument error: {}", e),
+            AppError::Send(e) => write!(f, "Send error: {}", e),
+            AppError::Template(e) => write!(f, "Template error: {}", e),
+            AppError::Http(e) => write!(f, "HTTP error: {}", e),
+            AppError::Parquet(e) => write!(f, "Parquet error: {}", e),
+            AppError::Postgres(e) => write!(f, "PostgreSQL error: {}", e),
+            AppError::Transcribe(e) => write!(f, "Transcription error: {}", e),
+            AppError::Encrypt(e) => write!(f, "Encryption error: {}", e),
+            AppError::Config(e) => write!(f, "Config error: {}", e),
+            AppError::Media(e) => write!(f, "Media error: {}", e),
+            AppError::Launchd(e) => write!(f, "launchd error: {}", e),
+            AppError::Mqtt(e) => write!(f, "MQTT error: {}", e),
+            AppError::BodyTooLarge(max) => write!(f, "Request body is over {} bytes", max),
+            AppError::FullDiskAccess(path) => write!(
+                f,
+                "Permission denied reading {}\n\n\
+                 Messages data is protected by macOS. Give your terminal Full Disk Access:\n  \
+                 1. Open System Settings > Privacy & Security > Full Disk Access\n  \
+                 2. Enable the app you're running this from (Terminal, iTerm, ...)\n  \
+                 3. Quit and reopen that app, then run this again",
+                path.display()
+            ),
+            AppError::WalUnreadable(path) => write!(
+                f,
+                "Can't read the write-ahead log of {}, which holds its most recent messages, where it is \
+                 as its folder isn't writable; copy the folder somewhere writable and read it from there",
+                path.display()
+            ),
+            AppError::Partial { exported, error } => {
+                write!(f, "{} (the export stopped after {} messages, so the output is incomplete)", error, exported)
+            }
+        }
+    }
+}
+
+impl Error for AppError {}
+
+impl AppError {
+    /// Process exit code for this error
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            AppError::FullDiskAccess(_)
+            | AppError::Table(TableError::CannotConnect(TableConnectError::Permissions(_))) => EXIT_NO_PERMISSION,
+            AppError::Table(TableError::CannotConnect(
+                TableConnectError::DoesNotExist(_) | TableConnectError::NotAFile(_) | TableConnectError::NotBackupRoot,
+            )) => EXIT_NO_INPUT,
+            AppError::Args(_) => EXIT_USAGE,
+            AppError::Config(_) => EXIT_CONFIG,
+            AppError::Partial { .. } => EXIT_PARTIAL,
+            _ => EXIT_FAILURE,
+        }
+    }
+
+    /// A short name for the kind of error, for scripts to branch on
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AppError::Table(TableError::CannotConnect(TableConnectError::Permissions(_)))
+            | AppError::FullDiskAccess(_) => "permission",
+            AppError::Table(_) if self.exit_code() == EXIT_NO_INPUT => "database_missing",
+            AppError::Table(_) | AppError::WalUnreadable(_) => "database",
+            AppError::Io(_) => "io",
+            AppError::Json(_) => "json",
+            AppError::Csv(_) => "csv",
+            AppError::Args(_) => "args",
+            AppError::Send(_) => "send",
+            AppError::Template(_) => "template",
+            AppError::Http(_) => "http",
+            AppError::Parquet(_) => "parquet",
+            AppError::Postgres(_) => "postgres",
+            AppError::Transcribe(_) => "transcribe",
+            AppError::Encrypt(_) => "encrypt",
+            AppError::Config(_) => "config",
+            AppError::Media(_) => "media",
+            AppError::Launchd(_) => "launchd",
+            AppError::Mqtt(_) => "mqtt",
+            AppError::BodyTooLarge(_) => "too_large",
+            AppError::Partial { .. } => "partial",
+        }
+    }
+
+    /// Mark an error that stopped an export partway, after `exported` messages
+    pub fn partial(self, exported: u64) -> Self {
+        match self {
+            AppError::Partial { .. } => self,
+            _ if exported == 0 => self,
+            error => AppError::Partial { exported, error: Box::new(error) },
+        }
+    }
+
+    /// The error as a JSON object for `--json-errors`, like
+    /// `{"error": {"kind": "args", "exit_code": 64, "message": "..."}}`
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut error = json!({ "kind": self.kind(), "exit_code": self.exit_code(), "message": self.to_string() });
+        if let AppError::Partial { exported, error: cause } = self {
+            error["exported"] = json!(exported);
+            error["cause"] = cause.to_json()["error"].take();
+        }
+        json!({ "error": error })
+    }
+}
+
+/// Whether SQLite refused to open or read the database because of permissions
+pub(crate) fn is_permission_error(err: &rusqlite::Error) -> bool {
+    matches!(
+        err,
+        rusqlite::Error::SqliteFailure(e, _)
+            if matches!(
+                e.code,
+                rusqlite::ErrorCode::CannotOpen
+                    | rusqlite::ErrorCode::PermissionDenied
+                    | rusqlite::ErrorCode::AuthorizationForStatementDenied
+            )
+    )
+}
+
+impl From<TableError> for AppError {
+    fn from(err: TableError) -> Self {
+        AppError::Table(err)
+    }
+}
+
+impl From<rusqlite::Error> for AppError {
+    fn from(err: rusqlite::Error) -> Self {
+        AppError::Table(TableError::QueryError(err))
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(err: std::io::Error) -> Self {
+        AppError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(err: serde_json::Error) -> Self {
+        AppError::Json(err)
+    }
+}
+
+impl From<csv::Error> for AppError {
+    fn from(err: csv::Error) -> Self {
+        AppError::Csv(err)
+    }
+}
+
+impl From<parquet::errors::ParquetError> for AppError {
+    fn from(err: parquet::errors::ParquetError) -> Self {
+        AppError::Parquet(err.to_string())
+    }
+}
+
+impl From<postgres::Error> for AppError {
+    fn from(err: postgres::Error) -> Self {
+        // The details, like the server's message for a failed query, are in the source
+        match err.source() {
+            Some(cause) => AppError::Postgres(format!("{}: {}", err, cause)),
+            None => AppError::Postgres(err.to_string()),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use imessage_database::tables::messages::{models::GroupAction, Message};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A change to a group chat, recorded in chat.db as a message of its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EventType {
+    NameChanged,
+    ParticipantAdded,
+    ParticipantRemoved,
+    /// The sender left the chat
+    ParticipantLeft,
+    PhotoChanged,
+    PhotoRemoved,
+}
+
+/// What a group event message did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupEvent {
+    pub event_type: EventType,
+    /// Who was added or removed
+    pub participant: Option<String>,
+    /// The chat's new name
+    pub name: Option<String>,
+}
+
+impl GroupEvent {
+    /// The event `msg` records, if it's a group event. `handle` looks up a
+    /// handle by its ROWID.
+    pub fn from_message(msg: &Message, handle: impl Fn(i32) -> Option<String>) -> Option<Self> {
+        let event = |event_type| GroupEvent { event_type, participant: None, name: None };
+        Some(match msg.group_action()? {
+            GroupAction::ParticipantAdded(id) => {
+                GroupEvent { participant: handle(id), ..event(EventType::ParticipantAdded) }
+            }
+            GroupAction::ParticipantRemoved(id) => {
+                GroupEvent { participant: handle(id), ..event(EventType::ParticipantRemoved) }
+            }
+            GroupAction::NameChange(name) => GroupEvent { name: Some(name.to_string()), ..event(EventType::NameChanged) },
+            GroupAction::ParticipantLeft => event(EventType::ParticipantLeft),
+            GroupAction::GroupIconChanged => event(EventType::PhotoChanged),
+            GroupAction::GroupIconRemoved => event(EventType::PhotoRemoved),
+        })
+    }
+
+    /// The event as the text of its record, to follow the sender's name in a
+    /// transcript, like "added +15551234567"
+    pub fn describe(&self) -> String {
+        let someone = self.participant.as_deref().unwrap_or("someone");
+        match self.event_type {
+            EventType::NameChanged => match &self.name {
+                Some(name) => format!("named the conversation \"{}\"", name),
+                None => "removed the conversation name".to_string(),
+            },
+            EventType::ParticipantAdded => format!("added {}", someone),
+            EventType::ParticipantRemoved => format!("removed {}", someone),
+            EventType::ParticipantLeft => "left the conversation".to_string(),
+            EventType::PhotoChanged => "changed the group photo".to_string(),
+            EventType::PhotoRemoved => "removed the group photo".to_string(),
+        }
+    }
+}
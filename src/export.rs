@@ -0,0 +1,1462 @@
+use chrono::{DateTime, Duration, FixedOffset, TimeZone, Utc};
+use imessage_database::{
+    error::table::{TableConnectError, TableError},
+    message_types::expressives::{BubbleEffect, Expressive, ScreenEffect},
+    tables::{
+        attachment::Attachment,
+        chat::Chat,
+        chat_handle::ChatToHandle,
+        handle::Handle,
+        messages::Message,
+        table::{
+            get_connection, Cacheable, Table, ATTACHMENT, CHAT_MESSAGE_JOIN, DEFAULT_PATH_IOS,
+            MESSAGE_ATTACHMENT_JOIN, RECENTLY_DELETED,
+        },
+    },
+    util::{dirs::default_db_path, platform::Platform},
+};
+use regex::Regex;
+use rusqlite::{params_from_iter, types::Value, Connection, OptionalExtension};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::ErrorKind;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Instant;
+use tracing::{debug, info, trace, warn};
+
+use crate::analyze;
+use crate::anonymize::Anonymizer;
+use crate::attachments::{self, AttachmentCopier, AttachmentRecord};
+use crate::balloon::{self, AppPayload};
+use crate::body;
+use crate::contacts::{handle_key, ContactBook};
+use crate::dates::TimeWindow;
+use crate::dedupe::Deduplicator;
+use crate::edits::{self, EditRecord};
+use crate::error::{is_permission_error, AppError};
+use crate::events::{EventType, GroupEvent};
+use crate::links::{self, LinkExtractor, LinkPreview};
+use crate::media::MediaConverter;
+use crate::merge::{HandleInfo, HandleMerger};
+use crate::phone::{NumberInfo, NumberNormalizer};
+use crate::query::{self, Filters, PageQuery, DELETED_MESSAGES};
+use crate::reactions::{self, ReactionMode, ReactionRecord};
+use crate::recover::{self, CarvedMessage};
+use crate::service::Service;
+use crate::snapshot::Snapshot;
+use crate::thumbnail::Thumbnailer;
+use crate::timezone::Zone;
+use crate::transcribe::{self, Transcriber};
+use crate::verify::{AttachmentStatus, AttachmentVerifier, Verification};
+
+/// Number of rows read from chat.db per query
+const PAGE_SIZE: i64 = 1000;
+
+/// Which messages to export and where to read them from
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    /// Path to chat.db, or to the root of an unencrypted iOS backup
+    pub db_path: PathBuf,
+    /// Only include messages sent at or after this time
+    pub start_date: Option<DateTime<Utc>>,
+    /// Only include messages sent at or before this time
+    pub end_date: Option<DateTime<Utc>>,
+    /// Only include messages sent at these times of day and days of the
+    /// week, in `timezone`
+    pub window: Option<TimeWindow>,
+    /// Only include messages sent by the user
+    pub only_from_me: bool,
+    /// Look up `from_name`/`to_name` in the macOS AddressBook
+    pub resolve_contacts: bool,
+    /// Only include messages sent by one of these phone numbers or emails
+    pub from: Vec<String>,
+    /// Only include messages sent to one of these phone numbers or emails
+    pub to: Vec<String>,
+    /// Only include messages exchanged with one of these phone numbers or emails
+    pub with: Vec<String>,
+    /// Only include messages in these chats, by ROWID
+    pub chat_ids: Vec<i32>,
+    /// Copy attachments into this directory
+    pub attachments_dir: Option<PathBuf>,
+    /// How tapbacks are exported
+    pub reactions: ReactionMode,
+    /// Only include messages with a ROWID greater than this
+    pub after_rowid: Option<i32>,
+    /// Only include messages whose text contains this, ignoring case
+    pub search: Option<String>,
+    /// Only include messages whose text matches this regular expression
+    pub regex: Option<String>,
+    /// Timezone record dates are written in
+    pub timezone: Zone,
+    /// Only export real text: drop tapbacks, text-only reaction placeholders,
+    /// group events and messages without text
+    pub clean: bool,
+    /// Convert attachments copied to `attachments_dir` out of Apple-only formats
+    pub convert_media: Option<MediaConverter>,
+    /// Transcribe audio message attachments with this backend
+    pub transcribe_audio: Option<Transcriber>,
+    /// Fill in each image and video attachment's `thumbnail`
+    pub thumbnails: Option<Thumbnailer>,
+    /// Check each attachment's file is on disk and fill in its `status` and `sha256`
+    pub verify_attachments: Option<AttachmentVerifier>,
+    /// Only include messages sent over one of these services
+    pub services: Vec<Service>,
+    /// Write phone number handles in E.164 form
+    pub normalize_numbers: Option<NumberNormalizer>,
+    /// Fill in each record's `handle_info`, reading numbers without a country
+    /// code as this normalizer's region
+    pub enrich_handles: Option<NumberNormalizer>,
+    /// Names for phone numbers and emails, used ahead of the AddressBook
+    pub aliases: HashMap<String, String>,
+    /// Report each person under one canonical handle, however many phone
+    /// numbers and emails they message from
+    pub merge_handles: bool,
+    /// Put each person's one-on-one chats, like separate SMS and iMessage
+    /// chats with them, into one conversation under the lowest chat ROWID,
+    /// named by their canonical handle. Implies `merge_handles`.
+    pub merge_conversations: bool,
+    /// Pseudonymize handles and redact personal details from text
+    pub anonymize: Option<Anonymizer>,
+    /// Fill in each record's `links` from its text and link preview
+    pub extract_links: bool,
+    /// Fill in each record's `link_preview` from the Open Graph metadata
+    /// stored with it
+    pub link_previews: bool,
+    /// Stop after exporting this many messages. Messages then come in ROWID
+    /// order rather than date order, so the last one's id can be given as
+    /// `after_rowid` to get the next page.
+    pub limit: Option<usize>,
+    /// Decode message bodies on this many threads, each with its own
+    /// connection; 1 decodes them on the calling thread
+    pub threads: usize,
+    /// Drop copies of messages left by merged or restored backups: repeated
+    /// guids, and the same text from the same sender within this many seconds
+    pub dedupe: Option<u64>,
+    /// Only include messages with attachments
+    pub only_attachments: bool,
+    /// Only include messages with a photo, video or audio attachment,
+    /// not counting stickers
+    pub only_media: bool,
+    /// Only include messages whose text is nothing but emoji
+    pub only_emoji: bool,
+    /// Only include messages with at least this many characters of text
+    pub min_length: Option<usize>,
+    /// Only include received messages that haven't been read yet
+    pub unread: bool,
+    /// Export from a private copy of the database rather than the live one,
+    /// so Messages' locks can't block the export
+    pub snapshot: bool,
+    /// Also export messages in Recently Deleted or listed in `deleted_messages`,
+    /// and deleted messages still in the write-ahead log, marked `deleted`
+    pub include_deleted: bool,
+    /// Only export group events: name and photo changes, and people added,
+    /// removed or leaving
+    pub events_only: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            db_path: default_db_path(),
+            start_date: None,
+            end_date: None,
+            window: None,
+            only_from_me: false,
+            resolve_contacts: false,
+            from: Vec::new(),
+            to: Vec::new(),
+            with: Vec::new(),
+            chat_ids: Vec::new(),
+            attachments_dir: None,
+            reactions: ReactionMode::default(),
+            after_rowid: None,
+            search: None,
+            regex: None,
+            timezone: Zone::Local,
+            clean: false,
+            transcribe_audio: None,
+            thumbnails: None,
+            verify_attachments: None,
+            convert_media: None,
+            services: Vec::new(),
+            normalize_numbers: None,
+            enrich_handles: None,
+            aliases: HashMap::new(),
+            merge_handles: false,
+            merge_conversations: false,
+            anonymize: None,
+            extract_links: false,
+            link_previews: false,
+            limit: None,
+            threads: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            dedupe: None,
+            only_attachments: false,
+            only_media: false,
+            only_emoji: false,
+            min_length: None,
+            unread: false,
+            snapshot: false,
+            include_deleted: false,
+            events_only: false,
+        }
+    }
+}
+
+/// A single exported message
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct MessageRecord {
+    pub id: i64,
+    /// ISO-8601 with the offset of the export's timezone
+    pub date: DateTime<FixedOffset>,
+    pub text: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub from_me: bool,
+    /// `iMessage`, `SMS` or `RCS`
+    pub service: Option<String>,
+    /// The user's own number or email the message was sent from or received
+    /// at. With Messages in iCloud, this tells apart the accounts the same
+    /// conversation reaches each device on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    pub chat_id: Option<i32>,
+    pub chat_name: Option<String>,
+    /// Handles of everyone in the chat other than the user
+    pub participants: Vec<String>,
+    /// When the message was read, by the recipient for sent messages
+    pub date_read: Option<DateTime<FixedOffset>>,
+    pub date_delivered: Option<DateTime<FixedOffset>>,
+    /// When the message was last edited
+    pub date_edited: Option<DateTime<FixedOffset>>,
+    /// Each version of the message's edited text, the original first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schemars(default)]
+    pub edit_history: Vec<EditRecord>,
+    /// The message, or part of it, was unsent
+    pub was_unsent: bool,
+    pub is_read: bool,
+    /// The message this is an inline reply to: the previous message in its thread
+    pub reply_to_id: Option<i64>,
+    /// The message that started the thread this reply is in
+    pub thread_root_id: Option<i64>,
+    /// Bubble or screen effect it was sent with, like `slam` or `invisible ink`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effect: Option<String>,
+    /// A Digital Touch sketch, heartbeat or tap
+    pub is_digital_touch: bool,
+    /// A handwritten message
+    pub is_handwriting: bool,
+    /// What an iMessage app balloon like Apple Pay or a shared location carried
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_payload: Option<AppPayload>,
+    /// URLs in the text or link preview, with `--extract-links`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schemars(default)]
+    pub links: Vec<String>,
+    /// The title, description and image of a shared link, as Messages
+    /// stored them, with `--link-previews`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link_preview: Option<LinkPreview>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schemars(default)]
+    pub attachments: Vec<AttachmentRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schemars(default)]
+    pub reactions: Vec<ReactionRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to_name: Option<String>,
+    /// The country and line type of `from`, `to` and each participant, by
+    /// handle, with `--enrich-handles`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[schemars(default)]
+    pub handle_info: BTreeMap<String, NumberInfo>,
+    /// Deleted by the user, recovered with `--include-deleted`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    #[schemars(default)]
+    pub deleted: bool,
+    /// What a group event did, for the messages that record them. Their
+    /// `text` describes the event, as it would follow the sender's name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_type: Option<EventType>,
+    /// Who a `participant_added` or `participant_removed` event added or removed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_participant: Option<String>,
+    /// The chat's new name, for a `name_changed` event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_name: Option<String>,
+}
+
+impl MessageRecord {
+    /// Names of the serialized fields, for selecting output columns
+    pub const FIELDS: &'static [&'static str] = &[
+        "id", "date", "text", "from", "to", "from_me", "service", "account", "chat_id", "chat_name", "participants",
+        "date_read", "date_delivered", "date_edited", "edit_history", "was_unsent", "is_read", "reply_to_id",
+        "thread_root_id", "effect", "is_digital_touch", "is_handwriting", "app_payload", "links", "link_preview",
+        "attachments", "reactions", "from_name", "to_name", "handle_info", "deleted", "event_type",
+        "event_participant", "event_name",
+    ];
+}
+
+/// Iterates over the messages in chat.db that match an `ExportOptions`
+///
+/// Rows are read a page at a time, so memory use stays flat regardless of
+/// the size of the database.
+pub struct MessageExporter {
+    db: Connection,
+    db_path: PathBuf,
+    platform: Platform,
+    handles: HashMap<i32, String>,
+    chats: HashMap<i32, Chat>,
+    chat_participants: HashMap<i32, BTreeSet<i32>>,
+    /// Chats merged into another, by ROWID, to the one they're merged into
+    /// and its name
+    merged_chats: HashMap<i32, (i32, String)>,
+    contacts: Option<ContactBook>,
+    reaction_mode: ReactionMode,
+    tapbacks: HashMap<String, HashMap<usize, Vec<Message>>>,
+    from_keys: Vec<String>,
+    to_keys: Vec<String>,
+    copier: Option<AttachmentCopier>,
+    converter: Option<MediaConverter>,
+    search: Option<String>,
+    regex: Option<Regex>,
+    timezone: Zone,
+    window: Option<TimeWindow>,
+    clean: bool,
+    events_only: bool,
+    only_emoji: bool,
+    min_length: Option<usize>,
+    transcriber: Option<Transcriber>,
+    thumbnailer: Option<Thumbnailer>,
+    verifier: Option<AttachmentVerifier>,
+    normalizer: Option<NumberNormalizer>,
+    enricher: Option<NumberNormalizer>,
+    anonymizer: Option<Anonymizer>,
+    links: Option<LinkExtractor>,
+    link_previews: bool,
+    deduplicator: Option<Deduplicator>,
+    /// Alias names by handle key
+    aliases: HashMap<String, String>,
+    /// GUIDs in `deleted_messages`, with `--include-deleted`
+    deleted_guids: HashSet<String>,
+    /// Merged in date order when there's more than one
+    page_queries: Vec<PageQuery>,
+    filters: Filters,
+    cursor: (i64, i32),
+    /// Connections for decoding message bodies in parallel; empty when decoding on this thread
+    workers: Vec<Connection>,
+    /// Rows read but not yet returned, with their decoded text
+    pending: VecDeque<(Message, Option<String>)>,
+    /// Deleted messages recovered from the write-ahead log, returned after the rest
+    carved: VecDeque<CarvedMessage>,
+    exhausted: bool,
+    last_seen: Option<(i32, i64)>,
+    rows_read: u64,
+    limit: Option<usize>,
+    exported: usize,
+    /// Kept until the connections to it are closed, then deleted
+    _snapshot: Option<Snapshot>,
+}
+
+/// The epoch iMessage dates are counted from
+pub fn imessage_epoch() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2001, 1, 1, 0, 0, 0).unwrap()
+}
+
+/// chat.db timestamps nearer 0 than this are in seconds, as macOS before High
+/// Sierra wrote them, rather than nanoseconds. That's the year 2293 in seconds,
+/// and under ten seconds past the epoch in nanoseconds, so no real date is
+/// both, and it's the most seconds that fit in an `i64` of nanoseconds.
+pub const SECONDS_LIMIT: i64 = i64::MAX / 1_000_000_000;
+
+/// A chat.db timestamp in nanoseconds, whichever unit the row was written in.
+/// Databases migrated from older Macs mix the two.
+pub fn imessage_ns(raw: i64) -> i64 {
+    if -SECONDS_LIMIT < raw && raw < SECONDS_LIMIT {
+        raw * 1_000_000_000
+    } else {
+        raw
+    }
+}
+
+/// Convert a chat.db timestamp, in seconds or nanoseconds, to a time
+pub fn from_imessage_ns(raw: i64) -> DateTime<Utc> {
+    imessage_epoch() + Duration::nanoseconds(imessage_ns(raw))
+}
+
+/// Convert a time to the nanoseconds-since-2001 representation used in chat.db
+pub fn to_imessage_ns(date: DateTime<Utc>) -> i64 {
+    (date - imessage_epoch()).num_nanoseconds().unwrap_or(0)
+}
+
+/// The name of a message's send effect, or the raw effect ID for ones we don't know
+fn effect_name(expressive: &Expressive) -> Option<String> {
+    let name = match expressive {
+        Expressive::None => return None,
+        Expressive::Unknown(id) => return Some(id.to_string()),
+        Expressive::Bubble(effect) => match effect {
+            BubbleEffect::Slam => "slam",
+            BubbleEffect::Loud => "loud",
+            BubbleEffect::Gentle => "gentle",
+            BubbleEffect::InvisibleInk => "invisible ink",
+        },
+        Expressive::Screen(effect) => match effect {
+            ScreenEffect::Confetti => "confetti",
+            ScreenEffect::Echo => "echo",
+            ScreenEffect::Fireworks => "fireworks",
+            ScreenEffect::Balloons => "balloons",
+            ScreenEffect::Heart => "heart",
+            ScreenEffect::Lasers => "lasers",
+            ScreenEffect::ShootingStar => "shooting star",
+            ScreenEffect::Sparkles => "sparkles",
+            ScreenEffect::Spotlight => "spotlight",
+        },
+    };
+    Some(name.to_string())
+}
+
+/// Whether `handle` is one of `keys`, or `keys` is empty
+fn matches_any(handle: &Option<String>, keys: &[String]) -> bool {
+    keys.is_empty() || handle.as_deref().is_some_and(|h| keys.contains(&handle_key(h)))
+}
+
+/// Locate the messages database under `db_path`, which is either chat.db
+/// itself or an iOS backup directory holding it under its hashed name
+pub fn database_file(db_path: &Path) -> Result<(PathBuf, Platform), AppError> {
+    let platform = Platform::determine(db_path)?;
+    let file = match platform {
+        Platform::iOS => db_path.join(DEFAULT_PATH_IOS),
+        Platform::macOS => db_path.to_path_buf(),
+    };
+    Ok((file, platform))
+}
+
+/// The write-ahead log beside a database, where SQLite keeps recent writes
+/// until they're checkpointed into the database itself
+pub fn wal_file(db_file: &Path) -> PathBuf {
+    let mut wal = db_file.as_os_str().to_owned();
+    wal.push("-wal");
+    PathBuf::from(wal)
+}
+
+/// Whether a database that couldn't be read has a write-ahead log that's the
+/// trouble: SQLite can only read a log through its `-shm` index, and can't
+/// make one for a read-only connection in a folder it can't write to, like a
+/// Time Machine backup. The database itself is still readable.
+fn wal_unreadable(db_file: &Path) -> bool {
+    let mut shm = db_file.as_os_str().to_owned();
+    shm.push("-shm");
+    fs::metadata(wal_file(db_file)).is_ok_and(|m| m.len() > 0)
+        && !Path::new(&shm).exists()
+        && fs::File::open(db_file).is_ok()
+}
+
+/// Open the messages database read-only, reporting a missing Full Disk Access
+/// grant as [`AppError::FullDiskAccess`] rather than a generic database error,
+/// and a write-ahead log that can't be read in place as
+/// [`AppError::WalUnreadable`]
+pub fn open_database(db_path: &Path) -> Result<(Connection, Platform), AppError> {
+    let denied = || AppError::FullDiskAccess(db_path.to_path_buf());
+    // Without access even stat fails, which get_connection reports as "does not exist"
+    if let Err(e) = fs::metadata(db_path) {
+        if e.kind() == ErrorKind::PermissionDenied {
+            return Err(denied());
+        }
+    }
+
+    let (db_file, platform) = database_file(db_path)?;
+    let db = match get_connection(&db_file) {
+        Err(TableError::CannotConnect(TableConnectError::Permissions(_))) => return Err(denied()),
+        other => other?,
+    };
+    // Opening is lazy, so touch the file to surface permission errors now
+    match db.query_row("SELECT 1 FROM sqlite_master LIMIT 1", [], |_| Ok(())) {
+        Err(_) if wal_unreadable(&db_file) => Err(AppError::WalUnreadable(db_file)),
+        Err(e) if is_permission_error(&e) => Err(denied()),
+        _ => Ok((db, platform)),
+    }
+}
+
+impl MessageExporter {
+    pub fn new(options: ExportOptions) -> Result<Self, AppError> {
+        let started = Instant::now();
+        let (db, platform, snapshot) = match open_database(&options.db_path) {
+            Ok((db, platform)) if options.snapshot => {
+                let snapshot = Snapshot::take(&db)?;
+                (snapshot.open()?, platform, Some(snapshot))
+            }
+            Ok((db, platform)) => (db, platform, None),
+            // Recent messages may only be in the log, so export from a copy
+            // with the log checkpointed into it rather than without them
+            Err(AppError::WalUnreadable(db_file)) => {
+                warn!(path = %db_file.display(), "can't read the write-ahead log in place; exporting from a copy");
+                let snapshot = Snapshot::checkpointed(&db_file)?;
+                (snapshot.open()?, database_file(&options.db_path)?.1, Some(snapshot))
+            }
+            Err(e) => return Err(e),
+        };
+        info!(path = %options.db_path.display(), ?platform, "opened database");
+
+        // Build handle map at the start
+        let mut handles = HashMap::new();
+        let mut person_ids = HashMap::new();
+        let mut handle_stmt = Handle::get(&db)?;
+        let handles_iter = handle_stmt.query_map([], |row| Ok(Handle::from_row(row)))?;
+        for handle in handles_iter.flatten().flatten() {
+            handles.insert(handle.rowid, handle.id);
+            person_ids.insert(handle.rowid, handle.person_centric_id);
+        }
+        drop(handle_stmt);
+
+        let workers = if options.threads > 1 {
+            (0..options.threads)
+                .map(|_| match &snapshot {
+                    Some(snapshot) => snapshot.open(),
+                    None => open_database(&options.db_path).map(|(db, _)| db),
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            Vec::new()
+        };
+
+        let chats = Chat::cache(&db)?;
+        let chat_participants = ChatToHandle::cache(&db)?;
+        info!(handles = handles.len(), chats = chats.len(), elapsed = ?started.elapsed(), "loaded handles and chats");
+
+        let contacts = if options.resolve_contacts {
+            let contacts = ContactBook::load()?;
+            info!(contacts = contacts.len(), "loaded contacts");
+            Some(contacts)
+        } else {
+            None
+        };
+
+        let reaction_mode = if options.clean { ReactionMode::Exclude } else { options.reactions };
+        let tapbacks = match reaction_mode {
+            ReactionMode::Exclude => HashMap::new(),
+            _ => Message::cache(&db)?,
+        };
+        info!(messages = tapbacks.len(), elapsed = ?started.elapsed(), "loaded tapbacks");
+
+        let copier = options
+            .attachments_dir
+            .as_deref()
+            .map(AttachmentCopier::new)
+            .transpose()?;
+
+        let regex = options
+            .regex
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| AppError::Args(format!("Invalid regex: {}", e)))?;
+
+        let recoverable = query::has_table(&db, RECENTLY_DELETED)?;
+        let tombstones = query::has_table(&db, DELETED_MESSAGES)?;
+        let filters = Self::build_filters(&options, &handles, recoverable, tombstones);
+        let deleted_guids = if options.include_deleted && tombstones {
+            query::deleted_guids(&db)?
+        } else {
+            HashSet::new()
+        };
+        let carved = if options.include_deleted {
+            let (db_file, _) = database_file(&options.db_path)?;
+            Self::filter_carved(&options, &handles, recover::carve_wal(&db_file, &db)?)
+        } else {
+            VecDeque::new()
+        };
+        // Only now, so the filters above matched the handles as stored
+        if let Some(normalizer) = &options.normalize_numbers {
+            for handle in handles.values_mut() {
+                *handle = normalizer.normalize(handle);
+            }
+        }
+        let merger = if options.merge_handles || options.merge_conversations {
+            Some(Self::merge_handles(&mut handles, &person_ids, contacts.as_ref()))
+        } else {
+            None
+        };
+        let merged_chats = if options.merge_conversations {
+            Self::merge_conversations(&chat_participants, &handles)
+        } else {
+            HashMap::new()
+        };
+        let filter_key = |handle: &String| match &merger {
+            Some(merger) => handle_key(&merger.canonical(handle)),
+            None => handle_key(handle),
+        };
+        let page_queries = query::message_pages(&query::message_head(&db)?, &filters, options.limit.is_some());
+        debug!(query = %page_queries[0].sql, "built message query");
+
+        Ok(MessageExporter {
+            db,
+            db_path: options.db_path.clone(),
+            platform,
+            handles,
+            chats,
+            chat_participants,
+            merged_chats,
+            contacts,
+            reaction_mode,
+            tapbacks,
+            from_keys: options.from.iter().map(filter_key).collect(),
+            to_keys: options.to.iter().map(filter_key).collect(),
+            copier,
+            converter: options.convert_media.clone(),
+            search: options.search.as_ref().map(|s| s.to_lowercase()),
+            timezone: options.timezone,
+            window: options.window.clone(),
+            clean: options.clean,
+            events_only: options.events_only,
+            only_emoji: options.only_emoji,
+            min_length: options.min_length,
+            transcriber: options.transcribe_audio.clone(),
+            thumbnailer: options.thumbnails.clone(),
+            verifier: options.verify_attachments.clone(),
+            normalizer: options.normalize_numbers,
+            enricher: options.enrich_handles,
+            anonymizer: options.anonymize.clone(),
+            links: options.extract_links.then(LinkExtractor::default),
+            link_previews: options.link_previews,
+            deduplicator: options.dedupe.map(Deduplicator::new),
+            aliases: options.aliases.iter().map(|(handle, name)| (handle_key(handle), name.clone())).collect(),
+            deleted_guids,
+            regex,
+            page_queries,
+            filters,
+            cursor: (i64::MIN, 0),
+            rows_read: 0,
+            limit: options.limit,
+            exported: 0,
+            workers,
+            pending: VecDeque::new(),
+            carved,
+            exhausted: false,
+            last_seen: None,
+            _snapshot: snapshot,
+        })
+    }
+
+    /// Rewrite each handle to the canonical one for its person, linking
+    /// aliases through chat.db and the AddressBook
+    fn merge_handles(
+        handles: &mut HashMap<i32, String>,
+        person_ids: &HashMap<i32, Option<String>>,
+        contacts: Option<&ContactBook>,
+    ) -> HandleMerger {
+        // The AddressBook links aliases even when names weren't asked for
+        let loaded;
+        let contacts = match contacts {
+            Some(contacts) => Some(contacts),
+            None => match ContactBook::load() {
+                Ok(book) => {
+                    loaded = book;
+                    Some(&loaded)
+                }
+                Err(e) => {
+                    warn!(error = %e, "couldn't read the AddressBook, merging handles by chat.db alone");
+                    None
+                }
+            },
+        };
+        let infos: Vec<HandleInfo> = handles
+            .iter()
+            .map(|(rowid, id)| HandleInfo {
+                rowid: *rowid,
+                id: id.clone(),
+                person_centric_id: person_ids.get(rowid).cloned().flatten(),
+            })
+            .collect();
+        let merger = HandleMerger::new(&infos, contacts);
+        for handle in handles.values_mut() {
+            *handle = merger.canonical(handle);
+        }
+        info!(handles = handles.len(), "merged handles");
+        merger
+    }
+
+    /// Group one-on-one chats by the canonical handle of the person they're
+    /// with, mapping every chat in a group of more than one to the group's
+    /// lowest ROWID
+    fn merge_conversations(
+        chat_participants: &HashMap<i32, BTreeSet<i32>>,
+        handles: &HashMap<i32, String>,
+    ) -> HashMap<i32, (i32, String)> {
+        let mut by_person: HashMap<String, (String, Vec<i32>)> = HashMap::new();
+        for (chat_id, participants) in chat_participants {
+            let [participant] = participants.iter().collect::<Vec<_>>()[..] else {
+                continue;
+            };
+            let Some(handle) = handles.get(participant) else {
+                continue;
+            };
+            let (_, chats) = by_person.entry(handle_key(handle)).or_insert_with(|| (handle.clone(), Vec::new()));
+            chats.push(*chat_id);
+        }
+        let mut merged = HashMap::new();
+        for (handle, chats) in by_person.into_values().filter(|(_, chats)| chats.len() > 1) {
+            let into = *chats.iter().min().expect("groups aren't empty");
+            debug!(%handle, ?chats, into, "merged conversations");
+            merged.extend(chats.into_iter().map(|id| (id, (into, handle.clone()))));
+        }
+        info!(chats = merged.len(), "merged conversations");
+        merged
+    }
+
+    /// Translate the export options into SQL predicates so SQLite can skip
+    /// rows before they are decoded
+    fn build_filters(
+        options: &ExportOptions,
+        handles: &HashMap<i32, String>,
+        recoverable: bool,
+        tombstones: bool,
+    ) -> Filters {
+        let mut filters = Filters::default();
+        // Messages in Recently Deleted are out of their chats, but still in the message table
+        if recoverable && !options.include_deleted {
+            filters.push(format!("NOT EXISTS (SELECT 1 FROM {RECENTLY_DELETED} r WHERE r.message_id = m.ROWID)"), []);
+        }
+        // deleted_messages lists the GUIDs of messages deleted on any device,
+        // for syncing, which can outlive the rows themselves for a while
+        if tombstones && !options.include_deleted {
+            filters.push(format!("m.guid NOT IN (SELECT guid FROM {DELETED_MESSAGES})"), []);
+        }
+        if let Some(start) = options.start_date {
+            filters.push_date("m.date", ">=", to_imessage_ns(start));
+        }
+        if let Some(end) = options.end_date {
+            filters.push_date("m.date", "<=", to_imessage_ns(end));
+        }
+        if options.only_from_me {
+            filters.push("m.is_from_me = 1", []);
+        }
+        // Read on any device sets both; older rows can leave date_read NULL
+        if options.unread {
+            filters.push("m.is_from_me = 0 AND m.is_read = 0 AND COALESCE(m.date_read, 0) = 0", []);
+        }
+        // Renames, joins and leaves are the non-zero item types
+        if options.clean {
+            filters.push("m.item_type = 0", []);
+        }
+        // Group events are stored with these item types; see `GroupEvent`
+        if options.events_only {
+            filters.push("m.item_type IN (1, 2, 3)", []);
+        }
+        if options.only_attachments {
+            filters.push(format!("EXISTS (SELECT 1 FROM {MESSAGE_ATTACHMENT_JOIN} a WHERE a.message_id = m.ROWID)"), []);
+        }
+        if options.only_media {
+            filters.push(
+                format!(
+                    "EXISTS (SELECT 1 FROM {MESSAGE_ATTACHMENT_JOIN} j JOIN {ATTACHMENT} a ON a.ROWID = j.attachment_id
+                     WHERE j.message_id = m.ROWID AND a.is_sticker = 0
+                       AND (a.mime_type LIKE 'image/%' OR a.mime_type LIKE 'video/%' OR a.mime_type LIKE 'audio/%'))"
+                ),
+                [],
+            );
+        }
+        if !options.services.is_empty() {
+            let services = options.services.iter().map(|s| Value::Text(s.as_str().to_string())).collect();
+            filters.push_in("m.service COLLATE NOCASE", services);
+        }
+        if let Some(rowid) = options.after_rowid {
+            filters.push("m.ROWID > ?", [Value::Integer(rowid.into())]);
+        }
+        // SQLite's LIKE only folds ASCII case, so leave anything else to the Rust check
+        if let Some(search) = options.search.as_ref().filter(|s| s.is_ascii()) {
+            // Rows whose text only lives in attributedBody are checked after decoding
+            let escaped = search.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+            filters.push(
+                "(m.text IS NULL OR m.text LIKE ? ESCAPE '\\')",
+                [Value::Text(format!("%{}%", escaped))],
+            );
+        }
+        if !options.with.is_empty() {
+            let keys: Vec<String> = options.with.iter().map(|h| handle_key(h)).collect();
+            let ids = handles
+                .iter()
+                .filter(|(_, handle)| keys.contains(&handle_key(handle)))
+                .map(|(id, _)| Value::Integer((*id).into()))
+                .collect();
+            filters.push_in("m.handle_id", ids);
+        }
+        if !options.chat_ids.is_empty() {
+            let placeholders = vec!["?"; options.chat_ids.len()].join(", ");
+            let ids = options.chat_ids.iter().map(|id| Value::Integer((*id).into()));
+            // Recently Deleted keeps the chat a message was in in its own join table
+            if recoverable && options.include_deleted {
+                filters.push(
+                    format!(
+                        "m.ROWID IN (SELECT message_id FROM {CHAT_MESSAGE_JOIN} WHERE chat_id IN ({placeholders})
+                                     UNION SELECT message_id FROM {RECENTLY_DELETED} WHERE chat_id IN ({placeholders}))"
+                    ),
+                    ids.clone().chain(ids),
+                );
+            } else {
+                filters.push(
+                    format!("m.ROWID IN (SELECT message_id FROM {CHAT_MESSAGE_JOIN} WHERE chat_id IN ({placeholders}))"),
+                    ids,
+                );
+            }
+        }
+        filters
+    }
+
+    /// Keep the recovered messages the SQL filters would have matched. The
+    /// ones only checked in Rust are checked as they're returned.
+    fn filter_carved(
+        options: &ExportOptions,
+        handles: &HashMap<i32, String>,
+        carved: Vec<CarvedMessage>,
+    ) -> VecDeque<CarvedMessage> {
+        // What the log keeps of a row says nothing about these, nor which
+        // chat it was in, since its chat_message_join row is gone with it
+        if options.unread
+            || options.only_attachments
+            || options.only_media
+            || options.events_only
+            || !options.chat_ids.is_empty()
+        {
+            if !carved.is_empty() {
+                warn!(
+                    messages = carved.len(),
+                    "skipped deleted messages from the write-ahead log, which can't be filtered by chat, unread, attachments or event"
+                );
+            }
+            return VecDeque::new();
+        }
+        let with: Vec<String> = options.with.iter().map(|h| handle_key(h)).collect();
+        let mut carved: VecDeque<CarvedMessage> = carved
+            .into_iter()
+            .filter(|msg| {
+                let date = from_imessage_ns(msg.date);
+                options.start_date.is_none_or(|start| date >= start)
+                    && options.end_date.is_none_or(|end| date <= end)
+                    && (!options.only_from_me || msg.is_from_me)
+                    && options.after_rowid.is_none_or(|rowid| msg.rowid > i64::from(rowid))
+                    && (options.services.is_empty()
+                        || options.services.iter().any(|s| {
+                            msg.service.as_deref().is_some_and(|service| service.eq_ignore_ascii_case(s.as_str()))
+                        }))
+                    && (with.is_empty()
+                        || msg
+                            .handle_id
+                            .and_then(|id| handles.get(&(id as i32)))
+                            .is_some_and(|handle| with.contains(&handle_key(handle))))
+            })
+            .collect();
+        carved.make_contiguous().sort_by_key(|msg| (imessage_ns(msg.date), msg.rowid));
+        carved
+    }
+
+    /// The highest ROWID read so far and its date, whether or not that
+    /// message passed the filters
+    pub fn last_seen(&self) -> Option<(i32, DateTime<Utc>)> {
+        self.last_seen.map(|(rowid, date)| (rowid, from_imessage_ns(date)))
+    }
+
+    /// How many rows match the SQL filters, before the checks done in Rust,
+    /// for sizing a progress bar against [`rows_read`](Self::rows_read)
+    pub fn count_rows(&self) -> Result<u64, AppError> {
+        let count: i64 = self.db.query_row(
+            &query::message_count(&self.filters),
+            params_from_iter(self.filters.params()),
+            |row| row.get(0),
+        )?;
+        Ok(count as u64)
+    }
+
+    /// How many rows have been read so far, exported or not
+    pub fn rows_read(&self) -> u64 {
+        self.rows_read
+    }
+
+    /// Read the next page of rows into `pending`
+    fn fetch_page(&mut self) -> Result<(), AppError> {
+        let mut page = Vec::new();
+        for page_query in &self.page_queries {
+            let mut statement = self.db.prepare_cached(&page_query.sql)?;
+            let mut params = self.filters.params().to_vec();
+            params.extend(page_query.cursor_params(self.cursor));
+            params.push(Value::Integer(PAGE_SIZE));
+            let rows = statement.query_map(params_from_iter(params), |row| Ok(Message::from_row(row)))?;
+            for row in rows {
+                page.push(Message::extract(row)?);
+            }
+        }
+        if self.page_queries.len() > 1 {
+            // Each query's rows are in order; past the first page's worth, one
+            // query's rows could be after rows another hasn't read yet
+            page.sort_by_key(|msg| (imessage_ns(msg.date), msg.rowid));
+            page.truncate(PAGE_SIZE as usize);
+        }
+        if let Some(msg) = page.last() {
+            self.cursor = (imessage_ns(msg.date), msg.rowid);
+        }
+        let fetched = page.len();
+        if (fetched as i64) < PAGE_SIZE {
+            self.exhausted = true;
+        }
+        let texts = self.decode_texts(&mut page);
+        self.pending.extend(page.into_iter().zip(texts));
+        trace!(rows = fetched, "read page");
+        Ok(())
+    }
+
+    /// Decode the text of each message, splitting the page between the worker
+    /// connections. Each worker takes a contiguous run, so order is kept.
+    fn decode_texts(&mut self, page: &mut [Message]) -> Vec<Option<String>> {
+        if self.workers.is_empty() || page.len() < 2 {
+            return page.iter_mut().map(|msg| body::message_text(msg, &self.db)).collect();
+        }
+        let run = page.len().div_ceil(self.workers.len());
+        thread::scope(|scope| {
+            let decoders: Vec<_> = page
+                .chunks_mut(run)
+                .zip(self.workers.iter_mut())
+                .map(|(run, db)| {
+                    scope.spawn(move || run.iter_mut().map(|msg| body::message_text(msg, db)).collect::<Vec<_>>())
+                })
+                .collect();
+            decoders.into_iter().flat_map(|decoder| decoder.join().expect("decoder thread panicked")).collect()
+        })
+    }
+
+    /// Describe a message's attachments, copying them out if `--attachments-dir` is set
+    fn attachments(&mut self, msg: &Message) -> Result<Vec<AttachmentRecord>, AppError> {
+        let mut records = Vec::new();
+        for attachment in Attachment::from_message(&self.db, msg)? {
+            // Before copying, so a file downloaded from iCloud is copied too
+            let verification = self
+                .verifier
+                .as_ref()
+                .map(|verifier| verifier.verify(&self.db, &attachment, &self.platform, &self.db_path))
+                .transpose()?;
+            let mut record = attachments::to_record(
+                &attachment,
+                &self.platform,
+                &self.db_path,
+                self.copier.as_mut(),
+            )?;
+            // Only copies are converted, never the files in ~/Library/Messages
+            if let (Some(converter), Some(_), Some(path)) = (&self.converter, &self.copier, record.path.clone()) {
+                match converter.convert(Path::new(&path), record.mime_type.as_deref()) {
+                    Ok(Some(converted)) => {
+                        let extension = converted.path.extension().map(|e| e.to_os_string()).unwrap_or_default();
+                        record.filename = record
+                            .filename
+                            .map(|name| Path::new(&name).with_extension(&extension).to_string_lossy().into_owned());
+                        record.path = Some(converted.path.to_string_lossy().into_owned());
+                        record.original_path = converter.keeps_originals().then_some(path);
+                        record.mime_type = Some(converted.mime_type.to_string());
+                        record.size = converted.size as i64;
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!(rowid = msg.rowid, "couldn't convert {}: {}", path, e),
+                }
+            }
+            if let (Some(transcriber), Some(path)) = (&self.transcriber, &record.path) {
+                if transcribe::is_audio(record.mime_type.as_deref()) {
+                    // One bad recording shouldn't stop the export
+                    match transcriber.transcribe(Path::new(path)) {
+                        Ok(transcript) => record.transcript = transcript,
+                        Err(e) => warn!(rowid = msg.rowid, "couldn't transcribe {}: {}", path, e),
+                    }
+                }
+            }
+            if let (Some(thumbnailer), Some(path)) = (&self.thumbnailer, &record.path) {
+                match thumbnailer.thumbnail(Path::new(path), record.mime_type.as_deref()) {
+                    Ok(thumbnail) => record.thumbnail = thumbnail,
+                    Err(e) => warn!(rowid = msg.rowid, "couldn't make a thumbnail of {}: {}", path, e),
+                }
+            }
+            if let Some(Verification { status, sha256 }) = verification {
+                if !matches!(status, AttachmentStatus::Ok | AttachmentStatus::Downloaded) {
+                    let name = record.filename.as_deref().unwrap_or("attachment");
+                    warn!(rowid = msg.rowid, "{} is {}", name, status.describe());
+                }
+                record.status = Some(status);
+                record.sha256 = sha256;
+            }
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    /// Check decoded text against `--search` and `--regex`
+    fn text_matches(&self, text: Option<&str>) -> bool {
+        if self.search.is_none() && self.regex.is_none() {
+            return true;
+        }
+        let Some(text) = text else {
+            return false;
+        };
+        let search = self.search.as_ref().is_none_or(|s| text.to_lowercase().contains(s));
+        let regex = self.regex.as_ref().is_none_or(|r| r.is_match(text));
+        search && regex
+    }
+
+    /// Check text against `--only-emoji` and `--min-length`
+    fn text_fits(&self, text: Option<&str>) -> bool {
+        if !self.only_emoji && self.min_length.is_none() {
+            return true;
+        }
+        let Some(text) = text else {
+            return false;
+        };
+        let emoji = !self.only_emoji || analyze::is_only_emoji(text);
+        let length = self.min_length.is_none_or(|min| text.trim().chars().count() >= min);
+        emoji && length
+    }
+
+    /// The handle that sent a message
+    fn sender(&self, msg: &Message) -> Option<String> {
+        if msg.is_from_me {
+            msg.destination_caller_id.clone()
+        } else {
+            msg.handle_id.and_then(|id| self.handles.get(&id).cloned())
+        }
+    }
+
+    /// The thread root and the previous message in the thread for an inline reply
+    fn thread_ids(&self, msg: &Message) -> Result<(Option<i64>, Option<i64>), AppError> {
+        let Some(root_guid) = &msg.thread_originator_guid else {
+            return Ok((None, None));
+        };
+        let root = self
+            .db
+            .prepare_cached("SELECT ROWID FROM message WHERE guid = ?1")?
+            .query_row([root_guid], |row| row.get(0))
+            .optional()?;
+        let previous = self
+            .db
+            .prepare_cached(
+                "SELECT ROWID FROM message
+                 WHERE (guid = ?1 OR thread_originator_guid = ?1)
+                   AND (date < ?2 OR (date = ?2 AND ROWID < ?3))
+                 ORDER BY date DESC, ROWID DESC
+                 LIMIT 1",
+            )?
+            .query_row(rusqlite::params![root_guid, msg.date, msg.rowid], |row| row.get(0))
+            .optional()?;
+        Ok((previous.or(root), root))
+    }
+
+    /// Describe the handles in a record for `handle_info`, if asked to
+    fn handle_info(&self, record: &MessageRecord) -> BTreeMap<String, NumberInfo> {
+        let Some(enricher) = &self.enricher else {
+            return BTreeMap::new();
+        };
+        let handles = record.from.iter().chain(&record.to).chain(&record.participants);
+        handles.map(|handle| (handle.clone(), enricher.describe(handle))).collect()
+    }
+
+    /// Whether a message sent at `date` is in the `--between`/`--days` window
+    fn in_window(&self, date: DateTime<FixedOffset>) -> bool {
+        self.window.as_ref().is_none_or(|window| window.contains(date))
+    }
+
+    /// Localize an iMessage date column, where 0 means it never happened
+    fn optional_date(&self, ns: i64) -> Option<DateTime<FixedOffset>> {
+        (ns != 0).then(|| self.timezone.localize(from_imessage_ns(ns)))
+    }
+
+    /// Convert a message to a record, or `None` if it is filtered out
+    fn build_record(&mut self, msg: Message, text: Option<String>) -> Result<Option<MessageRecord>, AppError> {
+        if msg.is_tapback() && self.reaction_mode != ReactionMode::Include {
+            debug!(rowid = msg.rowid, "skipped: tapback");
+            return Ok(None);
+        }
+
+        let event = GroupEvent::from_message(&msg, |id| self.handles.get(&id).cloned());
+        if self.events_only && event.is_none() {
+            debug!(rowid = msg.rowid, "skipped: not a group event (--events-only)");
+            return Ok(None);
+        }
+
+        // Messages with neither text nor attachments have nothing to export
+        let app_payload = balloon::decode(&msg, &self.db);
+        let text = text
+            .or_else(|| app_payload.as_ref().and_then(AppPayload::summary))
+            .or_else(|| event.as_ref().map(GroupEvent::describe));
+        // An unsent message is empty, but worth keeping as a record that there was one
+        let was_unsent = edits::was_unsent(msg.edited_parts.as_ref());
+        if text.is_none() && !msg.has_attachments() && !was_unsent {
+            debug!(rowid = msg.rowid, "skipped: no text or attachments");
+            return Ok(None);
+        }
+        if !self.text_matches(text.as_deref()) {
+            debug!(rowid = msg.rowid, "skipped: doesn't match --search/--regex");
+            return Ok(None);
+        }
+        if self.clean && text.as_deref().is_none_or(body::is_reaction_placeholder) {
+            debug!(rowid = msg.rowid, "skipped: not real text (--clean)");
+            return Ok(None);
+        }
+        if !self.text_fits(text.as_deref()) {
+            debug!(rowid = msg.rowid, "skipped: doesn't fit --only-emoji/--min-length");
+            return Ok(None);
+        }
+
+        let message_date = self.timezone.localize(from_imessage_ns(msg.date));
+        if !self.in_window(message_date) {
+            debug!(rowid = msg.rowid, "skipped: outside --between/--days");
+            return Ok(None);
+        }
+
+        let participants: Vec<String> = msg
+            .chat_id
+            .and_then(|id| self.chat_participants.get(&id))
+            .map(|ids| ids.iter().filter_map(|id| self.handles.get(id).cloned()).collect())
+            .unwrap_or_default();
+        let is_group = participants.len() > 1;
+
+        // Get the actual phone numbers using the handle map
+        let handle = msg.handle_id.and_then(|id| self.handles.get(&id).cloned());
+        let own_handle = msg.destination_caller_id.as_deref().map(|h| match &self.normalizer {
+            Some(normalizer) => normalizer.normalize(h),
+            None => h.to_string(),
+        });
+        let account = own_handle.clone();
+        let (from, to) = if msg.is_from_me { (own_handle, handle) } else { (handle, own_handle) };
+        // A group message isn't addressed to any one handle, see `participants`
+        let to = if is_group { None } else { to };
+
+        if !matches_any(&from, &self.from_keys) || !matches_any(&to, &self.to_keys) {
+            debug!(rowid = msg.rowid, "skipped: doesn't match --from/--to");
+            return Ok(None);
+        }
+
+        let attachments = self.attachments(&msg)?;
+        let reactions = self
+            .tapbacks
+            .get(&msg.guid)
+            .map(|tapbacks| {
+                let date_of = |ns| self.timezone.localize(from_imessage_ns(ns));
+                reactions::collect_reactions(tapbacks, date_of, |m| self.sender(m))
+            })
+            .unwrap_or_default();
+
+        let (reply_to_id, thread_root_id) = self.thread_ids(&msg)?;
+        let edit_history =
+            edits::edit_history(msg.edited_parts.as_ref(), |ns| self.timezone.localize(from_imessage_ns(ns)));
+        let preview = if self.links.is_some() || self.link_previews { links::preview(&msg, &self.db) } else { None };
+        let links = self
+            .links
+            .as_ref()
+            .map(|links| links.in_message(text.as_deref(), preview.as_ref()))
+            .unwrap_or_default();
+        // A preview Messages never loaded has nothing to tell beyond the URL
+        let link_preview = preview.filter(|p| self.link_previews && (p.title.is_some() || p.summary.is_some()));
+
+        let name_for = |handle: &Option<String>| {
+            let handle = handle.as_deref()?;
+            if let Some(alias) = self.aliases.get(&handle_key(handle)) {
+                return Some(alias.clone());
+            }
+            self.contacts.as_ref()?.name_for(handle).map(String::from)
+        };
+
+        let deleted = msg.deleted_from.is_some() || self.deleted_guids.contains(&msg.guid);
+        // A message in Recently Deleted has left its chat, but remembers it
+        let chat_id = msg.chat_id.or(msg.deleted_from);
+        let (chat_id, chat_name) = match chat_id.and_then(|id| self.merged_chats.get(&id)) {
+            Some((into, handle)) => (Some(*into), Some(handle.clone())),
+            None => (chat_id, chat_id.and_then(|id| self.chats.get(&id)).map(|chat| chat.name().to_string())),
+        };
+        let mut record = MessageRecord {
+            id: msg.rowid as i64,
+            date: message_date,
+            text,
+            from_name: name_for(&from),
+            to_name: name_for(&to),
+            from,
+            to,
+            from_me: msg.is_from_me,
+            service: msg.service.clone(),
+            account,
+            chat_id,
+            chat_name,
+            participants,
+            date_read: self.optional_date(msg.date_read),
+            date_delivered: self.optional_date(msg.date_delivered),
+            date_edited: self.optional_date(msg.date_edited),
+            edit_history,
+            was_unsent,
+            is_read: msg.is_read,
+            reply_to_id,
+            thread_root_id,
+            effect: effect_name(&msg.get_expressive()),
+            is_digital_touch: msg.is_digital_touch(),
+            is_handwriting: msg.is_handwriting(),
+            app_payload,
+            links,
+            link_preview,
+            attachments,
+            reactions,
+            handle_info: BTreeMap::new(),
+            deleted,
+            event_type: event.as_ref().map(|e| e.event_type),
+            event_participant: event.as_ref().and_then(|e| e.participant.clone()),
+            event_name: event.and_then(|e| e.name),
+        };
+        record.handle_info = self.handle_info(&record);
+        if let Some(anonymizer) = &self.anonymizer {
+            anonymizer.apply(&mut record);
+        }
+        Ok(Some(record))
+    }
+
+    /// Convert a message recovered from the write-ahead log to a record, or
+    /// `None` if it is filtered out. Only what the message row itself holds
+    /// is known; its chat, attachments and reactions are gone.
+    fn carved_record(&self, msg: CarvedMessage) -> Option<MessageRecord> {
+        let text = msg.text.filter(|t| !t.trim().is_empty())?;
+        if !self.text_matches(Some(&text)) {
+            debug!(rowid = msg.rowid, "skipped: doesn't match --search/--regex");
+            return None;
+        }
+        if self.clean && body::is_reaction_placeholder(&text) {
+            return None;
+        }
+        if !self.text_fits(Some(&text)) {
+            return None;
+        }
+        let date = self.timezone.localize(from_imessage_ns(msg.date));
+        if !self.in_window(date) {
+            return None;
+        }
+        let handle = msg.handle_id.and_then(|id| self.handles.get(&(id as i32)).cloned());
+        let own_handle = msg.destination_caller_id.as_deref().map(|h| match &self.normalizer {
+            Some(normalizer) => normalizer.normalize(h),
+            None => h.to_string(),
+        });
+        let account = own_handle.clone();
+        let (from, to) = if msg.is_from_me { (own_handle, handle) } else { (handle, own_handle) };
+        if !matches_any(&from, &self.from_keys) || !matches_any(&to, &self.to_keys) {
+            debug!(rowid = msg.rowid, "skipped: doesn't match --from/--to");
+            return None;
+        }
+        let name_for = |handle: &Option<String>| {
+            let handle = handle.as_deref()?;
+            if let Some(alias) = self.aliases.get(&handle_key(handle)) {
+                return Some(alias.clone());
+            }
+            self.contacts.as_ref()?.name_for(handle).map(String::from)
+        };
+        let mut record = MessageRecord {
+            id: msg.rowid,
+            date,
+            links: self.links.as_ref().map(|links| links.in_text(&text)).unwrap_or_default(),
+            text: Some(text),
+            from_name: name_for(&from),
+            to_name: name_for(&to),
+            from,
+            to,
+            from_me: msg.is_from_me,
+            service: msg.service,
+            account,
+            chat_id: None,
+            chat_name: None,
+            participants: Vec::new(),
+            date_read: None,
+            date_delivered: None,
+            date_edited: None,
+            edit_history: Vec::new(),
+            was_unsent: false,
+            is_read: false,
+            reply_to_id: None,
+            thread_root_id: None,
+            effect: None,
+            is_digital_touch: false,
+            is_handwriting: false,
+            app_payload: None,
+            link_preview: None,
+            attachments: Vec::new(),
+            reactions: Vec::new(),
+            handle_info: BTreeMap::new(),
+            deleted: true,
+            event_type: None,
+            event_participant: None,
+            event_name: None,
+        };
+        record.handle_info = self.handle_info(&record);
+        if let Some(anonymizer) = &self.anonymizer {
+            anonymizer.apply(&mut record);
+        }
+        Some(record)
+    }
+}
+
+impl Iterator for MessageExporter {
+    type Item = Result<MessageRecord, AppError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.limit.is_some_and(|limit| self.exported >= limit) {
+            return None;
+        }
+        loop {
+            if let Some((msg, text)) = self.pending.pop_front() {
+                self.rows_read += 1;
+                // Tracked as rows are used rather than read, so a --limit doesn't skip the rest of a page
+                if self.last_seen.is_none_or(|(rowid, _)| msg.rowid > rowid) {
+                    self.last_seen = Some((msg.rowid, msg.date));
+                }
+                if let Some(deduplicator) = &mut self.deduplicator {
+                    let conversation = msg.chat_id.and_then(|id| self.chats.get(&id)).map(|c| c.chat_identifier.as_str());
+                    if deduplicator.is_duplicate(&msg, text.as_deref(), conversation) {
+                        debug!(rowid = msg.rowid, "skipped: duplicate (--dedupe)");
+                        continue;
+                    }
+                }
+                match self.build_record(msg, text) {
+                    Ok(Some(record)) => {
+                        self.exported += 1;
+                        return Some(Ok(record));
+                    }
+                    Ok(None) => continue,
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            if self.exhausted {
+                let msg = self.carved.pop_front()?;
+                if let Some(record) = self.carved_record(msg) {
+                    self.exported += 1;
+                    return Some(Ok(record));
+                }
+                continue;
+            }
+            if let Err(e) = self.fetch_page() {
+                self.exhausted = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::Service;
+
+    fn database() -> Connection {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(&format!(
+            "CREATE TABLE message (
+                 ROWID INTEGER PRIMARY KEY, guid TEXT, date INTEGER, is_from_me INTEGER, is_read INTEGER,
+                 date_read INTEGER, item_type INTEGER, service TEXT, handle_id INTEGER, text TEXT
+             );
+             CREATE TABLE {CHAT_MESSAGE_JOIN} (chat_id INTEGER, message_id INTEGER);
+             CREATE TABLE {MESSAGE_ATTACHMENT_JOIN} (message_id INTEGER, attachment_id INTEGER);
+             CREATE TABLE {RECENTLY_DELETED} (chat_id INTEGER, message_id INTEGER);
+             CREATE TABLE {DELETED_MESSAGES} (guid TEXT);
+             -- 2023-01-01, 2023-01-02 stored in seconds, then 2024-01-02 a nanosecond apart
+             INSERT INTO message VALUES (1, 'a', 694224000000000000, 1, 1, 694224000000000000, 0, 'iMessage', 0, 'hi');
+             INSERT INTO message VALUES (2, 'b', 694310400, 0, 0, 0, 0, 'SMS', 1, 'Hello');
+             INSERT INTO message VALUES (3, 'c', 725846400000000000, 0, 1, NULL, 0, 'imessage', 2, '50% off');
+             INSERT INTO message VALUES (4, 'd', 725846400000000001, 0, 0, NULL, 2, 'iMessage', 2, NULL);
+             INSERT INTO message VALUES (5, 'e', 725846400000000002, 0, 0, NULL, 0, 'RCS', 1, 'in the bin');
+             INSERT INTO {CHAT_MESSAGE_JOIN} VALUES (7, 1), (7, 2), (8, 3), (8, 4);
+             INSERT INTO {RECENTLY_DELETED} VALUES (7, 5);
+             INSERT INTO {DELETED_MESSAGES} VALUES ('c');"
+        ))
+        .unwrap();
+        db
+    }
+
+    fn handles() -> HashMap<i32, String> {
+        HashMap::from([(1, "+1 (555) 010-0001".to_string()), (2, "Friend@Example.com".to_string())])
+    }
+
+    /// The ROWIDs `options` selects, with the recoverable and tombstone tables present or not
+    fn matching(options: ExportOptions, recoverable: bool, tombstones: bool) -> Vec<i64> {
+        let db = database();
+        let filters = MessageExporter::build_filters(&options, &handles(), recoverable, tombstones);
+        let sql = query::message_count(&filters).replace("COUNT(*)", "m.ROWID") + " ORDER BY m.ROWID";
+        let mut statement = db.prepare(&sql).unwrap();
+        let rows = statement.query_map(params_from_iter(filters.params()), |row| row.get(0)).unwrap();
+        rows.map(Result::unwrap).collect()
+    }
+
+    fn date(s: &str) -> Option<DateTime<Utc>> {
+        Some(DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc))
+    }
+
+    #[test]
+    fn no_options_match_everything_but_deleted() {
+        assert_eq!(matching(ExportOptions::default(), false, false), [1, 2, 3, 4, 5]);
+        assert_eq!(matching(ExportOptions::default(), true, true), [1, 2, 4]);
+        let options = ExportOptions { include_deleted: true, ..Default::default() };
+        assert_eq!(matching(options, true, true), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn dates_compare_seconds_and_nanoseconds() {
+        let options = ExportOptions { end_date: date("2023-01-02T00:00:00Z"), ..Default::default() };
+        assert_eq!(matching(options, false, false), [1, 2]);
+        let options = ExportOptions { start_date: date("2023-01-02T00:00:00Z"), ..Default::default() };
+        assert_eq!(matching(options, false, false), [2, 3, 4, 5]);
+        let options = ExportOptions {
+            start_date: date("2024-01-02T00:00:00Z"),
+            end_date: date("2024-01-02T00:00:00.000000001Z"),
+            ..Default::default()
+        };
+        assert_eq!(matching(options, false, false), [3, 4]);
+    }
+
+    #[test]
+    fn flags_filter_in_sql() {
+        let options = ExportOptions { only_from_me: true, ..Default::default() };
+        assert_eq!(matching(options, false, false), [1]);
+        let options = ExportOptions { unread: true, ..Default::default() };
+        assert_eq!(matching(options, false, false), [2, 4, 5]);
+        let options = ExportOptions { clean: true, ..Default::default() };
+        assert_eq!(matching(options, false, false), [1, 2, 3, 5]);
+        let options = ExportOptions { events_only: true, ..Default::default() };
+        assert_eq!(matching(options, false, false), [4]);
+        let options = ExportOptions { after_rowid: Some(3), ..Default::default() };
+        assert_eq!(matching(options, false, false), [4, 5]);
+    }
+
+    #[test]
+    fn services_ignore_case() {
+        let options = ExportOptions { services: vec![Service::IMessage], ..Default::default() };
+        assert_eq!(matching(options, false, false), [1, 3, 4]);
+        let options = ExportOptions { services: vec![Service::Sms, Service::Rcs], ..Default::default() };
+        assert_eq!(matching(options, false, false), [2, 5]);
+    }
+
+    #[test]
+    fn search_escapes_like_wildcards() {
+        let options = ExportOptions { search: Some("HELLO".to_string()), ..Default::default() };
+        assert_eq!(matching(options, false, false), [2, 4]);
+        let options = ExportOptions { search: Some("0%".to_string()), ..Default::default() };
+        assert_eq!(matching(options, false, false), [3, 4]);
+    }
+
+    #[test]
+    fn with_matches_normalized_handles() {
+        let options = ExportOptions { with: vec!["555-010-0001".to_string()], ..Default::default() };
+        assert_eq!(matching(options, false, false), [2, 5]);
+        let options = ExportOptions { with: vec!["friend@example.com".to_string()], ..Default::default() };
+        assert_eq!(matching(options, false, false), [3, 4]);
+        let options = ExportOptions { with: vec!["nobody@example.com".to_string()], ..Default::default() };
+        assert!(matching(options, false, false).is_empty());
+    }
+
+    #[test]
+    fn chat_ids_include_recently_deleted_only_when_asked() {
+        let options = ExportOptions { chat_ids: vec![7], ..Default::default() };
+        assert_eq!(matching(options, true, false), [1, 2]);
+        let options = ExportOptions { chat_ids: vec![7], include_deleted: true, ..Default::default() };
+        assert_eq!(matching(options, true, false), [1, 2, 5]);
+        let options = ExportOptions { chat_ids: vec![7, 8], include_deleted: true, ..Default::default() };
+        assert_eq!(matching(options, true, true), [1, 2, 3, 4, 5]);
+    }
+}
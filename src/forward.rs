@@ -0,0 +1,163 @@
+use serde_json::json;
+use std::fs;
+use std::str::FromStr;
+use tracing::warn;
+
+use crate::error::AppError;
+use crate::export::MessageRecord;
+use crate::output::sender_label;
+use crate::webhook::Webhook;
+
+/// Longest message Discord accepts, in characters
+const DISCORD_MAX_CONTENT: usize = 2000;
+/// Most files a Discord webhook message can carry
+const DISCORD_MAX_FILES: usize = 10;
+/// Attachments larger than this are named rather than uploaded; Discord's
+/// limit for servers without boosts
+const DISCORD_MAX_FILE_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Where `--forward` posts messages, given as `slack:<webhook URL>` or
+/// `discord:<webhook URL>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForwardTarget {
+    Slack(String),
+    Discord(String),
+}
+
+impl FromStr for ForwardTarget {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("slack", url)) if !url.is_empty() => Ok(ForwardTarget::Slack(url.to_string())),
+            Some(("discord", url)) if !url.is_empty() => Ok(ForwardTarget::Discord(url.to_string())),
+            _ => Err(AppError::Args(format!(
+                "--forward {} should be slack:<webhook URL> or discord:<webhook URL>",
+                s
+            ))),
+        }
+    }
+}
+
+/// Posts incoming messages to a Slack or Discord channel through its webhook
+#[derive(Debug, Clone)]
+pub struct Forwarder {
+    target: ForwardTarget,
+    webhook: Webhook,
+}
+
+/// Who sent a message, and in which group chat if it was one
+fn heading(record: &MessageRecord) -> String {
+    match record.chat_name.as_deref().filter(|_| record.participants.len() > 1) {
+        Some(chat) => format!("{} in {}", sender_label(record), chat),
+        None => sender_label(record),
+    }
+}
+
+fn attachment_name(attachment: &crate::attachments::AttachmentRecord) -> &str {
+    attachment.filename.as_deref().unwrap_or("attachment")
+}
+
+/// Escape the characters Slack's mrkdwn treats as markup
+fn slack_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+impl Forwarder {
+    pub fn new(target: ForwardTarget, retries: u32) -> Self {
+        let url = match &target {
+            ForwardTarget::Slack(url) | ForwardTarget::Discord(url) => url.clone(),
+        };
+        Forwarder { webhook: Webhook::new(&url, retries), target }
+    }
+
+    /// Post `record` if someone else sent it; the user's own messages aren't forwarded
+    pub fn forward(&self, record: &MessageRecord) -> Result<(), AppError> {
+        if record.from_me {
+            return Ok(());
+        }
+        match self.target {
+            ForwardTarget::Slack(_) => self.slack(record),
+            ForwardTarget::Discord(_) => self.discord(record),
+        }
+    }
+
+    /// Slack's incoming webhooks can't carry files, so attachments are named
+    fn slack(&self, record: &MessageRecord) -> Result<(), AppError> {
+        let mut text = format!("*{}*", slack_escape(&heading(record)));
+        if let Some(body) = record.text.as_deref().filter(|t| !t.is_empty()) {
+            text.push('\n');
+            text.push_str(&slack_escape(body));
+        }
+        for attachment in &record.attachments {
+            text.push_str(&format!("\n:paperclip: {}", slack_escape(attachment_name(attachment))));
+        }
+        self.webhook.deliver(&json!({ "text": text }))
+    }
+
+    /// Discord takes the files themselves as a multipart upload
+    fn discord(&self, record: &MessageRecord) -> Result<(), AppError> {
+        let mut content = format!("**{}**", heading(record));
+        if let Some(body) = record.text.as_deref().filter(|t| !t.is_empty()) {
+            content.push('\n');
+            content.push_str(body);
+        }
+
+        let mut files = Vec::new();
+        for attachment in &record.attachments {
+            let upload = attachment.path.as_deref().filter(|path| {
+                files.len() < DISCORD_MAX_FILES
+                    && fs::metadata(path).is_ok_and(|m| m.len() <= DISCORD_MAX_FILE_BYTES)
+            });
+            match upload.map(|path| (path, fs::read(path))) {
+                Some((_, Ok(bytes))) => files.push((attachment_name(attachment).to_string(), bytes)),
+                Some((path, Err(e))) => {
+                    warn!(path, "couldn't read attachment to forward: {}", e);
+                    content.push_str(&format!("\n📎 {}", attachment_name(attachment)));
+                }
+                None => content.push_str(&format!("\n📎 {}", attachment_name(attachment))),
+            }
+        }
+        if content.chars().count() > DISCORD_MAX_CONTENT {
+            content = content.chars().take(DISCORD_MAX_CONTENT - 1).chain(['…']).collect();
+        }
+
+        // Nobody in the channel gets pinged by an @everyone in a text
+        let payload = json!({
+            "content": content,
+            "allowed_mentions": { "parse": [] },
+        });
+        if files.is_empty() {
+            return self.webhook.deliver(&payload);
+        }
+        let (content_type, body) = multipart(&payload.to_string(), &files);
+        self.webhook.deliver_body(&content_type, &body)
+    }
+}
+
+/// A `multipart/form-data` body with the JSON payload and each file, as
+/// Discord expects uploads
+fn multipart(payload: &str, files: &[(String, Vec<u8>)]) -> (String, Vec<u8>) {
+    let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    let boundary = format!("imessage-blaster-{}-{}", std::process::id(), nanos);
+    let mut body = Vec::new();
+    body.extend_from_slice(
+        format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"payload_json\"\r\nContent-Type: application/json\r\n\r\n{payload}\r\n"
+        )
+        .as_bytes(),
+    );
+    for (i, (name, bytes)) in files.iter().enumerate() {
+        let name = name.replace(['"', '\r', '\n'], "_");
+        body.extend_from_slice(
+            format!(
+                "--{boundary}\r\nContent-Disposition: form-data; name=\"files[{i}]\"; filename=\"{name}\"\r\nContent-Type: application/octet-stream\r\n\r\n"
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(bytes);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+    (format!("multipart/form-data; boundary={}", boundary), body)
+}
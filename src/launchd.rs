@@ -0,0 +1,207 @@
+use imessage_database::util::dirs::home;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tracing::debug;
+
+use crate::error::AppError;
+use crate::output::html::escape;
+
+/// Label of the agent `daemon install` writes unless given another
+pub const DEFAULT_LABEL: &str = "com.imessage-blaster.daemon";
+
+/// Seconds launchd waits before starting the agent again after it exits
+const THROTTLE_INTERVAL: u32 = 10;
+
+/// A LaunchAgent that runs this binary at login and again whenever it dies
+#[derive(Debug, Clone)]
+pub struct LaunchAgent {
+    label: String,
+}
+
+/// Whether an agent is installed and running, as `daemon status` prints it
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentStatus {
+    pub label: String,
+    pub plist: PathBuf,
+    /// The plist is in ~/Library/LaunchAgents
+    pub installed: bool,
+    /// launchd knows about the agent
+    pub loaded: bool,
+    pub pid: Option<u32>,
+    /// How the agent last exited, if it has
+    pub last_exit_status: Option<i32>,
+    /// The command the plist runs
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub program_arguments: Vec<String>,
+    /// Where its stdout and stderr go
+    pub log: PathBuf,
+}
+
+/// Run `launchctl`, returning its stdout
+fn launchctl(args: &[&str]) -> Result<String, AppError> {
+    let output = Command::new("launchctl")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| AppError::Launchd(format!("Couldn't run launchctl: {}", e)))?;
+    if output.status.success() {
+        return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    Err(AppError::Launchd(if stderr.is_empty() {
+        format!("launchctl {} exited with {}", args.join(" "), output.status)
+    } else {
+        stderr
+    }))
+}
+
+/// The value of `"key" = value;` in `launchctl list <label>`'s output
+fn list_value<T: std::str::FromStr>(listing: &str, key: &str) -> Option<T> {
+    let prefix = format!("\"{}\" = ", key);
+    listing.lines().find_map(|line| line.trim().strip_prefix(&prefix)?.trim_end_matches(';').parse().ok())
+}
+
+impl LaunchAgent {
+    pub fn new(label: &str) -> Result<Self, AppError> {
+        let valid = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_');
+        if label.is_empty() || !label.chars().all(valid) {
+            return Err(AppError::Args(format!(
+                "Invalid LaunchAgent label '{}': use letters, numbers, dots, dashes and underscores",
+                label
+            )));
+        }
+        Ok(LaunchAgent { label: label.to_string() })
+    }
+
+    /// `~/Library/LaunchAgents/<label>.plist`
+    pub fn plist_path(&self) -> PathBuf {
+        PathBuf::from(home()).join("Library").join("LaunchAgents").join(format!("{}.plist", self.label))
+    }
+
+    /// `~/.imessage-blaster/logs/<label>.log`
+    pub fn log_path(&self) -> PathBuf {
+        PathBuf::from(home()).join(".imessage-blaster").join("logs").join(format!("{}.log", self.label))
+    }
+
+    /// The plist for running `program` with `args`. launchd starts agents
+    /// with a bare PATH, so the current one is passed on for tools like
+    /// ffmpeg and osascript.
+    pub fn plist(&self, program: &Path, args: &[String]) -> String {
+        let log = escape(&self.log_path().to_string_lossy());
+        let mut arguments = format!("        <string>{}</string>\n", escape(&program.to_string_lossy()));
+        for arg in args {
+            arguments.push_str(&format!("        <string>{}</string>\n", escape(arg)));
+        }
+        let path = std::env::var("PATH").unwrap_or_else(|_| "/usr/bin:/bin:/usr/sbin:/sbin".to_string());
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+{arguments}    </array>
+    <key>EnvironmentVariables</key>
+    <dict>
+        <key>PATH</key>
+        <string>{path}</string>
+    </dict>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>ThrottleInterval</key>
+    <integer>{throttle}</integer>
+    <key>ProcessType</key>
+    <string>Background</string>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#,
+            label = escape(&self.label),
+            path = escape(&path),
+            throttle = THROTTLE_INTERVAL,
+        )
+    }
+
+    /// Write the plist and load it, replacing an agent already installed
+    /// under the same label. `args` follow the program.
+    pub fn install(&self, program: &Path, args: &[String]) -> Result<AgentStatus, AppError> {
+        let plist = self.plist_path();
+        if let Some(dir) = plist.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        if let Some(dir) = self.log_path().parent() {
+            fs::create_dir_all(dir)?;
+        }
+        if plist.exists() {
+            // Loading over a loaded agent fails, so take the old one down first
+            let _ = launchctl(&["unload", &plist.to_string_lossy()]);
+        }
+        fs::write(&plist, self.plist(program, args))?;
+        launchctl(&["load", "-w", &plist.to_string_lossy()])?;
+        debug!(label = %self.label, plist = %plist.display(), "loaded LaunchAgent");
+        self.status()
+    }
+
+    /// Stop the agent and delete its plist. `false` if it wasn't installed.
+    pub fn uninstall(&self) -> Result<bool, AppError> {
+        let plist = self.plist_path();
+        if !plist.exists() {
+            return Ok(false);
+        }
+        if let Err(e) = launchctl(&["unload", "-w", &plist.to_string_lossy()]) {
+            // Already unloaded, say after a failed install; the plist still goes
+            debug!(error = %e, "couldn't unload LaunchAgent");
+        }
+        fs::remove_file(&plist)?;
+        Ok(true)
+    }
+
+    pub fn status(&self) -> Result<AgentStatus, AppError> {
+        let plist = self.plist_path();
+        let installed = plist.exists();
+        // `launchctl list <label>` fails for agents launchd doesn't know
+        let listing = launchctl(&["list", &self.label]).ok();
+        let program_arguments = if installed { Self::read_arguments(&fs::read_to_string(&plist)?) } else { Vec::new() };
+        Ok(AgentStatus {
+            label: self.label.clone(),
+            installed,
+            loaded: listing.is_some(),
+            pid: listing.as_deref().and_then(|l| list_value(l, "PID")),
+            last_exit_status: listing.as_deref().and_then(|l| list_value(l, "LastExitStatus")),
+            program_arguments,
+            log: self.log_path(),
+            plist,
+        })
+    }
+
+    /// The `ProgramArguments` of a plist this wrote
+    fn read_arguments(plist: &str) -> Vec<String> {
+        let Some(array) = plist.split("<key>ProgramArguments</key>").nth(1).and_then(|rest| rest.split("</array>").next())
+        else {
+            return Vec::new();
+        };
+        array
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("<string>")?.strip_suffix("</string>"))
+            .map(|arg| {
+                arg.replace("&lt;", "<")
+                    .replace("&gt;", ">")
+                    .replace("&quot;", "\"")
+                    .replace("&#39;", "'")
+                    .replace("&amp;", "&")
+            })
+            .collect()
+    }
+}
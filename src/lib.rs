@@ -0,0 +1,69 @@
+//! Export messages from the macOS iMessage database (chat.db).
+//!
+//! ```no_run
+//! use imessagedump::{ExportOptions, MessageExporter};
+//!
+//! let exporter = MessageExporter::new(ExportOptions::default())?;
+//! for record in exporter {
+//!     println!("{:?}", record?);
+//! }
+//! # Ok::<(), imessagedump::AppError>(())
+//! ```
+
+pub mod analyze;
+pub mod anonymize;
+pub mod attachments;
+pub mod autorespond;
+pub mod balloon;
+pub mod body;
+pub mod campaign;
+pub mod chats;
+pub mod compress;
+pub mod config;
+pub mod contacts;
+pub mod dates;
+pub mod dedupe;
+pub mod edits;
+pub mod encrypt;
+pub mod error;
+pub mod events;
+pub mod export;
+pub mod forward;
+pub mod launchd;
+pub mod links;
+pub mod media;
+pub mod merge;
+pub mod mqtt;
+pub mod optout;
+pub mod output;
+pub mod people;
+pub mod phone;
+mod query;
+pub mod queue;
+pub mod quiet;
+pub mod reactions;
+pub mod recover;
+pub mod retry;
+pub mod search;
+pub mod send;
+pub mod server;
+pub mod service;
+pub mod shortcuts;
+pub mod sink;
+pub mod snapshot;
+pub mod state;
+pub mod summary;
+pub mod template;
+pub mod thumbnail;
+pub mod timezone;
+pub mod transcribe;
+pub mod tui;
+pub mod verify;
+pub mod watch;
+pub mod webhook;
+
+pub use contacts::ContactBook;
+pub use error::AppError;
+pub use export::{ExportOptions, MessageExporter, MessageRecord};
+pub use output::OutputFormat;
+pub use send::{send_message, SendResult};
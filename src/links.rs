@@ -0,0 +1,178 @@
+use chrono::{DateTime, FixedOffset};
+use imessage_database::message_types::url::URLMessage;
+use imessage_database::message_types::variants::BalloonProvider;
+use imessage_database::tables::messages::Message;
+use imessage_database::util::plist::parse_ns_keyed_archiver;
+use regex::Regex;
+use rusqlite::Connection;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::error::AppError;
+use crate::export::MessageRecord;
+
+/// `http(s)://` and `www.` links, up to the first whitespace or quote
+const URL_PATTERN: &str = r#"(?i)\b(?:https?://|www\.)[^\s<>"“”]+"#;
+
+/// The Open Graph metadata Messages fetched for a link when it was shared,
+/// stored with the message as its rich link preview
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct LinkPreview {
+    /// Where the link ended up, after redirects
+    pub url: Option<String>,
+    /// The link as it was shared, when redirects took it somewhere else
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_url: Option<String>,
+    /// The page's `og:title`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// The page's `og:description`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub site_name: Option<String>,
+    /// What kind of page Apple took it for, like `article` or `video`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub item_type: Option<String>,
+    /// URL of the page's `og:image`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    /// URL of the site's icon
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+}
+
+impl LinkPreview {
+    /// Whether `url` is the link this is a preview of
+    pub fn is_for(&self, url: &str) -> bool {
+        self.url.as_deref() == Some(url) || self.original_url.as_deref() == Some(url)
+    }
+}
+
+/// The link preview stored with `msg`, if it has one. Previews Messages
+/// hadn't loaded yet carry a URL and nothing else.
+pub fn preview(msg: &Message, db: &Connection) -> Option<LinkPreview> {
+    if !msg.is_url() {
+        return None;
+    }
+    let payload = parse_ns_keyed_archiver(&msg.payload_data(db)?).ok()?;
+    let preview = URLMessage::from_map(&payload).ok()?;
+    let owned = |value: Option<&str>| value.filter(|v| !v.is_empty()).map(String::from);
+    let url = owned(preview.url);
+    Some(LinkPreview {
+        original_url: owned(preview.original_url).filter(|original| url.as_ref() != Some(original)),
+        url,
+        title: owned(preview.title),
+        summary: owned(preview.summary),
+        site_name: owned(preview.site_name),
+        item_type: owned(preview.item_type),
+        image: owned(preview.images.first().copied()),
+        icon: owned(preview.icons.first().copied()),
+    })
+}
+
+/// Finds the URLs shared in a message
+#[derive(Debug, Clone)]
+pub struct LinkExtractor {
+    pattern: Regex,
+}
+
+impl Default for LinkExtractor {
+    fn default() -> Self {
+        LinkExtractor { pattern: Regex::new(URL_PATTERN).expect("URL pattern is valid") }
+    }
+}
+
+impl LinkExtractor {
+    /// Links in `text`, in the order they appear, without trailing punctuation
+    pub fn in_text(&self, text: &str) -> Vec<String> {
+        self.pattern
+            .find_iter(text)
+            .map(|m| trim_url(m.as_str()))
+            .filter(|url| !url.is_empty())
+            .map(|url| if url.starts_with("www.") { format!("https://{}", url) } else { url.to_string() })
+            .collect()
+    }
+
+    /// Links in a message's text and its rich link preview, each listed once
+    pub fn in_message(&self, text: Option<&str>, preview: Option<&LinkPreview>) -> Vec<String> {
+        let mut links = text.map(|t| self.in_text(t)).unwrap_or_default();
+        if let Some(preview) = preview {
+            // The original URL is what was typed; `url` is where redirects ended up
+            links.extend(preview.original_url.iter().chain(&preview.url).cloned());
+        }
+        let mut seen = Vec::new();
+        links.retain(|url| {
+            let new = !seen.contains(url);
+            if new {
+                seen.push(url.clone());
+            }
+            new
+        });
+        links
+    }
+}
+
+/// Drop sentence punctuation after a URL, and a closing bracket that wasn't opened in it
+fn trim_url(url: &str) -> &str {
+    let mut url = url.trim_end_matches(['.', ',', ';', ':', '!', '?', '\'', '’']);
+    for (open, close) in [('(', ')'), ('[', ']'), ('{', '}')] {
+        while url.ends_with(close) && url.matches(close).count() > url.matches(open).count() {
+            url = &url[..url.len() - 1];
+        }
+    }
+    url
+}
+
+/// A URL and when it was shared
+#[derive(Debug, Clone, Serialize)]
+pub struct SharedLink {
+    pub url: String,
+    /// How many messages shared it
+    pub count: usize,
+    pub first_shared: DateTime<FixedOffset>,
+    pub last_shared: DateTime<FixedOffset>,
+    /// Handles that shared it, the user's own included
+    pub shared_by: Vec<String>,
+    /// The page's title, description and image, from the first preview of it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<LinkPreview>,
+}
+
+/// Every URL in `records`, each listed once, in the order first shared
+pub fn shared_links<I>(records: I) -> Result<Vec<SharedLink>, AppError>
+where
+    I: IntoIterator<Item = Result<MessageRecord, AppError>>,
+{
+    let mut links: Vec<SharedLink> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for record in records {
+        let record = record?;
+        for url in &record.links {
+            let i = *index.entry(url.clone()).or_insert_with(|| {
+                links.push(SharedLink {
+                    url: url.clone(),
+                    count: 0,
+                    first_shared: record.date,
+                    last_shared: record.date,
+                    shared_by: Vec::new(),
+                    preview: None,
+                });
+                links.len() - 1
+            });
+            let link = &mut links[i];
+            link.count += 1;
+            link.first_shared = link.first_shared.min(record.date);
+            link.last_shared = link.last_shared.max(record.date);
+            if link.preview.is_none() {
+                link.preview = record.link_preview.clone().filter(|p| p.is_for(url));
+            }
+            if let Some(from) = record.from.as_ref().filter(|f| !link.shared_by.contains(f)) {
+                link.shared_by.push(from.clone());
+            }
+        }
+    }
+    links.sort_by_key(|link| link.first_shared);
+    Ok(links)
+}
@@ -0,0 +1,143 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tracing::debug;
+
+use crate::error::AppError;
+
+/// Run a media tool, turning a failure into an error with what it printed
+pub(crate) fn run_tool(command: &mut Command) -> Result<(), AppError> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| AppError::Media(format!("Couldn't run {}: {}", program, e)))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    Err(AppError::Media(if stderr.is_empty() {
+        format!("{} exited with {}", program, output.status)
+    } else {
+        format!("{} failed: {}", program, stderr)
+    }))
+}
+
+/// An attachment rewritten in a format other platforms can open
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Converted {
+    pub path: PathBuf,
+    pub mime_type: &'static str,
+    pub size: u64,
+}
+
+/// Apple-only formats and what they're converted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Conversion {
+    /// HEIC and HEIF photos, to JPEG
+    Photo,
+    /// QuickTime movies, often HEVC, to H.264 MP4
+    Movie,
+}
+
+impl Conversion {
+    fn of(path: &Path, mime_type: Option<&str>) -> Option<Self> {
+        let extension = path.extension().and_then(OsStr::to_str).unwrap_or_default().to_ascii_lowercase();
+        match (mime_type.unwrap_or_default(), extension.as_str()) {
+            ("image/heic" | "image/heif", _) | (_, "heic" | "heif") => Some(Conversion::Photo),
+            ("video/quicktime", _) | (_, "mov") => Some(Conversion::Movie),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Conversion::Photo => "jpg",
+            Conversion::Movie => "mp4",
+        }
+    }
+
+    fn mime_type(&self) -> &'static str {
+        match self {
+            Conversion::Photo => "image/jpeg",
+            Conversion::Movie => "video/mp4",
+        }
+    }
+}
+
+/// Converts copied attachments out of Apple-only formats: HEIC photos to
+/// JPEG and QuickTime movies to MP4
+#[derive(Debug, Clone, Default)]
+pub struct MediaConverter {
+    keep_originals: bool,
+}
+
+impl MediaConverter {
+    /// With `keep_originals`, the copy in its original format is left beside the converted one
+    pub fn new(keep_originals: bool) -> Self {
+        MediaConverter { keep_originals }
+    }
+
+    pub fn keeps_originals(&self) -> bool {
+        self.keep_originals
+    }
+
+    /// Convert the attachment copied to `path`, or `None` if it's already
+    /// in a format that opens anywhere. A file converted by an earlier
+    /// export is reused.
+    pub fn convert(&self, path: &Path, mime_type: Option<&str>) -> Result<Option<Converted>, AppError> {
+        let Some(conversion) = Conversion::of(path, mime_type) else {
+            return Ok(None);
+        };
+        let dest = path.with_extension(conversion.extension());
+        if dest == path {
+            return Ok(None);
+        }
+        if !dest.exists() {
+            debug!(path = %path.display(), "converting attachment");
+            // Written under another name first, so a failure never leaves half a file behind
+            let partial = path.with_extension(format!("partial.{}", conversion.extension()));
+            let result = match conversion {
+                Conversion::Photo => Self::photo(path, &partial),
+                Conversion::Movie => Self::movie(path, &partial),
+            };
+            if let Err(e) = result.and_then(|()| Ok(fs::rename(&partial, &dest)?)) {
+                let _ = fs::remove_file(&partial);
+                return Err(e);
+            }
+        }
+        if !self.keep_originals && path.exists() {
+            fs::remove_file(path)?;
+        }
+        let size = fs::metadata(&dest)?.len();
+        Ok(Some(Converted { path: dest, mime_type: conversion.mime_type(), size }))
+    }
+
+    /// `sips` ships with macOS and reads every HEIC an iPhone takes;
+    /// elsewhere, ffmpeg can read most of them
+    fn photo(source: &Path, dest: &Path) -> Result<(), AppError> {
+        let sips = run_tool(Command::new("sips").args(["-s", "format", "jpeg"]).arg(source).arg("--out").arg(dest));
+        sips.or_else(|e| {
+            debug!(error = %e, "sips couldn't convert, trying ffmpeg");
+            run_tool(
+                Command::new("ffmpeg")
+                    .args(["-y", "-loglevel", "error", "-i"])
+                    .arg(source)
+                    .args(["-frames:v", "1", "-q:v", "2"])
+                    .arg(dest),
+            )
+        })
+    }
+
+    fn movie(source: &Path, dest: &Path) -> Result<(), AppError> {
+        run_tool(
+            Command::new("ffmpeg")
+                .args(["-y", "-loglevel", "error", "-i"])
+                .arg(source)
+                .args(["-c:v", "libx264", "-pix_fmt", "yuv420p", "-crf", "23", "-c:a", "aac"])
+                .args(["-movflags", "+faststart", "-f", "mp4"])
+                .arg(dest),
+        )
+    }
+}
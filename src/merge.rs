@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use crate::contacts::{handle_key, ContactBook};
+
+/// A chat.db handle, with the identifier Messages uses to link a person's aliases
+#[derive(Debug, Clone)]
+pub struct HandleInfo {
+    pub rowid: i32,
+    pub id: String,
+    pub person_centric_id: Option<String>,
+}
+
+/// Maps every phone number and email of a person to one canonical handle, so
+/// someone who texts from both `+15551234567` and `jane@icloud.com` shows up
+/// as one sender
+#[derive(Debug, Default, Clone)]
+pub struct HandleMerger {
+    /// Handle key to the canonical handle for that person
+    canonical: HashMap<String, String>,
+}
+
+/// Union-find over handle keys
+#[derive(Default)]
+struct Groups {
+    parent: HashMap<String, String>,
+}
+
+impl Groups {
+    fn find(&mut self, key: &str) -> String {
+        let parent = self.parent.entry(key.to_string()).or_insert_with(|| key.to_string()).clone();
+        if parent == key {
+            return parent;
+        }
+        let root = self.find(&parent);
+        self.parent.insert(key.to_string(), root.clone());
+        root
+    }
+
+    fn join(&mut self, a: &str, b: &str) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parent.insert(a, b);
+        }
+    }
+}
+
+impl HandleMerger {
+    /// Link handles that share a `person_centric_id` in chat.db, or that are on
+    /// the same card in `contacts`. Each person's canonical handle is their
+    /// first phone number in chat.db, or their first email if they have none.
+    pub fn new(handles: &[HandleInfo], contacts: Option<&ContactBook>) -> Self {
+        let mut groups = Groups::default();
+        let mut by_person: HashMap<&str, &str> = HashMap::new();
+        for handle in handles {
+            let key = handle_key(&handle.id);
+            groups.find(&key);
+            if let Some(person) = handle.person_centric_id.as_deref().filter(|p| !p.is_empty()) {
+                match by_person.get(person) {
+                    Some(other) => groups.join(&key, &handle_key(other)),
+                    None => {
+                        by_person.insert(person, &handle.id);
+                    }
+                }
+            }
+        }
+        for keys in contacts.into_iter().flat_map(ContactBook::linked_handles) {
+            for pair in keys.windows(2) {
+                groups.join(&pair[0], &pair[1]);
+            }
+        }
+
+        // Phone numbers before emails, then oldest first
+        let mut ordered: Vec<&HandleInfo> = handles.iter().collect();
+        ordered.sort_by_key(|h| (h.id.contains('@'), h.rowid));
+        let mut chosen: HashMap<String, String> = HashMap::new();
+        for handle in &ordered {
+            let root = groups.find(&handle_key(&handle.id));
+            chosen.entry(root).or_insert_with(|| handle.id.clone());
+        }
+
+        let keys: Vec<String> = groups.parent.keys().cloned().collect();
+        let canonical = keys
+            .into_iter()
+            .filter_map(|key| {
+                let root = groups.find(&key);
+                chosen.get(&root).map(|handle| (key, handle.clone()))
+            })
+            .collect();
+        HandleMerger { canonical }
+    }
+
+    /// The canonical handle for the person behind `handle`, or `handle` itself
+    /// if it isn't linked to any other
+    pub fn canonical(&self, handle: &str) -> String {
+        self.canonical.get(&handle_key(handle)).cloned().unwrap_or_else(|| handle.to_string())
+    }
+}
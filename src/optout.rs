@@ -0,0 +1,176 @@
+use chrono::{DateTime, Utc};
+use imessage_database::util::dirs::home;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::contacts::handle_key;
+use crate::error::AppError;
+use crate::export::MessageRecord;
+use crate::send::SendResult;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS optouts (
+    key TEXT PRIMARY KEY,
+    handle TEXT NOT NULL,
+    reason TEXT,
+    added_at INTEGER NOT NULL
+);
+";
+
+/// Replies that mean "stop messaging me"
+const STOP_WORDS: &[&str] = &["STOP", "STOPALL", "UNSUBSCRIBE", "UNSUB", "CANCEL", "END", "QUIT", "OPTOUT", "OPT OUT"];
+
+/// Someone who asked not to be messaged
+#[derive(Debug, Clone, Serialize)]
+pub struct OptOut {
+    pub handle: String,
+    pub reason: Option<String>,
+    pub added_at: DateTime<Utc>,
+}
+
+/// The sender and text of an incoming STOP-style reply, which
+/// [`SuppressionList::record_reply`] adds to the list
+pub fn stop_reply(record: &MessageRecord) -> Option<(&str, &str)> {
+    let (false, Some(from), Some(text)) = (record.from_me, &record.from, &record.text) else {
+        return None;
+    };
+    is_stop_request(text).then_some((from.as_str(), text.as_str()))
+}
+
+/// Whether a reply is an opt-out request like `STOP` or `Unsubscribe.`
+pub fn is_stop_request(text: &str) -> bool {
+    let word = text.trim().trim_end_matches(['.', '!']).to_uppercase();
+    STOP_WORDS.contains(&word.as_str())
+}
+
+/// Handles that must not be sent to, stored in SQLite and matched loosely
+/// (see [`handle_key`]) so `(555) 123-4567` covers `+15551234567`
+pub struct SuppressionList {
+    db: Connection,
+}
+
+impl SuppressionList {
+    /// `~/.imessage-blaster/optouts.sqlite`
+    pub fn default_path() -> PathBuf {
+        PathBuf::from(home()).join(".imessage-blaster").join("optouts.sqlite")
+    }
+
+    pub fn open(path: &Path) -> Result<Self, AppError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let db = Connection::open(path)?;
+        db.execute_batch(SCHEMA)?;
+        Ok(SuppressionList { db })
+    }
+
+    /// Open the list at its default location
+    pub fn open_default() -> Result<Self, AppError> {
+        Self::open(&Self::default_path())
+    }
+
+    /// Add a handle, returning false if it was already on the list
+    pub fn add(&self, handle: &str, reason: Option<&str>) -> Result<bool, AppError> {
+        let added = self.db.execute(
+            "INSERT OR IGNORE INTO optouts (key, handle, reason, added_at) VALUES (?1, ?2, ?3, ?4)",
+            params![handle_key(handle), handle, reason, Utc::now().timestamp()],
+        )?;
+        Ok(added > 0)
+    }
+
+    /// Remove a handle, returning false if it wasn't on the list
+    pub fn remove(&self, handle: &str) -> Result<bool, AppError> {
+        Ok(self.db.execute("DELETE FROM optouts WHERE key = ?1", [handle_key(handle)])? > 0)
+    }
+
+    pub fn contains(&self, handle: &str) -> Result<bool, AppError> {
+        Ok(self
+            .db
+            .query_row("SELECT 1 FROM optouts WHERE key = ?1", [handle_key(handle)], |_| Ok(()))
+            .optional()?
+            .is_some())
+    }
+
+    pub fn list(&self) -> Result<Vec<OptOut>, AppError> {
+        let mut statement = self.db.prepare("SELECT handle, reason, added_at FROM optouts ORDER BY added_at")?;
+        let rows = statement.query_map([], |row| {
+            Ok(OptOut {
+                handle: row.get(0)?,
+                reason: row.get(1)?,
+                added_at: DateTime::from_timestamp(row.get(2)?, 0).unwrap_or_default(),
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Add the sender of an incoming STOP-style reply, returning their handle
+    /// if they weren't already suppressed
+    pub fn record_reply(&self, record: &MessageRecord) -> Result<Option<String>, AppError> {
+        let Some((from, text)) = stop_reply(record) else {
+            return Ok(None);
+        };
+        let reason = format!("replied \"{}\"", text.trim());
+        Ok(self.add(from, Some(&reason))?.then(|| from.to_string()))
+    }
+}
+
+/// The result reported for a recipient who was skipped because they opted out
+pub fn suppressed_result(recipient: &str) -> SendResult {
+    SendResult {
+        recipient: recipient.to_string(),
+        success: false,
+        error: Some("recipient has opted out".to_string()),
+        attachments: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn reply(from_me: bool, text: &str) -> MessageRecord {
+        serde_json::from_value(json!({
+            "id": 1,
+            "date": "2024-03-15T12:00:00Z",
+            "text": text,
+            "from": "+15551234567",
+            "from_me": from_me,
+            "participants": [],
+            "was_unsent": false,
+            "is_read": true,
+            "is_digital_touch": false,
+            "is_handwriting": false,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn stop_words_are_matched_loosely() {
+        for text in ["STOP", "stop", " Stop. ", "Unsubscribe!", "opt out"] {
+            assert!(is_stop_request(text), "{text}");
+        }
+        for text in ["please stop", "STOPPED", "don't stop", ""] {
+            assert!(!is_stop_request(text), "{text}");
+        }
+    }
+
+    #[test]
+    fn only_incoming_stop_replies_count() {
+        assert_eq!(stop_reply(&reply(false, "STOP")), Some(("+15551234567", "STOP")));
+        assert_eq!(stop_reply(&reply(true, "STOP")), None);
+        assert_eq!(stop_reply(&reply(false, "Thanks!")), None);
+    }
+
+    #[test]
+    fn replies_are_recorded_once_and_match_any_format() {
+        let list = SuppressionList::open(Path::new(":memory:")).unwrap();
+        assert_eq!(list.record_reply(&reply(false, "Thanks!")).unwrap(), None);
+        assert_eq!(list.record_reply(&reply(false, "stop")).unwrap().as_deref(), Some("+15551234567"));
+        assert_eq!(list.record_reply(&reply(false, "STOP")).unwrap(), None);
+        assert!(list.contains("(555) 123-4567").unwrap());
+        assert!(!list.contains("+15559876543").unwrap());
+    }
+}
@@ -0,0 +1,163 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::fmt::Write;
+use std::fs;
+
+use super::{sender_label, Conversation};
+use crate::attachments::AttachmentRecord;
+use crate::error::AppError;
+
+const STYLE: &str = "
+body { font-family: -apple-system, Helvetica, sans-serif; max-width: 720px; margin: 2em auto; background: #fff; }
+h1 { text-align: center; font-size: 1.2em; color: #333; }
+.message { display: flex; flex-direction: column; margin: 6px 0; }
+.message.me { align-items: flex-end; }
+.sender { font-size: 0.75em; color: #888; margin: 0 12px 2px; }
+.bubble { max-width: 70%; padding: 8px 12px; border-radius: 18px; background: #e5e5ea; color: #000; white-space: pre-wrap; word-wrap: break-word; }
+.me .bubble { background: #0b84ff; color: #fff; }
+.bubble img { max-width: 100%; border-radius: 12px; display: block; margin: 4px 0; }
+.time { font-size: 0.7em; color: #aaa; margin: 2px 12px; }
+.reactions { font-size: 0.75em; color: #666; margin: 0 12px; }
+";
+
+/// Escape text for use in HTML content and attribute values
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Percent-encode each segment of a path for use in a URL, so names with
+/// `#`, `?`, `%` or spaces still point at the file
+fn url_path(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => out.push(byte as char),
+            _ => {
+                let _ = write!(out, "%{:02X}", byte);
+            }
+        }
+    }
+    out
+}
+
+/// A `file://` URL for an absolute path
+fn file_url(path: &str) -> String {
+    format!("file://{}", url_path(path))
+}
+
+/// Render an attachment: thumbnails link to their originals, other images are
+/// inlined as data URIs so the transcript is self-contained, and anything
+/// else is a link to the file
+fn render_attachment(attachment: &AttachmentRecord) -> String {
+    let name = attachment.filename.as_deref().unwrap_or("attachment");
+    let mime = attachment.mime_type.as_deref().unwrap_or("");
+    let Some(path) = &attachment.path else {
+        return format!("<div>[{} not on disk]</div>", escape(name));
+    };
+
+    // A thumbnail keeps the page small; the original is a click away
+    if let Some(thumbnail) = &attachment.thumbnail {
+        return format!(
+            "<a href=\"{}\"><img src=\"{}\" alt=\"{}\" loading=\"lazy\"></a>",
+            escape(&file_url(path)),
+            escape(&url_path(thumbnail)),
+            escape(name)
+        );
+    }
+    if mime.starts_with("image/") {
+        if let Ok(bytes) = fs::read(path) {
+            return format!(
+                "<img src=\"data:{};base64,{}\" alt=\"{}\">",
+                escape(mime),
+                STANDARD.encode(bytes),
+                escape(name)
+            );
+        }
+    }
+    format!("<div><a href=\"{}\">{}</a></div>", escape(&file_url(path)), escape(name))
+}
+
+/// Render a conversation as a standalone HTML page
+pub fn render(conversation: &Conversation) -> Result<String, AppError> {
+    let mut html = String::new();
+    let title = escape(&conversation.name);
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>{STYLE}</style>\n</head>\n<body>\n<h1>{title}</h1>\n"
+    );
+
+    for message in &conversation.messages {
+        let class = if message.from_me { "message me" } else { "message" };
+        let _ = writeln!(html, "<div class=\"{}\">", class);
+        let _ = writeln!(html, "<div class=\"sender\">{}</div>", escape(&sender_label(message)));
+
+        html.push_str("<div class=\"bubble\">");
+        for attachment in &message.attachments {
+            html.push_str(&render_attachment(attachment));
+        }
+        if let Some(text) = &message.text {
+            html.push_str(&escape(text));
+        }
+        html.push_str("</div>\n");
+
+        if !message.reactions.is_empty() {
+            let reactions: Vec<String> = message
+                .reactions
+                .iter()
+                .map(|r| r.emoji.clone().unwrap_or_else(|| r.kind.clone()))
+                .collect();
+            let _ = writeln!(html, "<div class=\"reactions\">{}</div>", escape(&reactions.join(" ")));
+        }
+
+        let time = message.date.format("%b %-d, %Y %-I:%M %p");
+        let _ = writeln!(html, "<div class=\"time\">{}</div>\n</div>", time);
+    }
+
+    html.push_str("</body>\n</html>\n");
+    Ok(html)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn attachment(path: &str, mime_type: &str, thumbnail: Option<&str>) -> AttachmentRecord {
+        serde_json::from_value(json!({
+            "filename": "notes #1.pdf",
+            "mime_type": mime_type,
+            "path": path,
+            "size": 10,
+            "thumbnail": thumbnail,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn paths_are_percent_encoded_in_links() {
+        assert_eq!(file_url("/Users/me/notes #1?.pdf"), "file:///Users/me/notes%20%231%3F.pdf");
+        assert_eq!(file_url("/tmp/100%/é.txt"), "file:///tmp/100%25/%C3%A9.txt");
+        assert_eq!(url_path("thumbnails/a b.jpg"), "thumbnails/a%20b.jpg");
+    }
+
+    #[test]
+    fn attachment_links_point_at_the_file() {
+        let html = render_attachment(&attachment("/tmp/missing/notes #1.pdf", "application/pdf", None));
+        assert_eq!(html, "<div><a href=\"file:///tmp/missing/notes%20%231.pdf\">notes #1.pdf</a></div>");
+        let html = render_attachment(&attachment("/tmp/missing/a&b.jpg", "image/jpeg", Some("thumbs/a&b.jpg")));
+        assert_eq!(
+            html,
+            "<a href=\"file:///tmp/missing/a%26b.jpg\"><img src=\"thumbs/a%26b.jpg\" alt=\"notes #1.pdf\" loading=\"lazy\"></a>"
+        );
+    }
+}
@@ -0,0 +1,44 @@
+use chrono::NaiveDate;
+use std::fmt::Write;
+
+use super::{sender_label, Conversation};
+use crate::error::AppError;
+
+/// Render a conversation as Markdown with a `## YYYY-MM-DD` header per day
+pub fn render(conversation: &Conversation) -> Result<String, AppError> {
+    let mut md = String::new();
+    let _ = write!(md, "# {}\n\n", conversation.name);
+
+    let mut day: Option<NaiveDate> = None;
+    for message in &conversation.messages {
+        let date = message.date.date_naive();
+        if day != Some(date) {
+            let _ = write!(md, "## {}\n\n", date.format("%Y-%m-%d"));
+            day = Some(date);
+        }
+
+        let mut parts: Vec<String> = message
+            .attachments
+            .iter()
+            .map(|a| {
+                let name = a.filename.as_deref().unwrap_or("attachment");
+                let image = a.mime_type.as_deref().is_some_and(|m| m.starts_with("image/"));
+                match (&a.path, &a.thumbnail, image) {
+                    (Some(path), Some(thumbnail), _) => format!("[![{}](<{}>)](<{}>)", name, thumbnail, path),
+                    (Some(path), None, true) => format!("![{}](<{}>)", name, path),
+                    (Some(path), None, false) => format!("[{}](<{}>)", name, path),
+                    (None, _, _) => format!("[{}]", name),
+                }
+            })
+            .collect();
+        if let Some(text) = &message.text {
+            // Hard line breaks keep multi-line messages together in one paragraph
+            parts.push(text.trim_end().replace('\n', "  \n"));
+        }
+
+        let _ = write!(md, "**{}:** {}\n\n", sender_label(message), parts.join(" "));
+    }
+    md.truncate(md.trim_end().len());
+    md.push('\n');
+    Ok(md)
+}
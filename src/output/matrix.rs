@@ -0,0 +1,152 @@
+use serde_json::{json, Map, Value};
+use std::collections::BTreeSet;
+
+use super::Conversation;
+use crate::attachments::AttachmentRecord;
+use crate::error::AppError;
+use crate::export::MessageRecord;
+
+/// Whose Matrix IDs the exported events are sent as
+#[derive(Debug, Clone)]
+pub struct MatrixIds {
+    /// The homeserver the room is imported into, like `example.org`
+    pub server_name: String,
+    /// The user's own Matrix ID, for messages they sent
+    pub own_user: String,
+}
+
+impl MatrixIds {
+    /// Own messages come from `@me:<server_name>` unless `own_user` is given
+    pub fn new(server_name: &str, own_user: Option<&str>) -> Self {
+        MatrixIds {
+            server_name: server_name.to_string(),
+            own_user: own_user.map_or_else(|| format!("@me:{}", server_name), String::from),
+        }
+    }
+
+    /// A puppet user for a handle, like `@imessage_15551234567:example.org`
+    fn puppet(&self, handle: Option<&str>) -> String {
+        format!("@imessage_{}:{}", localpart(handle.unwrap_or("unknown")), self.server_name)
+    }
+
+    fn sender(&self, from_me: bool, from: Option<&str>) -> String {
+        if from_me {
+            self.own_user.clone()
+        } else {
+            self.puppet(from)
+        }
+    }
+}
+
+/// A handle as a Matrix localpart: lowercase letters, digits and `._-/` as
+/// they are, anything else `=xx` escaped, so `bob@example.com` is `bob=40example.com`
+fn localpart(handle: &str) -> String {
+    let mut local = String::new();
+    for byte in handle.to_lowercase().bytes() {
+        match byte {
+            b'a'..=b'z' | b'0'..=b'9' | b'.' | b'_' | b'-' | b'/' => local.push(byte as char),
+            // Phone numbers read better without an escaped +
+            b'+' if local.is_empty() => {}
+            _ => local.push_str(&format!("={:02x}", byte)),
+        }
+    }
+    local
+}
+
+fn event_id(id: i64) -> String {
+    format!("$imessage-{}", id)
+}
+
+fn millis(record: &MessageRecord) -> i64 {
+    record.date.timestamp_millis()
+}
+
+/// The message type for an attachment, by its MIME type
+fn msgtype(attachment: &AttachmentRecord) -> &'static str {
+    match attachment.mime_type.as_deref().and_then(|m| m.split('/').next()) {
+        Some("image") => "m.image",
+        Some("video") => "m.video",
+        Some("audio") => "m.audio",
+        _ => "m.file",
+    }
+}
+
+fn event(id: String, sender: String, ts: i64, kind: &str, content: Value) -> Value {
+    json!({
+        "type": kind,
+        "event_id": id,
+        "sender": sender,
+        "origin_server_ts": ts,
+        "content": content,
+    })
+}
+
+/// The events for one message: its text, each attachment, and its tapbacks
+fn message_events(ids: &MatrixIds, record: &MessageRecord, events: &mut Vec<Value>) {
+    let sender = ids.sender(record.from_me, record.from.as_deref());
+    let reply_to = record.reply_to_id.map(|id| json!({ "m.in_reply_to": { "event_id": event_id(id) } }));
+
+    if let Some(text) = record.text.as_deref().filter(|t| !t.is_empty()) {
+        let mut content = Map::new();
+        content.insert("msgtype".to_string(), json!("m.text"));
+        content.insert("body".to_string(), json!(text));
+        if let Some(relates_to) = &reply_to {
+            content.insert("m.relates_to".to_string(), relates_to.clone());
+        }
+        events.push(event(event_id(record.id), sender.clone(), millis(record), "m.room.message", Value::Object(content)));
+    }
+    for (i, attachment) in record.attachments.iter().enumerate() {
+        // The file itself has to be uploaded to the homeserver to get an mxc:// URL,
+        // so it's left at its path on disk for the importer
+        let content = json!({
+            "msgtype": msgtype(attachment),
+            "body": attachment.filename.as_deref().unwrap_or("attachment"),
+            "info": { "mimetype": attachment.mime_type, "size": attachment.size },
+            "net.imessage.path": attachment.path,
+        });
+        // The text, if any, keeps the message's own event ID
+        let id = if i == 0 && record.text.as_deref().is_none_or(str::is_empty) {
+            event_id(record.id)
+        } else {
+            format!("{}-{}", event_id(record.id), i)
+        };
+        events.push(event(id, sender.clone(), millis(record), "m.room.message", content));
+    }
+    for (i, reaction) in record.reactions.iter().enumerate() {
+        let key = reaction.as_emoji();
+        let content = json!({
+            "m.relates_to": { "rel_type": "m.annotation", "event_id": event_id(record.id), "key": key },
+        });
+        events.push(event(
+            format!("{}-reaction-{}", event_id(record.id), i),
+            ids.sender(reaction.from_me, reaction.from.as_deref()),
+            reaction.date.timestamp_millis(),
+            "m.reaction",
+            content,
+        ));
+    }
+}
+
+/// Render a conversation as a Matrix room to import: its name, members, and
+/// `m.room.message` and `m.reaction` events with their original timestamps,
+/// in the shape an appservice sends them with `?ts=`
+pub fn render(conversation: &Conversation, ids: &MatrixIds) -> Result<String, AppError> {
+    let mut members = BTreeSet::from([ids.own_user.clone()]);
+    let mut events = Vec::new();
+    for record in &conversation.messages {
+        members.insert(ids.sender(record.from_me, record.from.as_deref()));
+        for participant in &record.participants {
+            members.insert(ids.puppet(Some(participant)));
+        }
+        message_events(ids, record, &mut events);
+    }
+    let room = json!({
+        "room": {
+            "name": conversation.name,
+            "topic": "Imported from iMessage",
+            "members": members,
+        },
+        "events": events,
+    });
+    Ok(serde_json::to_string_pretty(&room)?)
+}
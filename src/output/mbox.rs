@@ -0,0 +1,150 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Utc;
+use std::fmt::Write;
+use std::fs;
+
+use super::Conversation;
+use crate::attachments::AttachmentRecord;
+use crate::error::AppError;
+use crate::export::MessageRecord;
+
+/// Domain for the addresses made up for phone numbers and unknown senders
+const DOMAIN: &str = "imessage.invalid";
+
+/// An RFC 5322 address for a handle: emails as they are, anything else under [`DOMAIN`]
+fn address(handle: Option<&str>) -> String {
+    match handle {
+        Some(h) if h.contains('@') => h.to_string(),
+        Some(h) => {
+            let local: String = h.chars().filter(|c| !c.is_whitespace() && !"<>()[]\\,;:\"@".contains(*c)).collect();
+            format!("{}@{}", local, DOMAIN)
+        }
+        None => format!("unknown@{}", DOMAIN),
+    }
+}
+
+/// Header text, as an RFC 2047 encoded word when it isn't plain ASCII
+fn encode_header(text: &str) -> String {
+    if text.is_ascii() && !text.contains(['\r', '\n']) {
+        text.to_string()
+    } else {
+        format!("=?utf-8?B?{}?=", STANDARD.encode(text))
+    }
+}
+
+/// `Name <address>`, or just the address when there's no name
+fn mailbox(name: Option<&str>, handle: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("\"{}\" <{}>", encode_header(&name.replace('"', "'")), address(handle)),
+        None => format!("<{}>", address(handle)),
+    }
+}
+
+fn message_id(id: i64) -> String {
+    format!("<{}@{}>", id, DOMAIN)
+}
+
+/// Escape body lines that would read as the start of a new message (mboxrd)
+fn escape_from_lines(text: &str) -> String {
+    let mut escaped = String::new();
+    for line in text.lines() {
+        if line.trim_start_matches('>').starts_with("From ") {
+            escaped.push('>');
+        }
+        escaped.push_str(line);
+        escaped.push('\n');
+    }
+    escaped
+}
+
+/// A base64 MIME part for an attachment, or a note if the file isn't on disk
+fn attachment_part(attachment: &AttachmentRecord, boundary: &str) -> String {
+    let name = attachment.filename.as_deref().unwrap_or("attachment");
+    let data = attachment.path.as_deref().and_then(|path| fs::read(path).ok());
+    let mut part = format!("--{}\n", boundary);
+    match data {
+        Some(data) => {
+            let mime = attachment.mime_type.as_deref().unwrap_or("application/octet-stream");
+            let name = encode_header(&name.replace('"', "'"));
+            let _ = write!(
+                part,
+                "Content-Type: {}; name=\"{}\"\nContent-Disposition: attachment; filename=\"{}\"\nContent-Transfer-Encoding: base64\n\n",
+                mime, name, name
+            );
+            let encoded = STANDARD.encode(data);
+            for line in encoded.as_bytes().chunks(76) {
+                part.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+                part.push('\n');
+            }
+        }
+        None => {
+            let _ = write!(
+                part,
+                "Content-Type: text/plain; charset=utf-8\nContent-Transfer-Encoding: 8bit\n\n[{} is not on this Mac]\n",
+                name
+            );
+        }
+    }
+    part
+}
+
+fn render_message(conversation: &Conversation, message: &MessageRecord, out: &mut String) {
+    let from_name = if message.from_me { Some("Me") } else { message.from_name.as_deref() };
+    let from = mailbox(from_name, message.from.as_deref());
+    let to = if message.from_me {
+        // A group message is addressed to everyone else in the chat
+        if message.participants.len() > 1 {
+            message.participants.iter().map(|p| mailbox(None, Some(p))).collect()
+        } else {
+            vec![mailbox(message.to_name.as_deref(), message.to.as_deref())]
+        }
+    } else {
+        vec![mailbox(Some("Me"), message.to.as_deref())]
+    };
+
+    let _ = writeln!(
+        out,
+        "From {} {}",
+        address(message.from.as_deref()),
+        message.date.with_timezone(&Utc).format("%a %b %e %H:%M:%S %Y")
+    );
+    let _ = writeln!(out, "From: {}", from);
+    let _ = writeln!(out, "To: {}", to.join(", "));
+    let _ = writeln!(out, "Date: {}", message.date.to_rfc2822());
+    let _ = writeln!(out, "Subject: {}", encode_header(&conversation.name));
+    let _ = writeln!(out, "Message-ID: {}", message_id(message.id));
+    if let Some(reply_to) = message.reply_to_id {
+        let _ = writeln!(out, "In-Reply-To: {}", message_id(reply_to));
+    }
+    let _ = writeln!(out, "MIME-Version: 1.0");
+
+    let text = message.text.as_deref().map(escape_from_lines).unwrap_or_default();
+    if message.attachments.is_empty() {
+        let _ = write!(out, "Content-Type: text/plain; charset=utf-8\nContent-Transfer-Encoding: 8bit\n\n{}", text);
+    } else {
+        let boundary = format!("imessage-{}", message.id);
+        let _ = write!(out, "Content-Type: multipart/mixed; boundary=\"{}\"\n\n", boundary);
+        if !text.is_empty() {
+            let _ = write!(
+                out,
+                "--{}\nContent-Type: text/plain; charset=utf-8\nContent-Transfer-Encoding: 8bit\n\n{}",
+                boundary, text
+            );
+        }
+        for attachment in &message.attachments {
+            out.push_str(&attachment_part(attachment, &boundary));
+        }
+        let _ = writeln!(out, "--{}--", boundary);
+    }
+    out.push('\n');
+}
+
+/// Render a conversation as an mbox mailbox with one email per message, for
+/// importing into mail clients like Thunderbird
+pub fn render(conversation: &Conversation) -> Result<String, AppError> {
+    let mut mbox = String::new();
+    for message in &conversation.messages {
+        render_message(conversation, message, &mut mbox);
+    }
+    Ok(mbox)
+}
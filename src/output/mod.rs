@@ -0,0 +1,433 @@
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::attachments::AttachmentEntry;
+use crate::error::AppError;
+use crate::export::MessageRecord;
+use spill::Spill;
+use writer::{CsvWriter, JsonWriter, NdjsonWriter, Writer};
+
+pub mod html;
+pub mod markdown;
+pub mod matrix;
+pub mod mbox;
+pub mod parquet;
+pub mod pdf;
+pub mod signal;
+pub mod spill;
+pub mod split;
+pub mod sqlite;
+pub mod telegram;
+pub mod writer;
+
+/// Version of the `--format json` layout, bumped whenever fields change in a
+/// way readers would notice
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Columns written to CSV when `--columns` isn't given
+pub const DEFAULT_CSV_COLUMNS: &[&str] = &["id", "date", "from", "to", "from_me", "text"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// A single JSON array
+    #[default]
+    Json,
+    /// Comma-separated values with a header row
+    Csv,
+    /// One JSON object per line, written as messages are read
+    Ndjson,
+    /// One HTML transcript per conversation, written into the output directory
+    Html,
+    /// One Markdown transcript per conversation, written into the output directory
+    Markdown,
+    /// One mbox mailbox per conversation, an email per message with its
+    /// attachments, written into the output directory
+    Mbox,
+    /// One JSON file per conversation of Matrix room events, for importing
+    /// into a Matrix room with an appservice
+    Matrix,
+    /// One JSON file per conversation in Telegram Desktop's "Export chat
+    /// history" layout, written into the output directory
+    TelegramJson,
+    /// One JSON file per conversation of Signal Desktop's conversation and
+    /// message attributes, written into the output directory
+    SignalDesktop,
+    /// One paginated PDF per conversation, with message bubbles, timestamps
+    /// and embedded images, written into the output directory
+    Pdf,
+    /// A standalone SQLite database with messages, handles and chats tables
+    Sqlite,
+    /// Parquet with a fixed id/date/text/from/to/chat_id/is_from_me schema
+    Parquet,
+}
+
+impl OutputFormat {
+    /// Whether the output path is a directory of per-conversation files
+    pub fn is_per_conversation(&self) -> bool {
+        self.conversation_renderer().is_some() || matches!(self, OutputFormat::Matrix | OutputFormat::Pdf)
+    }
+
+    /// File extension for output in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Json => "json",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Html => "html",
+            OutputFormat::Markdown => "md",
+            OutputFormat::Mbox => "mbox",
+            OutputFormat::Matrix => "json",
+            OutputFormat::TelegramJson => "json",
+            OutputFormat::SignalDesktop => "json",
+            OutputFormat::Pdf => "pdf",
+            OutputFormat::Sqlite => "sqlite",
+            OutputFormat::Parquet => "parquet",
+        }
+    }
+
+    /// A streaming writer for formats that are a single stream of records;
+    /// `columns` is only used by CSV
+    pub fn writer<'a, W: Write + 'a>(&self, out: W, columns: &[String]) -> Option<Box<dyn Writer + 'a>> {
+        match self {
+            OutputFormat::Json => Some(Box::new(JsonWriter::new(out))),
+            OutputFormat::Csv => Some(Box::new(CsvWriter::new(out, columns))),
+            OutputFormat::Ndjson => Some(Box::new(NdjsonWriter::new(out))),
+            _ => None,
+        }
+    }
+
+    /// File extension and renderer for per-conversation formats
+    pub fn conversation_renderer(&self) -> Option<(&'static str, Renderer)> {
+        match self {
+            OutputFormat::Html => Some((self.extension(), html::render)),
+            OutputFormat::Markdown => Some((self.extension(), markdown::render)),
+            OutputFormat::Mbox => Some((self.extension(), mbox::render)),
+            OutputFormat::TelegramJson => Some((self.extension(), telegram::render)),
+            OutputFormat::SignalDesktop => Some((self.extension(), signal::render)),
+            _ => None,
+        }
+    }
+}
+
+/// Renders one conversation as the contents of its file
+pub type Renderer = fn(&Conversation) -> Result<String, AppError>;
+
+/// The messages of one chat, in date order
+#[derive(Debug, Clone)]
+pub struct Conversation {
+    pub chat_id: Option<i32>,
+    /// The other person's handle for messages that aren't in a chat, which
+    /// they're grouped by instead; empty for chats
+    pub handle: String,
+    /// Display name of the chat, or of the other person in a one-on-one chat
+    pub name: String,
+    pub messages: Vec<MessageRecord>,
+}
+
+impl Conversation {
+    /// The conversation named from its messages: the other person in a
+    /// one-on-one chat, else the chat's name, else the handle it's keyed by
+    fn from_messages(chat_id: Option<i32>, handle: String, messages: Vec<MessageRecord>) -> Self {
+        let is_group = messages.iter().any(|m| m.participants.len() > 1);
+        let contact_name =
+            messages.iter().find_map(|m| if m.from_me { m.to_name.clone() } else { m.from_name.clone() });
+        let chat_name = messages.iter().find_map(|m| m.chat_name.clone());
+        let name = match (is_group, contact_name, chat_name) {
+            (false, Some(contact), _) => contact,
+            (_, _, Some(chat)) => chat,
+            _ if !handle.is_empty() => handle.clone(),
+            _ => "Unknown".to_string(),
+        };
+        Conversation { chat_id, handle, name, messages }
+    }
+
+    /// A filesystem-safe name for this conversation's file, unique to it:
+    /// ending in the chat's ID, or for messages outside a chat, a hash of
+    /// the handle, as two people can have the same name
+    pub fn file_name(&self, extension: &str) -> String {
+        let safe: String = self
+            .name
+            .chars()
+            .map(|c| if c.is_alphanumeric() || "+-_ ".contains(c) { c } else { '_' })
+            .collect();
+        match self.chat_id {
+            Some(id) => format!("{}-{}.{}", safe.trim(), id, extension),
+            None => {
+                let digest = Sha256::digest(self.handle.as_bytes());
+                let hash: String = digest[..4].iter().map(|b| format!("{:02x}", b)).collect();
+                format!("{}-{}.{}", safe.trim(), hash, extension)
+            }
+        }
+    }
+}
+
+/// A message and the inline replies to it
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct Thread {
+    pub root_id: i64,
+    pub messages: Vec<MessageRecord>,
+}
+
+/// Group records by thread, keeping threads in the order they first appear.
+/// Messages that aren't part of a thread are a thread of their own. With
+/// `max_memory` bytes, records past that are spilled to disk and each thread
+/// is read back as it's taken.
+pub fn group_threads<I>(
+    records: I,
+    max_memory: Option<usize>,
+) -> Result<impl Iterator<Item = Result<Thread, AppError>>, AppError>
+where
+    I: IntoIterator<Item = Result<MessageRecord, AppError>>,
+{
+    let mut threads = Spill::new(max_memory);
+    let mut index: HashMap<i64, usize> = HashMap::new();
+    for record in records {
+        let record = record?;
+        let root_id = record.thread_root_id.unwrap_or(record.id);
+        let next = index.len();
+        threads.push(*index.entry(root_id).or_insert(next), record)?;
+    }
+    Ok(threads.into_groups().map(|thread| {
+        let (_, messages) = thread?;
+        let first = &messages[0];
+        Ok(Thread { root_id: first.thread_root_id.unwrap_or(first.id), messages })
+    }))
+}
+
+/// The name shown for whoever sent a message
+pub fn sender_label(record: &MessageRecord) -> String {
+    if record.from_me {
+        return "Me".to_string();
+    }
+    record
+        .from_name
+        .clone()
+        .or_else(|| record.from.clone())
+        .unwrap_or_else(|| "Unknown".to_string())
+}
+
+/// Split records into conversations, keyed by chat (or by the other handle
+/// for messages that aren't in a chat). With `max_memory` bytes, records
+/// past that are spilled to disk and each conversation is read back as it's
+/// taken.
+pub fn group_conversations<I>(
+    records: I,
+    max_memory: Option<usize>,
+) -> Result<impl Iterator<Item = Result<Conversation, AppError>>, AppError>
+where
+    I: IntoIterator<Item = Result<MessageRecord, AppError>>,
+{
+    let mut chats = Spill::new(max_memory);
+    for record in records {
+        let record = record?;
+        let other = if record.from_me { &record.to } else { &record.from };
+        let key = match record.chat_id {
+            Some(id) => (Some(id), String::new()),
+            None => (None, other.clone().unwrap_or_default()),
+        };
+        chats.push(key, record)?;
+    }
+    Ok(chats.into_groups().map(|chat| {
+        let ((chat_id, handle), messages) = chat?;
+        Ok(Conversation::from_messages(chat_id, handle, messages))
+    }))
+}
+
+/// Write each conversation to its own file in `dir` using `render`, one at a time
+pub fn write_conversations<I, F, C>(
+    dir: &Path,
+    conversations: I,
+    extension: &str,
+    render: F,
+) -> Result<Vec<PathBuf>, AppError>
+where
+    I: IntoIterator<Item = Result<Conversation, AppError>>,
+    F: Fn(&Conversation) -> Result<C, AppError>,
+    C: AsRef<[u8]>,
+{
+    fs::create_dir_all(dir)?;
+    let mut written = Vec::new();
+    for conversation in conversations {
+        let conversation = conversation?;
+        let path = dir.join(conversation.file_name(extension));
+        fs::write(&path, render(&conversation)?)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Render a field of a serialized record as a CSV cell
+fn csv_cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(other) => other.to_string(),
+    }
+}
+
+/// What `--format json` writes: the messages (or threads, with `--threads`)
+/// along with the schema version they follow
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct Envelope<'a> {
+    pub schema_version: u32,
+    pub exported_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub messages: Option<&'a [MessageRecord]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threads: Option<&'a [Thread]>,
+    /// With `--attachments-only`, every attachment instead of the messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attachments: Option<&'a [AttachmentEntry]>,
+}
+
+impl<'a> Envelope<'a> {
+    fn new() -> Self {
+        Envelope {
+            schema_version: SCHEMA_VERSION,
+            exported_at: Utc::now(),
+            messages: None,
+            threads: None,
+            attachments: None,
+        }
+    }
+}
+
+/// JSON Schema for `--format json` output, or for a single message when
+/// `record` is set (a line of NDJSON)
+pub fn json_schema(record: bool) -> Value {
+    let schema = if record { schemars::schema_for!(MessageRecord) } else { schemars::schema_for!(Envelope) };
+    schema.to_value()
+}
+
+/// Write records as JSON, all in one array inside an [`Envelope`]
+pub fn write_json<W: Write>(out: W, records: &[MessageRecord]) -> Result<(), AppError> {
+    let mut writer = JsonWriter::new(out);
+    for record in records {
+        writer.write_record(record)?;
+    }
+    writer.finish()
+}
+
+/// Write threads as JSON in the layout of an [`Envelope`], a thread at a time
+pub fn write_json_threads<W, I>(mut out: W, threads: I) -> Result<(), AppError>
+where
+    W: Write,
+    I: IntoIterator<Item = Result<Thread, AppError>>,
+{
+    write!(
+        out,
+        "{{\"schema_version\":{},\"exported_at\":{},\"threads\":[",
+        SCHEMA_VERSION,
+        serde_json::to_string(&Utc::now())?
+    )?;
+    for (i, thread) in threads.into_iter().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        serde_json::to_writer(&mut out, &thread?)?;
+    }
+    out.write_all(b"]}")?;
+    Ok(())
+}
+
+/// Write the attachments of `records` as a JSON array inside an [`Envelope`],
+/// NDJSON or CSV, one attachment per entry, oldest message first
+pub fn write_attachments<W, I>(mut out: W, records: I, format: OutputFormat) -> Result<(), AppError>
+where
+    W: Write,
+    I: IntoIterator<Item = Result<MessageRecord, AppError>>,
+{
+    let entries = records.into_iter().map(|record| record.map(|r| AttachmentEntry::from_record(&r)));
+    match format {
+        OutputFormat::Json => {
+            let mut attachments = Vec::new();
+            for entry in entries {
+                attachments.extend(entry?);
+            }
+            serde_json::to_writer(out, &Envelope { attachments: Some(&attachments), ..Envelope::new() })?;
+        }
+        OutputFormat::Ndjson => {
+            for entry in entries {
+                for attachment in entry? {
+                    serde_json::to_writer(&mut out, &attachment)?;
+                    out.write_all(b"\n")?;
+                }
+            }
+        }
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            writer.write_record(AttachmentEntry::FIELDS)?;
+            for entry in entries {
+                for attachment in entry? {
+                    let value = serde_json::to_value(attachment)?;
+                    writer.write_record(AttachmentEntry::FIELDS.iter().map(|field| csv_cell(value.get(field))))?;
+                }
+            }
+            writer.flush()?;
+        }
+        _ => return Err(AppError::Args("--attachments-only works with --format json, csv or ndjson".to_string())),
+    }
+    Ok(())
+}
+
+/// Write records as newline-delimited JSON, streaming each record as it is read
+pub fn write_ndjson<W, I>(out: W, records: I) -> Result<(), AppError>
+where
+    W: Write,
+    I: IntoIterator<Item = Result<MessageRecord, AppError>>,
+{
+    writer::write_records(&mut NdjsonWriter::new(out), records)
+}
+
+/// Write records as CSV with the given column set, streaming each record as it is read
+pub fn write_csv<W, I>(out: W, records: I, columns: &[String]) -> Result<(), AppError>
+where
+    W: Write,
+    I: IntoIterator<Item = Result<MessageRecord, AppError>>,
+{
+    writer::write_records(&mut CsvWriter::new(out, columns), records)
+}
+
+/// Check that every requested column is a field of `MessageRecord`
+pub fn validate_columns(columns: &[String]) -> Result<(), AppError> {
+    match columns.iter().find(|c| !MessageRecord::FIELDS.contains(&c.as_str())) {
+        Some(unknown) => Err(AppError::Args(format!(
+            "Unknown column: {}. Expected one of {}",
+            unknown,
+            MessageRecord::FIELDS.join(", ")
+        ))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation(chat_id: Option<i32>, handle: &str, name: &str) -> Conversation {
+        Conversation { chat_id, handle: handle.to_string(), name: name.to_string(), messages: Vec::new() }
+    }
+
+    #[test]
+    fn chats_are_named_by_id() {
+        assert_eq!(conversation(Some(7), "", "Book club").file_name("html"), "Book club-7.html");
+        assert_eq!(conversation(Some(8), "", "Mom & Dad / 🎉").file_name("md"), "Mom _ Dad _ _-8.md");
+    }
+
+    #[test]
+    fn people_with_the_same_name_get_their_own_files() {
+        let phone = conversation(None, "+15551234567", "Sam");
+        let email = conversation(None, "sam@example.com", "Sam");
+        assert_ne!(phone.file_name("html"), email.file_name("html"));
+        assert_eq!(phone.file_name("html"), conversation(None, "+15551234567", "Sam").file_name("html"));
+        assert!(phone.file_name("html").starts_with("Sam-"));
+    }
+}
@@ -0,0 +1,104 @@
+use arrow_array::{
+    builder::{BooleanBuilder, Int32Builder, Int64Builder, StringBuilder, TimestampSecondBuilder},
+    ArrayRef, RecordBatch,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::error::AppError;
+use crate::export::MessageRecord;
+
+/// Rows buffered before each row group is written
+const BATCH_SIZE: usize = 8192;
+
+/// The columns of a Parquet export. Dates are UTC seconds.
+pub fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("date", DataType::Timestamp(TimeUnit::Second, Some("UTC".into())), false),
+        Field::new("text", DataType::Utf8, true),
+        Field::new("from", DataType::Utf8, true),
+        Field::new("to", DataType::Utf8, true),
+        Field::new("chat_id", DataType::Int32, true),
+        Field::new("is_from_me", DataType::Boolean, false),
+    ]))
+}
+
+/// Column builders for one batch of rows
+struct Columns {
+    id: Int64Builder,
+    date: TimestampSecondBuilder,
+    text: StringBuilder,
+    from: StringBuilder,
+    to: StringBuilder,
+    chat_id: Int32Builder,
+    is_from_me: BooleanBuilder,
+    len: usize,
+}
+
+impl Columns {
+    fn new() -> Self {
+        Columns {
+            id: Int64Builder::new(),
+            date: TimestampSecondBuilder::new().with_timezone("UTC"),
+            text: StringBuilder::new(),
+            from: StringBuilder::new(),
+            to: StringBuilder::new(),
+            chat_id: Int32Builder::new(),
+            is_from_me: BooleanBuilder::new(),
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, record: &MessageRecord) {
+        self.id.append_value(record.id);
+        self.date.append_value(record.date.timestamp());
+        self.text.append_option(record.text.as_deref());
+        self.from.append_option(record.from.as_deref());
+        self.to.append_option(record.to.as_deref());
+        self.chat_id.append_option(record.chat_id);
+        self.is_from_me.append_value(record.from_me);
+        self.len += 1;
+    }
+
+    /// Turn the buffered rows into a batch and start a new one
+    fn finish(&mut self, schema: &SchemaRef) -> Result<RecordBatch, AppError> {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.id.finish()),
+            Arc::new(self.date.finish()),
+            Arc::new(self.text.finish()),
+            Arc::new(self.from.finish()),
+            Arc::new(self.to.finish()),
+            Arc::new(self.chat_id.finish()),
+            Arc::new(self.is_from_me.finish()),
+        ];
+        self.len = 0;
+        RecordBatch::try_new(schema.clone(), columns).map_err(|e| AppError::Parquet(e.to_string()))
+    }
+}
+
+/// Write records to a Snappy-compressed Parquet file at `path`
+pub fn write_parquet<I>(path: &Path, records: I) -> Result<(), AppError>
+where
+    I: IntoIterator<Item = Result<MessageRecord, AppError>>,
+{
+    let schema = schema();
+    let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+    let mut writer = ArrowWriter::try_new(File::create(path)?, schema.clone(), Some(properties))?;
+
+    let mut columns = Columns::new();
+    for record in records {
+        columns.push(&record?);
+        if columns.len == BATCH_SIZE {
+            writer.write(&columns.finish(&schema)?)?;
+        }
+    }
+    if columns.len > 0 {
+        writer.write(&columns.finish(&schema)?)?;
+    }
+    writer.close()?;
+    Ok(())
+}
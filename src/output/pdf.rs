@@ -0,0 +1,438 @@
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::codecs::jpeg::JpegEncoder;
+use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref, Str, TextStr};
+use std::io::Write;
+use std::path::Path;
+use tracing::debug;
+
+use super::{sender_label, Conversation};
+use crate::attachments::AttachmentRecord;
+use crate::error::AppError;
+use crate::export::MessageRecord;
+use crate::thumbnail;
+
+/// US Letter, in points
+const PAGE_WIDTH: f32 = 612.0;
+const PAGE_HEIGHT: f32 = 792.0;
+const MARGIN: f32 = 54.0;
+/// Room kept at the bottom of each page for the page number
+const FOOTER: f32 = 18.0;
+
+const FONT_SIZE: f32 = 10.5;
+const LEADING: f32 = 13.5;
+const SMALL_FONT_SIZE: f32 = 7.5;
+const SMALL_LEADING: f32 = 10.0;
+const TITLE_FONT_SIZE: f32 = 15.0;
+
+const BUBBLE_PADDING_X: f32 = 9.0;
+const BUBBLE_PADDING_Y: f32 = 6.0;
+const BUBBLE_RADIUS: f32 = 9.0;
+const MESSAGE_GAP: f32 = 9.0;
+
+const MAX_IMAGE_WIDTH: f32 = 230.0;
+const MAX_IMAGE_HEIGHT: f32 = 260.0;
+/// Larger images are scaled down to this many pixels on their long side
+const MAX_IMAGE_PIXELS: u32 = 1600;
+
+const REGULAR: Name = Name(b"F1");
+const BOLD: Name = Name(b"F2");
+
+const MY_BUBBLE: (f32, f32, f32) = (0.043, 0.518, 1.0);
+const THEIR_BUBBLE: (f32, f32, f32) = (0.898, 0.898, 0.918);
+
+fn content_width() -> f32 {
+    PAGE_WIDTH - 2.0 * MARGIN
+}
+
+fn max_bubble_width() -> f32 {
+    content_width() * 0.65
+}
+
+/// Widths of the printable ASCII characters in Helvetica, per 1000 units of font size
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556, 556, 556, 556, 556,
+    556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833,
+    722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500, 556,
+    556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334,
+    260, 334, 584,
+];
+
+/// How wide `text` is in Helvetica at `size`. Bold is a little wider, so
+/// it's measured with some slack.
+fn text_width(text: &str, size: f32, bold: bool) -> f32 {
+    let units: u32 = text
+        .chars()
+        .map(|c| match c as u32 {
+            code @ 0x20..=0x7E => u32::from(HELVETICA_WIDTHS[(code - 0x20) as usize]),
+            _ => 556,
+        })
+        .sum();
+    units as f32 / 1000.0 * size * if bold { 1.08 } else { 1.0 }
+}
+
+/// `text` in WinAnsiEncoding, which the built-in PDF fonts use. It covers
+/// Latin-1 and typographic punctuation; anything else, emoji included,
+/// comes out as `?`.
+fn win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        // Variation selectors, joiners and skin tones only make sense inside an emoji
+        .filter(|c| !matches!(c, '\u{FE0E}' | '\u{FE0F}' | '\u{200D}' | '\u{1F3FB}'..='\u{1F3FF}'))
+        .map(|c| match c {
+            ' '..='~' | '\u{A0}'..='\u{FF}' => c as u8,
+            '€' => 0x80,
+            '…' => 0x85,
+            '‘' => 0x91,
+            '’' => 0x92,
+            '“' => 0x93,
+            '”' => 0x94,
+            '•' => 0x95,
+            '–' => 0x96,
+            '—' => 0x97,
+            '\t' => b' ',
+            _ => b'?',
+        })
+        .collect()
+}
+
+/// Break `text` into lines no wider than `width`, keeping its own line breaks
+fn wrap(text: &str, size: f32, width: f32) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        for word in paragraph.split(' ') {
+            let candidate = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+            if text_width(&candidate, size, false) <= width {
+                line = candidate;
+                continue;
+            }
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            // A word too long for a line on its own, like a URL, is broken anywhere
+            for c in word.chars() {
+                line.push(c);
+                if text_width(&line, size, false) > width {
+                    line.pop();
+                    lines.push(std::mem::replace(&mut line, c.to_string()));
+                }
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// An image re-encoded as JPEG for embedding
+struct EmbeddedImage {
+    jpeg: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+/// Decode an image attachment, turned upright, scaled down and flattened
+/// onto white. `None` for formats that can't be decoded here, like HEIC.
+fn load_image(path: &str) -> Option<EmbeddedImage> {
+    let load = || -> Result<EmbeddedImage, image::ImageError> {
+        let mut image = thumbnail::load_upright(Path::new(path))?;
+        if image.width().max(image.height()) > MAX_IMAGE_PIXELS {
+            image = image.thumbnail(MAX_IMAGE_PIXELS, MAX_IMAGE_PIXELS);
+        }
+        let flattened = thumbnail::flatten(&image.to_rgba8());
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, 85).encode_image(&flattened)?;
+        Ok(EmbeddedImage { jpeg, width: flattened.width(), height: flattened.height() })
+    };
+    load().map_err(|e| debug!(path, error = %e, "couldn't embed image in PDF")).ok()
+}
+
+struct Page {
+    content: Content,
+    /// Indexes of the images drawn on this page
+    images: Vec<usize>,
+}
+
+/// Lays messages out top to bottom, starting new pages as they fill
+struct Layout {
+    pages: Vec<Page>,
+    images: Vec<EmbeddedImage>,
+    /// Distance of the next thing drawn from the bottom of the page
+    y: f32,
+}
+
+impl Layout {
+    fn new() -> Self {
+        let mut layout = Layout { pages: Vec::new(), images: Vec::new(), y: 0.0 };
+        layout.new_page();
+        layout
+    }
+
+    fn new_page(&mut self) {
+        self.pages.push(Page { content: Content::new(), images: Vec::new() });
+        self.y = PAGE_HEIGHT - MARGIN;
+    }
+
+    fn content(&mut self) -> &mut Content {
+        &mut self.pages.last_mut().expect("layout always has a page").content
+    }
+
+    fn room(&self) -> f32 {
+        self.y - MARGIN - FOOTER
+    }
+
+    /// Start a new page unless `height` fits on this one
+    fn make_room(&mut self, height: f32) {
+        if height > self.room() {
+            self.new_page();
+        }
+    }
+
+    fn text(&mut self, font: Name, size: f32, gray: f32, x: f32, y: f32, text: &str) {
+        let content = self.content();
+        content.begin_text();
+        content.set_font(font, size);
+        content.set_fill_gray(gray);
+        content.next_line(x, y);
+        content.show(Str(&win_ansi(text)));
+        content.end_text();
+    }
+
+    fn title(&mut self, conversation: &Conversation) {
+        let first = conversation.messages.first().map(|m| m.date.format("%b %-d, %Y").to_string());
+        let last = conversation.messages.last().map(|m| m.date.format("%b %-d, %Y").to_string());
+        self.y -= TITLE_FONT_SIZE;
+        let width = text_width(&conversation.name, TITLE_FONT_SIZE, true);
+        self.text(BOLD, TITLE_FONT_SIZE, 0.2, (PAGE_WIDTH - width) / 2.0, self.y, &conversation.name);
+        let mut details = format!("{} messages", conversation.messages.len());
+        if let (Some(first), Some(last)) = (first, last) {
+            details.push_str(&format!(", {} – {}", first, last));
+        }
+        self.y -= LEADING + 2.0;
+        let width = text_width(&details, SMALL_FONT_SIZE, false);
+        self.text(REGULAR, SMALL_FONT_SIZE, 0.45, (PAGE_WIDTH - width) / 2.0, self.y, &details);
+        self.y -= LEADING * 2.0;
+    }
+
+    /// The x coordinate something `width` wide starts at, on the sender's side
+    fn aligned(from_me: bool, width: f32) -> f32 {
+        if from_me {
+            PAGE_WIDTH - MARGIN - width
+        } else {
+            MARGIN
+        }
+    }
+
+    /// A small gray line beside the bubbles, like the sender and time
+    fn caption(&mut self, from_me: bool, text: &str) {
+        let width = text_width(text, SMALL_FONT_SIZE, false);
+        self.y -= SMALL_LEADING;
+        let x = Layout::aligned(from_me, width + 2.0 * BUBBLE_PADDING_X) + BUBBLE_PADDING_X;
+        self.text(REGULAR, SMALL_FONT_SIZE, 0.5, x, self.y + 2.0, text);
+    }
+
+    /// A bubble of text, split across pages if it's longer than one
+    fn bubble(&mut self, from_me: bool, lines: &[String]) {
+        let widest = lines.iter().map(|l| text_width(l, FONT_SIZE, false)).fold(0.0, f32::max);
+        let width = widest + 2.0 * BUBBLE_PADDING_X;
+        let x = Layout::aligned(from_me, width);
+        let mut rest = lines;
+        while !rest.is_empty() {
+            let fits = ((self.room() - 2.0 * BUBBLE_PADDING_Y) / LEADING).floor().max(0.0) as usize;
+            if fits == 0 {
+                self.new_page();
+                continue;
+            }
+            let (chunk, remaining) = rest.split_at(fits.min(rest.len()));
+            rest = remaining;
+            let height = chunk.len() as f32 * LEADING + 2.0 * BUBBLE_PADDING_Y;
+            let (r, g, b) = if from_me { MY_BUBBLE } else { THEIR_BUBBLE };
+            let bottom = self.y - height;
+            let content = self.content();
+            content.set_fill_rgb(r, g, b);
+            rounded_rect(content, x, bottom, width, height, BUBBLE_RADIUS);
+            content.fill_nonzero();
+            let text_gray = if from_me { 1.0 } else { 0.0 };
+            let mut baseline = self.y - BUBBLE_PADDING_Y - FONT_SIZE;
+            for line in chunk {
+                self.text(REGULAR, FONT_SIZE, text_gray, x + BUBBLE_PADDING_X, baseline, line);
+                baseline -= LEADING;
+            }
+            self.y -= height;
+        }
+        self.y -= 3.0;
+    }
+
+    fn image(&mut self, from_me: bool, image: EmbeddedImage) {
+        let scale = (MAX_IMAGE_WIDTH / image.width as f32).min(MAX_IMAGE_HEIGHT / image.height as f32).min(1.0);
+        let (width, height) = (image.width as f32 * scale, image.height as f32 * scale);
+        self.make_room(height);
+        let x = Layout::aligned(from_me, width);
+        let index = self.images.len();
+        self.images.push(image);
+        let page = self.pages.last_mut().expect("layout always has a page");
+        page.images.push(index);
+        page.content.save_state();
+        page.content.transform([width, 0.0, 0.0, height, x, self.y - height]);
+        page.content.x_object(Name(image_name(index).as_bytes()));
+        page.content.restore_state();
+        self.y -= height + 3.0;
+    }
+
+    fn message(&mut self, message: &MessageRecord) {
+        let caption = format!("{} · {}", sender_label(message), message.date.format("%b %-d, %Y %-I:%M %p"));
+        let mut lines: Vec<String> = Vec::new();
+        let mut images = Vec::new();
+        for attachment in &message.attachments {
+            match image_attachment(attachment) {
+                Some(image) => images.push(image),
+                None => lines.push(format!("[{}]", attachment_label(attachment))),
+            }
+        }
+        if let Some(text) = message.text.as_deref().filter(|t| !t.is_empty()) {
+            lines.extend(wrap(text, FONT_SIZE, max_bubble_width() - 2.0 * BUBBLE_PADDING_X));
+        }
+
+        // Keep the caption with at least the start of what it's captioning
+        let first_height = match (lines.is_empty(), images.first()) {
+            (false, _) | (true, None) => LEADING + 2.0 * BUBBLE_PADDING_Y,
+            (true, Some(image)) => MAX_IMAGE_HEIGHT.min(image.height as f32),
+        };
+        self.make_room(SMALL_LEADING + first_height);
+        self.caption(message.from_me, &caption);
+        for image in images {
+            self.image(message.from_me, image);
+        }
+        if !lines.is_empty() {
+            self.bubble(message.from_me, &lines);
+        }
+        if !message.reactions.is_empty() {
+            let reactions: Vec<String> = message
+                .reactions
+                .iter()
+                .map(|r| format!("{} {}", r.kind, r.from.as_deref().filter(|_| !r.from_me).unwrap_or("by me")))
+                .collect();
+            self.make_room(SMALL_LEADING);
+            self.caption(message.from_me, &reactions.join(", "));
+        }
+        self.y -= MESSAGE_GAP;
+    }
+
+    /// Number every page, now that it's known how many there are
+    fn footers(&mut self, name: &str) {
+        let count = self.pages.len();
+        for i in 0..count {
+            let footer = format!("{} — page {} of {}", name, i + 1, count);
+            let width = text_width(&footer, SMALL_FONT_SIZE, false);
+            let content = &mut self.pages[i].content;
+            content.begin_text();
+            content.set_font(REGULAR, SMALL_FONT_SIZE);
+            content.set_fill_gray(0.5);
+            content.next_line((PAGE_WIDTH - width) / 2.0, MARGIN - FOOTER);
+            content.show(Str(&win_ansi(&footer)));
+            content.end_text();
+        }
+    }
+}
+
+fn image_name(index: usize) -> String {
+    format!("Im{}", index + 1)
+}
+
+fn attachment_label(attachment: &AttachmentRecord) -> String {
+    let name = attachment.filename.as_deref().unwrap_or("attachment");
+    if attachment.path.is_none() {
+        format!("{} not on disk", name)
+    } else {
+        format!("attachment: {}", name)
+    }
+}
+
+fn image_attachment(attachment: &AttachmentRecord) -> Option<EmbeddedImage> {
+    if !attachment.mime_type.as_deref().is_some_and(|m| m.starts_with("image/")) {
+        return None;
+    }
+    load_image(attachment.path.as_deref()?)
+}
+
+/// Add a rectangle with rounded corners to the current path
+fn rounded_rect(content: &mut Content, x: f32, y: f32, width: f32, height: f32, radius: f32) {
+    let r = radius.min(width / 2.0).min(height / 2.0);
+    // Distance of the control points that make a quarter circle
+    let k = r * 0.552_285;
+    let (left, right, bottom, top) = (x, x + width, y, y + height);
+    content.move_to(left + r, bottom);
+    content.line_to(right - r, bottom);
+    content.cubic_to(right - r + k, bottom, right, bottom + r - k, right, bottom + r);
+    content.line_to(right, top - r);
+    content.cubic_to(right, top - r + k, right - r + k, top, right - r, top);
+    content.line_to(left + r, top);
+    content.cubic_to(left + r - k, top, left, top - r + k, left, top - r);
+    content.line_to(left, bottom + r);
+    content.cubic_to(left, bottom + r - k, left + r - k, bottom, left + r, bottom);
+    content.close_path();
+}
+
+fn deflate(data: &[u8]) -> Result<Vec<u8>, AppError> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Render a conversation as a paginated PDF: a bubble per message under its
+/// sender and time, with images embedded and other attachments named, and
+/// every page numbered
+pub fn render(conversation: &Conversation) -> Result<Vec<u8>, AppError> {
+    let mut layout = Layout::new();
+    layout.title(conversation);
+    for message in &conversation.messages {
+        layout.message(message);
+    }
+    layout.footers(&conversation.name);
+
+    let mut pdf = Pdf::new();
+    let catalog_id = Ref::new(1);
+    let tree_id = Ref::new(2);
+    let regular_id = Ref::new(3);
+    let bold_id = Ref::new(4);
+    let info_id = Ref::new(5);
+    let mut next_id = Ref::new(6);
+    let mut alloc = || next_id.bump();
+
+    let page_ids: Vec<(Ref, Ref)> = layout.pages.iter().map(|_| (alloc(), alloc())).collect();
+    let image_ids: Vec<Ref> = layout.images.iter().map(|_| alloc()).collect();
+
+    pdf.catalog(catalog_id).pages(tree_id);
+    pdf.pages(tree_id).kids(page_ids.iter().map(|(page, _)| *page)).count(page_ids.len() as i32);
+    pdf.type1_font(regular_id).base_font(Name(b"Helvetica")).encoding_predefined(Name(b"WinAnsiEncoding"));
+    pdf.type1_font(bold_id).base_font(Name(b"Helvetica-Bold")).encoding_predefined(Name(b"WinAnsiEncoding"));
+    pdf.document_info(info_id).title(TextStr(&conversation.name)).producer(TextStr("imessage-blaster"));
+
+    for (page, (page_id, content_id)) in layout.pages.into_iter().zip(page_ids) {
+        let mut writer = pdf.page(page_id);
+        writer.media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT));
+        writer.parent(tree_id);
+        writer.contents(content_id);
+        let mut resources = writer.resources();
+        resources.fonts().pair(REGULAR, regular_id).pair(BOLD, bold_id);
+        let names: Vec<String> = page.images.iter().map(|&i| image_name(i)).collect();
+        let mut x_objects = resources.x_objects();
+        for (&i, name) in page.images.iter().zip(&names) {
+            x_objects.pair(Name(name.as_bytes()), image_ids[i]);
+        }
+        x_objects.finish();
+        resources.finish();
+        writer.finish();
+        pdf.stream(content_id, &deflate(&page.content.finish())?).filter(Filter::FlateDecode);
+    }
+
+    for (image, id) in layout.images.iter().zip(image_ids) {
+        let mut xobject = pdf.image_xobject(id, &image.jpeg);
+        xobject.filter(Filter::DctDecode);
+        xobject.width(image.width as i32);
+        xobject.height(image.height as i32);
+        xobject.color_space().device_rgb();
+        xobject.bits_per_component(8);
+    }
+
+    Ok(pdf.finish())
+}
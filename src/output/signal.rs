@@ -0,0 +1,203 @@
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+
+use super::Conversation;
+use crate::contacts::handle_key;
+use crate::error::AppError;
+use crate::events::EventType;
+use crate::export::MessageRecord;
+
+/// Signal Desktop's `ReadStatus`
+const READ: u8 = 0;
+const UNREAD: u8 = 1;
+
+/// A stable UUID for `name`, as Signal Desktop keys messages, conversations
+/// and accounts by UUID: the first 16 bytes of its SHA-256, marked as a
+/// name-based UUID
+fn uuid(name: &str) -> String {
+    let mut bytes: [u8; 16] = Sha256::digest(name.as_bytes())[..16].try_into().expect("SHA-256 is 32 bytes");
+    bytes[6] = (bytes[6] & 0x0f) | 0x50;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// The Signal account ID standing in for a handle
+fn service_id(handle: Option<&str>) -> String {
+    uuid(&format!("imessage-handle:{}", handle_key(handle.unwrap_or("unknown"))))
+}
+
+/// Phone numbers are what Signal knows people by; emails have no equivalent
+fn e164(handle: &str) -> Option<&str> {
+    handle.starts_with('+').then_some(handle)
+}
+
+fn millis(date: &chrono::DateTime<chrono::FixedOffset>) -> i64 {
+    date.timestamp_millis()
+}
+
+/// A group event as the `group-v2-change` notification Signal shows for it
+fn group_change(record: &MessageRecord, event_type: EventType) -> Value {
+    let detail = match event_type {
+        EventType::NameChanged => json!({ "type": "title", "newTitle": record.event_name }),
+        EventType::ParticipantAdded => {
+            json!({ "type": "member-add", "aci": service_id(record.event_participant.as_deref()) })
+        }
+        EventType::ParticipantRemoved => {
+            json!({ "type": "member-remove", "aci": service_id(record.event_participant.as_deref()) })
+        }
+        EventType::ParticipantLeft => json!({ "type": "member-remove", "aci": service_id(record.from.as_deref()) }),
+        EventType::PhotoChanged => json!({ "type": "avatar", "removed": false }),
+        EventType::PhotoRemoved => json!({ "type": "avatar", "removed": true }),
+    };
+    json!({ "from": service_id(record.from.as_deref()), "details": [detail] })
+}
+
+fn message(record: &MessageRecord, conversation_id: &str, sent_at: &HashMap<i64, (i64, String)>) -> Value {
+    let timestamp = millis(&record.date);
+    let mut map = Map::new();
+    map.insert("id".to_string(), json!(uuid(&format!("imessage-message:{}", record.id))));
+    map.insert("conversationId".to_string(), json!(conversation_id));
+    map.insert("sent_at".to_string(), json!(timestamp));
+    map.insert("timestamp".to_string(), json!(timestamp));
+    map.insert("received_at".to_string(), json!(timestamp));
+
+    if let Some(event_type) = record.event_type {
+        map.insert("type".to_string(), json!("group-v2-change"));
+        map.insert("groupV2Change".to_string(), group_change(record, event_type));
+        return Value::Object(map);
+    }
+
+    if record.from_me {
+        map.insert("type".to_string(), json!("outgoing"));
+        let status = if record.date_read.is_some() {
+            "Read"
+        } else if record.date_delivered.is_some() {
+            "Delivered"
+        } else {
+            "Sent"
+        };
+        let updated = record.date_read.or(record.date_delivered).map_or(timestamp, |d| millis(&d));
+        map.insert(
+            "sendStateByConversationId".to_string(),
+            json!({ conversation_id: { "status": status, "updatedAt": updated } }),
+        );
+    } else {
+        map.insert("type".to_string(), json!("incoming"));
+        if let Some(source) = record.from.as_deref() {
+            map.insert("source".to_string(), json!(source));
+        }
+        map.insert("sourceServiceId".to_string(), json!(service_id(record.from.as_deref())));
+        map.insert("sourceDevice".to_string(), json!(1));
+        let status = if record.is_read { READ } else { UNREAD };
+        map.insert("readStatus".to_string(), json!(status));
+        map.insert("seenStatus".to_string(), json!(status));
+    }
+    if let Some(text) = record.text.as_deref().filter(|t| !t.is_empty()) {
+        map.insert("body".to_string(), json!(text));
+    }
+
+    if !record.attachments.is_empty() {
+        let attachments: Vec<Value> = record
+            .attachments
+            .iter()
+            .map(|a| {
+                json!({
+                    "contentType": a.mime_type.as_deref().unwrap_or("application/octet-stream"),
+                    "fileName": a.filename,
+                    "size": a.size,
+                    "path": a.path,
+                })
+            })
+            .collect();
+        map.insert("attachments".to_string(), json!(attachments));
+        map.insert("hasAttachments".to_string(), json!(1));
+    }
+    if let Some((quoted_at, author)) = record.reply_to_id.and_then(|id| sent_at.get(&id)) {
+        map.insert(
+            "quote".to_string(),
+            json!({
+                "id": quoted_at,
+                "authorAci": author,
+                "attachments": [],
+                "referencedMessageNotFound": false,
+                "isViewOnce": false,
+            }),
+        );
+    }
+    if !record.reactions.is_empty() {
+        let author = service_id(record.from.as_deref());
+        let reactions: Vec<Value> = record
+            .reactions
+            .iter()
+            .map(|r| {
+                json!({
+                    "emoji": r.as_emoji(),
+                    "fromId": service_id(r.from.as_deref()),
+                    "targetAuthorAci": author,
+                    "targetTimestamp": timestamp,
+                    "timestamp": millis(&r.date),
+                })
+            })
+            .collect();
+        map.insert("reactions".to_string(), json!(reactions));
+    }
+    if !record.edit_history.is_empty() {
+        let history: Vec<Value> = record
+            .edit_history
+            .iter()
+            .rev()
+            .map(|edit| json!({ "body": edit.text, "timestamp": millis(&edit.date) }))
+            .collect();
+        map.insert("editHistory".to_string(), json!(history));
+        if let Some(edited) = &record.date_edited {
+            map.insert("editMessageTimestamp".to_string(), json!(millis(edited)));
+        }
+    }
+    Value::Object(map)
+}
+
+/// Render a conversation as Signal Desktop stores it: the conversation's
+/// attributes and each message's, with the field names and values of its
+/// database, for migration tools that write them into Signal
+pub fn render(conversation: &Conversation) -> Result<String, AppError> {
+    let key = match conversation.chat_id {
+        Some(id) => format!("imessage-chat:{}", id),
+        None => format!("imessage-chat:{}", conversation.name),
+    };
+    let conversation_id = uuid(&key);
+    let members: BTreeSet<&str> =
+        conversation.messages.iter().flat_map(|m| m.participants.iter().map(String::as_str)).collect();
+    let is_group = members.len() > 1;
+
+    // Quotes point at the quoted message's sent time and author
+    let sent_at: HashMap<i64, (i64, String)> =
+        conversation.messages.iter().map(|m| (m.id, (millis(&m.date), service_id(m.from.as_deref())))).collect();
+    let messages: Vec<Value> = conversation.messages.iter().map(|m| message(m, &conversation_id, &sent_at)).collect();
+
+    let last = conversation.messages.last().map(|m| millis(&m.date));
+    let mut attributes = Map::new();
+    attributes.insert("id".to_string(), json!(conversation_id));
+    attributes.insert("name".to_string(), json!(conversation.name));
+    attributes.insert("active_at".to_string(), json!(last));
+    attributes.insert("timestamp".to_string(), json!(last));
+    if is_group {
+        attributes.insert("type".to_string(), json!("group"));
+        attributes.insert("groupId".to_string(), json!(uuid(&format!("{}:group", key))));
+        let members: Vec<Value> = members
+            .iter()
+            .map(|m| json!({ "aci": service_id(Some(m)), "e164": e164(m), "name": m }))
+            .collect();
+        attributes.insert("membersV2".to_string(), json!(members));
+    } else {
+        let handle = members.iter().next().copied();
+        attributes.insert("type".to_string(), json!("private"));
+        attributes.insert("serviceId".to_string(), json!(service_id(handle)));
+        if let Some(number) = handle.and_then(e164) {
+            attributes.insert("e164".to_string(), json!(number));
+        }
+    }
+    let export = json!({ "conversation": attributes, "messages": messages });
+    Ok(serde_json::to_string_pretty(&export)?)
+}
@@ -0,0 +1,154 @@
+use std::collections::{btree_map, BTreeMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::mem::size_of;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+use crate::attachments::AttachmentRecord;
+use crate::edits::EditRecord;
+use crate::error::AppError;
+use crate::export::MessageRecord;
+use crate::reactions::ReactionRecord;
+
+/// Convert `--max-memory` megabytes to the byte budget a [`Spill`] takes
+pub fn megabytes(mb: usize) -> usize {
+    mb.saturating_mul(1024 * 1024)
+}
+
+/// Roughly how much memory a record holds: the struct itself and what it owns
+fn footprint(record: &MessageRecord) -> usize {
+    let len = |s: &Option<String>| s.as_ref().map_or(0, String::len);
+    let strings = [
+        &record.text,
+        &record.from,
+        &record.to,
+        &record.from_name,
+        &record.to_name,
+        &record.chat_name,
+        &record.service,
+        &record.account,
+    ];
+    size_of::<MessageRecord>()
+        + strings.into_iter().map(len).sum::<usize>()
+        + record.participants.iter().chain(&record.links).map(String::len).sum::<usize>()
+        + record.attachments.len() * size_of::<AttachmentRecord>()
+        + record.reactions.len() * size_of::<ReactionRecord>()
+        + record.edit_history.iter().map(|e| size_of::<EditRecord>() + len(&e.text)).sum::<usize>()
+}
+
+/// The temporary directory spilled records go in, removed when dropped
+struct SpillDir {
+    path: PathBuf,
+    files: usize,
+}
+
+impl SpillDir {
+    fn create() -> Result<Self, AppError> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+        let path = std::env::temp_dir().join(format!("imessage-blaster-spill-{}-{}", std::process::id(), nanos));
+        fs::create_dir_all(&path)?;
+        Ok(SpillDir { path, files: 0 })
+    }
+
+    fn next_file(&mut self) -> PathBuf {
+        self.files += 1;
+        self.path.join(format!("{}.ndjson", self.files))
+    }
+}
+
+impl Drop for SpillDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[derive(Default)]
+struct Group {
+    records: Vec<MessageRecord>,
+    /// Where this group's earlier records were spilled, oldest first
+    file: Option<PathBuf>,
+}
+
+impl Group {
+    /// The spilled records followed by those still in memory
+    fn load(self) -> Result<Vec<MessageRecord>, AppError> {
+        let Some(path) = &self.file else {
+            return Ok(self.records);
+        };
+        let mut records = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            records.push(serde_json::from_str(&line?)?);
+        }
+        fs::remove_file(path)?;
+        records.extend(self.records);
+        Ok(records)
+    }
+}
+
+/// Records grouped by a key, held in memory up to a budget. Past it, every
+/// group's records are appended to a file of its own in a temporary
+/// directory, and read back one group at a time once all records are in.
+pub struct Spill<K> {
+    max_bytes: Option<usize>,
+    held: usize,
+    groups: BTreeMap<K, Group>,
+    dir: Option<SpillDir>,
+}
+
+impl<K: Ord> Spill<K> {
+    /// With no budget, every record stays in memory
+    pub fn new(max_bytes: Option<usize>) -> Self {
+        Spill { max_bytes, held: 0, groups: BTreeMap::new(), dir: None }
+    }
+
+    pub fn push(&mut self, key: K, record: MessageRecord) -> Result<(), AppError> {
+        self.held += footprint(&record);
+        self.groups.entry(key).or_default().records.push(record);
+        if self.max_bytes.is_some_and(|max| self.held > max) {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Move every record held in memory to disk
+    fn spill(&mut self) -> Result<(), AppError> {
+        let dir = match &mut self.dir {
+            Some(dir) => dir,
+            None => self.dir.insert(SpillDir::create()?),
+        };
+        debug!(bytes = self.held, groups = self.groups.len(), dir = %dir.path.display(), "spilling records to disk");
+        for group in self.groups.values_mut().filter(|g| !g.records.is_empty()) {
+            let path = group.file.get_or_insert_with(|| dir.next_file());
+            let mut out = BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?);
+            for record in group.records.drain(..) {
+                serde_json::to_writer(&mut out, &record)?;
+                out.write_all(b"\n")?;
+            }
+            out.flush()?;
+        }
+        self.held = 0;
+        Ok(())
+    }
+
+    /// Every group in key order, each with its records in the order they were pushed
+    pub fn into_groups(self) -> Groups<K> {
+        Groups { groups: self.groups.into_iter(), _dir: self.dir }
+    }
+}
+
+/// The groups of a [`Spill`], each loaded back into memory as it's taken
+pub struct Groups<K> {
+    groups: btree_map::IntoIter<K, Group>,
+    _dir: Option<SpillDir>,
+}
+
+impl<K> Iterator for Groups<K> {
+    type Item = Result<(K, Vec<MessageRecord>), AppError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, group) = self.groups.next()?;
+        Some(group.load().map(|records| (key, records)))
+    }
+}
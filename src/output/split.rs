@@ -0,0 +1,137 @@
+use clap::ValueEnum;
+use std::path::{Path, PathBuf};
+
+use super::spill::{Groups, Spill};
+use crate::error::AppError;
+use crate::export::MessageRecord;
+
+/// Placeholders a filename template can use
+const PLACEHOLDERS: &[&str] = &["chat_name", "chat_id", "contact", "year", "month", "day", "ext"];
+
+/// How `--split-by` divides an export into files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SplitBy {
+    /// One file per conversation
+    Chat,
+    /// One file per person messaged
+    Contact,
+    /// One file per calendar day
+    Day,
+    /// One file per calendar month
+    Month,
+}
+
+impl SplitBy {
+    /// The template used when `--filename-template` isn't given
+    pub fn default_template(&self) -> &'static str {
+        match self {
+            SplitBy::Chat => "{chat_name}-{chat_id}.{ext}",
+            SplitBy::Contact => "{contact}.{ext}",
+            SplitBy::Day => "{year}-{month}-{day}.{ext}",
+            SplitBy::Month => "{year}-{month}.{ext}",
+        }
+    }
+}
+
+/// A file name like `{chat_name}-{year}-{month}.json`, filled in from each
+/// message. Messages whose names come out the same share a file.
+#[derive(Debug, Clone)]
+pub struct FileNameTemplate {
+    template: String,
+}
+
+/// Make a value safe to use in a file name
+fn sanitize(value: &str) -> String {
+    let safe: String = value
+        .chars()
+        .map(|c| if c.is_alphanumeric() || "+-_ .@".contains(c) { c } else { '_' })
+        .collect();
+    let safe = safe.trim().trim_start_matches('.');
+    if safe.is_empty() { "unknown".to_string() } else { safe.to_string() }
+}
+
+/// The name of a message's conversation: the chat's name, or the other
+/// person in a one-on-one chat
+fn chat_name(record: &MessageRecord) -> String {
+    if let Some(name) = &record.chat_name {
+        return name.clone();
+    }
+    if record.participants.len() > 1 {
+        return record.chat_id.map(|id| format!("chat {}", id)).unwrap_or_default();
+    }
+    contact(record)
+}
+
+/// The other person in a message: who sent it, or who it was sent to
+fn contact(record: &MessageRecord) -> String {
+    let (name, handle) = if record.from_me {
+        (&record.to_name, &record.to)
+    } else {
+        (&record.from_name, &record.from)
+    };
+    name.clone().or_else(|| handle.clone()).unwrap_or_default()
+}
+
+impl FileNameTemplate {
+    /// Check the template only uses known placeholders
+    pub fn new(template: &str) -> Result<Self, AppError> {
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| AppError::Args(format!("Unclosed {{ in filename template: {}", template)))?;
+            let name = &rest[start + 1..start + end];
+            if !PLACEHOLDERS.contains(&name) {
+                return Err(AppError::Args(format!(
+                    "Unknown filename placeholder: {{{}}}. Expected one of {}",
+                    name,
+                    PLACEHOLDERS.iter().map(|p| format!("{{{}}}", p)).collect::<Vec<_>>().join(", ")
+                )));
+            }
+            rest = &rest[start + end + 1..];
+        }
+        Ok(FileNameTemplate { template: template.to_string() })
+    }
+
+    /// The file name for `record`
+    pub fn render(&self, record: &MessageRecord, extension: &str) -> String {
+        let date = record.date;
+        PLACEHOLDERS.iter().fold(self.template.clone(), |name, placeholder| {
+            let pattern = format!("{{{}}}", placeholder);
+            if !name.contains(&pattern) {
+                return name;
+            }
+            let value = match *placeholder {
+                "chat_name" => sanitize(&chat_name(record)),
+                "chat_id" => record.chat_id.map(|id| id.to_string()).unwrap_or_else(|| "none".to_string()),
+                "contact" => sanitize(&contact(record)),
+                "year" => date.format("%Y").to_string(),
+                "month" => date.format("%m").to_string(),
+                "day" => date.format("%d").to_string(),
+                _ => extension.to_string(),
+            };
+            name.replace(&pattern, &value)
+        })
+    }
+}
+
+/// Group records by the file `template` names for them under `dir`. With
+/// `max_memory` bytes, records past that are spilled to disk and each file's
+/// records are read back as they're taken.
+pub fn split_records<I>(
+    records: I,
+    dir: &Path,
+    template: &FileNameTemplate,
+    extension: &str,
+    max_memory: Option<usize>,
+) -> Result<Groups<PathBuf>, AppError>
+where
+    I: IntoIterator<Item = Result<MessageRecord, AppError>>,
+{
+    let mut files = Spill::new(max_memory);
+    for record in records {
+        let record = record?;
+        files.push(dir.join(template.render(&record, extension)), record)?;
+    }
+    Ok(files.into_groups())
+}
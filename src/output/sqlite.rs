@@ -0,0 +1,124 @@
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::error::AppError;
+use crate::export::MessageRecord;
+
+const SCHEMA: &str = "
+CREATE TABLE handles (
+    id INTEGER PRIMARY KEY,
+    handle TEXT NOT NULL UNIQUE,
+    name TEXT
+);
+CREATE TABLE chats (
+    id INTEGER PRIMARY KEY,
+    name TEXT,
+    participants TEXT NOT NULL
+);
+CREATE TABLE messages (
+    id INTEGER PRIMARY KEY,
+    date TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    text TEXT,
+    from_handle_id INTEGER REFERENCES handles(id),
+    to_handle_id INTEGER REFERENCES handles(id),
+    from_me INTEGER NOT NULL,
+    chat_id INTEGER REFERENCES chats(id),
+    attachments TEXT,
+    reactions TEXT
+);
+CREATE INDEX messages_date ON messages(timestamp);
+CREATE INDEX messages_chat ON messages(chat_id, timestamp);
+CREATE INDEX messages_from ON messages(from_handle_id);
+CREATE INDEX messages_to ON messages(to_handle_id);
+";
+
+/// Maps handles to their row in the `handles` table, inserting as needed
+struct HandleIds {
+    ids: HashMap<String, i64>,
+}
+
+impl HandleIds {
+    fn get(&mut self, tx: &Transaction, handle: Option<&str>, name: Option<&str>) -> Result<Option<i64>, AppError> {
+        let Some(handle) = handle else {
+            return Ok(None);
+        };
+        if let Some(&id) = self.ids.get(handle) {
+            if name.is_some() {
+                tx.execute("UPDATE handles SET name = ?1 WHERE id = ?2 AND name IS NULL", params![name, id])?;
+            }
+            return Ok(Some(id));
+        }
+        tx.execute("INSERT INTO handles (handle, name) VALUES (?1, ?2)", params![handle, name])?;
+        let id = tx.last_insert_rowid();
+        self.ids.insert(handle.to_string(), id);
+        Ok(Some(id))
+    }
+}
+
+/// Serialize a list as a JSON column, or NULL when it's empty
+fn json_column<T: serde::Serialize>(items: &[T]) -> Result<Option<String>, AppError> {
+    if items.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(serde_json::to_string(items)?))
+    }
+}
+
+/// Write records into a new standalone SQLite database at `path`, replacing
+/// any file already there
+pub fn write_sqlite<I>(path: &Path, records: I) -> Result<(), AppError>
+where
+    I: IntoIterator<Item = Result<MessageRecord, AppError>>,
+{
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    let mut db = Connection::open(path)?;
+    db.execute_batch(SCHEMA)?;
+
+    let tx = db.transaction()?;
+    let mut handles = HandleIds { ids: HashMap::new() };
+    for record in records {
+        let record = record?;
+        let from_id = handles.get(&tx, record.from.as_deref(), record.from_name.as_deref())?;
+        let to_id = handles.get(&tx, record.to.as_deref(), record.to_name.as_deref())?;
+
+        if let Some(chat_id) = record.chat_id {
+            let known = tx
+                .query_row("SELECT 1 FROM chats WHERE id = ?1", [chat_id], |_| Ok(()))
+                .optional()?
+                .is_some();
+            if !known {
+                for participant in &record.participants {
+                    handles.get(&tx, Some(participant), None)?;
+                }
+                tx.execute(
+                    "INSERT INTO chats (id, name, participants) VALUES (?1, ?2, ?3)",
+                    params![chat_id, record.chat_name, serde_json::to_string(&record.participants)?],
+                )?;
+            }
+        }
+
+        tx.execute(
+            "INSERT INTO messages (id, date, timestamp, text, from_handle_id, to_handle_id, from_me, chat_id, attachments, reactions)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                record.id,
+                record.date.to_rfc3339(),
+                record.date.timestamp(),
+                record.text,
+                from_id,
+                to_id,
+                record.from_me,
+                record.chat_id,
+                json_column(&record.attachments)?,
+                json_column(&record.reactions)?,
+            ],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
@@ -0,0 +1,161 @@
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+use super::{sender_label, Conversation};
+use crate::attachments::AttachmentRecord;
+use crate::error::AppError;
+use crate::events::EventType;
+use crate::export::MessageRecord;
+
+/// What Telegram Desktop writes in place of a file it didn't export
+const FILE_NOT_INCLUDED: &str = "(File not included. Change data exporting settings to download.)";
+
+/// A stable Telegram-style peer ID for a handle, like `user1234567890`
+fn user_id(handle: Option<&str>) -> String {
+    let digest = Sha256::digest(handle.unwrap_or("unknown").to_lowercase().as_bytes());
+    format!("user{}", u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) >> 1)
+}
+
+/// Telegram writes local times without an offset, next to the Unix time as a string
+fn dates(map: &mut Map<String, Value>, key: &str, date: &chrono::DateTime<chrono::FixedOffset>) {
+    map.insert(key.to_string(), json!(date.format("%Y-%m-%dT%H:%M:%S").to_string()));
+    map.insert(format!("{}_unixtime", key), json!(date.timestamp().to_string()));
+}
+
+fn text_fields(map: &mut Map<String, Value>, text: &str) {
+    map.insert("text".to_string(), json!(text));
+    let entities = if text.is_empty() { json!([]) } else { json!([{ "type": "plain", "text": text }]) };
+    map.insert("text_entities".to_string(), entities);
+}
+
+/// The media fields Telegram gives a message for one attached file
+fn media_fields(map: &mut Map<String, Value>, attachment: &AttachmentRecord) {
+    let path = attachment.path.clone().unwrap_or_else(|| FILE_NOT_INCLUDED.to_string());
+    let mime_type = attachment.mime_type.as_deref().unwrap_or("application/octet-stream");
+    if mime_type.starts_with("image/") && !attachment.is_sticker {
+        map.insert("photo".to_string(), json!(path));
+        map.insert("photo_file_size".to_string(), json!(attachment.size));
+        return;
+    }
+    map.insert("file".to_string(), json!(path));
+    if let Some(name) = &attachment.filename {
+        map.insert("file_name".to_string(), json!(name));
+    }
+    map.insert("file_size".to_string(), json!(attachment.size));
+    let media_type = match mime_type.split('/').next() {
+        _ if attachment.is_sticker => Some("sticker"),
+        // Audio messages recorded in Messages are .caf files
+        Some("audio") if attachment.filename.as_deref().is_some_and(|f| f.ends_with(".caf")) => Some("voice_message"),
+        Some("audio") => Some("audio_file"),
+        Some("video") => Some("video_file"),
+        _ => None,
+    };
+    if let Some(media_type) = media_type {
+        map.insert("media_type".to_string(), json!(media_type));
+    }
+    map.insert("mime_type".to_string(), json!(mime_type));
+}
+
+/// Tapbacks as Telegram reactions, one per emoji with a count
+fn reactions(record: &MessageRecord) -> Value {
+    let mut by_emoji: Vec<(String, Vec<Value>)> = Vec::new();
+    for reaction in &record.reactions {
+        let emoji = reaction.as_emoji();
+        let from = if reaction.from_me { "Me".to_string() } else { reaction.from.clone().unwrap_or_default() };
+        let recent = json!({
+            "from": from,
+            "from_id": user_id(reaction.from.as_deref()),
+            "date": reaction.date.format("%Y-%m-%dT%H:%M:%S").to_string(),
+        });
+        match by_emoji.iter_mut().find(|(e, _)| *e == emoji) {
+            Some((_, recents)) => recents.push(recent),
+            None => by_emoji.push((emoji, vec![recent])),
+        }
+    }
+    by_emoji
+        .into_iter()
+        .map(|(emoji, recent)| json!({ "type": "emoji", "count": recent.len(), "emoji": emoji, "recent": recent }))
+        .collect()
+}
+
+/// A group event as a Telegram service message
+fn service_message(id: usize, record: &MessageRecord, event_type: EventType) -> Value {
+    let mut map = Map::new();
+    map.insert("id".to_string(), json!(id));
+    map.insert("type".to_string(), json!("service"));
+    dates(&mut map, "date", &record.date);
+    map.insert("actor".to_string(), json!(sender_label(record)));
+    map.insert("actor_id".to_string(), json!(user_id(record.from.as_deref())));
+    let members = |member: Option<&str>| json!([member.unwrap_or_default()]);
+    let (action, extra) = match event_type {
+        EventType::NameChanged => ("edit_group_title", Some(("title", json!(record.event_name)))),
+        EventType::ParticipantAdded => ("invite_members", Some(("members", members(record.event_participant.as_deref())))),
+        EventType::ParticipantRemoved => {
+            ("remove_members", Some(("members", members(record.event_participant.as_deref()))))
+        }
+        EventType::ParticipantLeft => ("remove_members", Some(("members", json!([sender_label(record)])))),
+        EventType::PhotoChanged => ("edit_group_photo", None),
+        EventType::PhotoRemoved => ("delete_group_photo", None),
+    };
+    map.insert("action".to_string(), json!(action));
+    if let Some((key, value)) = extra {
+        map.insert(key.to_string(), value);
+    }
+    text_fields(&mut map, "");
+    Value::Object(map)
+}
+
+/// Render a conversation in the shape of Telegram Desktop's "Export chat
+/// history" `result.json`: the chat's name, type and ID, and its messages
+/// with sequential IDs. Messages with several attachments become one
+/// message per file, as Telegram only has one.
+pub fn render(conversation: &Conversation) -> Result<String, AppError> {
+    let is_group = conversation.messages.iter().any(|m| m.participants.len() > 1);
+    // Telegram IDs count up from 1 through the chat; replies point at the first for each message
+    let mut ids: HashMap<i64, usize> = HashMap::new();
+    let mut messages = Vec::new();
+    for record in &conversation.messages {
+        let id = messages.len() + 1;
+        ids.insert(record.id, id);
+        if let Some(event_type) = record.event_type {
+            messages.push(service_message(id, record, event_type));
+            continue;
+        }
+        let mut parts: Vec<Option<&AttachmentRecord>> = record.attachments.iter().map(Some).collect();
+        if parts.is_empty() {
+            parts.push(None);
+        }
+        for (i, attachment) in parts.into_iter().enumerate() {
+            let mut map = Map::new();
+            map.insert("id".to_string(), json!(messages.len() + 1));
+            map.insert("type".to_string(), json!("message"));
+            dates(&mut map, "date", &record.date);
+            if let Some(edited) = &record.date_edited {
+                dates(&mut map, "edited", edited);
+            }
+            map.insert("from".to_string(), json!(sender_label(record)));
+            map.insert("from_id".to_string(), json!(user_id(record.from.as_deref())));
+            if let Some(reply_to) = record.reply_to_id.and_then(|r| ids.get(&r)) {
+                map.insert("reply_to_message_id".to_string(), json!(reply_to));
+            }
+            if let Some(attachment) = attachment {
+                media_fields(&mut map, attachment);
+            }
+            // The text goes with the first file, as a caption
+            let text = if i == 0 { record.text.as_deref().unwrap_or_default() } else { "" };
+            text_fields(&mut map, text);
+            if i == 0 && !record.reactions.is_empty() {
+                map.insert("reactions".to_string(), reactions(record));
+            }
+            messages.push(Value::Object(map));
+        }
+    }
+    let chat = json!({
+        "name": conversation.name,
+        "type": if is_group { "private_group" } else { "personal_chat" },
+        "id": conversation.chat_id.unwrap_or_default(),
+        "messages": messages,
+    });
+    Ok(serde_json::to_string_pretty(&chat)?)
+}
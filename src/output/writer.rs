@@ -0,0 +1,135 @@
+use chrono::Utc;
+use std::io::Write;
+
+use super::{csv_cell, SCHEMA_VERSION};
+use crate::error::AppError;
+use crate::export::MessageRecord;
+
+/// Serializes records one at a time as the exporter yields them, so only the
+/// record being written is held in memory
+pub trait Writer {
+    fn write_record(&mut self, record: &MessageRecord) -> Result<(), AppError>;
+
+    /// Write anything that closes the output, like JSON's closing brackets.
+    /// The output is flushed but left open.
+    fn finish(&mut self) -> Result<(), AppError>;
+}
+
+/// Write every record with `writer`, then finish it
+pub fn write_records<I>(writer: &mut dyn Writer, records: I) -> Result<(), AppError>
+where
+    I: IntoIterator<Item = Result<MessageRecord, AppError>>,
+{
+    for record in records {
+        writer.write_record(&record?)?;
+    }
+    writer.finish()
+}
+
+/// The same document as [`super::Envelope`], written a message at a time
+pub struct JsonWriter<W: Write> {
+    out: W,
+    /// Messages written so far; `None` until the document is opened
+    written: Option<usize>,
+}
+
+impl<W: Write> JsonWriter<W> {
+    pub fn new(out: W) -> Self {
+        JsonWriter { out, written: None }
+    }
+
+    /// Open the envelope and its messages array, the first time only
+    fn header(&mut self) -> Result<usize, AppError> {
+        if let Some(written) = self.written {
+            return Ok(written);
+        }
+        write!(
+            self.out,
+            "{{\"schema_version\":{},\"exported_at\":{},\"messages\":[",
+            SCHEMA_VERSION,
+            serde_json::to_string(&Utc::now())?
+        )?;
+        self.written = Some(0);
+        Ok(0)
+    }
+}
+
+impl<W: Write> Writer for JsonWriter<W> {
+    fn write_record(&mut self, record: &MessageRecord) -> Result<(), AppError> {
+        let written = self.header()?;
+        if written > 0 {
+            self.out.write_all(b",")?;
+        }
+        serde_json::to_writer(&mut self.out, record)?;
+        self.written = Some(written + 1);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), AppError> {
+        self.header()?;
+        self.out.write_all(b"]}")?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// One JSON object per line
+pub struct NdjsonWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> NdjsonWriter<W> {
+    pub fn new(out: W) -> Self {
+        NdjsonWriter { out }
+    }
+}
+
+impl<W: Write> Writer for NdjsonWriter<W> {
+    fn write_record(&mut self, record: &MessageRecord) -> Result<(), AppError> {
+        serde_json::to_writer(&mut self.out, record)?;
+        self.out.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), AppError> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// CSV with a header row of `columns`, each a field of [`MessageRecord`]
+pub struct CsvWriter<W: Write> {
+    out: csv::Writer<W>,
+    columns: Vec<String>,
+    started: bool,
+}
+
+impl<W: Write> CsvWriter<W> {
+    pub fn new(out: W, columns: &[String]) -> Self {
+        CsvWriter { out: csv::Writer::from_writer(out), columns: columns.to_vec(), started: false }
+    }
+
+    fn header(&mut self) -> Result<(), AppError> {
+        if !self.started {
+            self.out.write_record(&self.columns)?;
+            self.started = true;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Writer for CsvWriter<W> {
+    fn write_record(&mut self, record: &MessageRecord) -> Result<(), AppError> {
+        self.header()?;
+        let value = serde_json::to_value(record)?;
+        self.out.write_record(self.columns.iter().map(|column| csv_cell(value.get(column))))?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), AppError> {
+        // Even an empty export gets its header
+        self.header()?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use chrono::{DateTime, FixedOffset};
+use clap::ValueEnum;
+use imessage_database::tables::{handle::Handle, table::Table};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::Path;
+use tracing::warn;
+
+use crate::contacts::{handle_key, ContactBook};
+use crate::error::AppError;
+use crate::export::{from_imessage_ns, imessage_ns, open_database};
+use crate::merge::{HandleInfo, HandleMerger};
+use crate::timezone::Zone;
+
+/// How the contacts command writes people out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ContactsFormat {
+    Json,
+    /// A `.vcf` file of vCard 3.0 cards, which Contacts and most address books import
+    Vcard,
+}
+
+/// Someone the user has exchanged messages with, and every phone number and
+/// email they've used
+#[derive(Debug, Clone, Serialize)]
+pub struct Person {
+    /// From the aliases file or the AddressBook
+    pub name: Option<String>,
+    /// Phone numbers first, then emails
+    pub handles: Vec<String>,
+    pub message_count: i64,
+    pub last_message_date: Option<DateTime<FixedOffset>>,
+}
+
+/// Every person in the database at `db_path`, their handles merged as for
+/// `--merge-handles`, most messaged first
+pub fn list_people(
+    db_path: &Path,
+    aliases: &HashMap<String, String>,
+    timezone: Zone,
+) -> Result<Vec<Person>, AppError> {
+    let (db, _) = open_database(db_path)?;
+    let mut statement = Handle::get(&db)?;
+    let handles: Vec<Handle> = statement.query_map([], |row| Ok(Handle::from_row(row)))?.flatten().flatten().collect();
+    drop(statement);
+
+    let mut activity: HashMap<i32, (i64, i64)> = HashMap::new();
+    let mut statement = db.prepare("SELECT handle_id, COUNT(*), MAX(date) FROM message GROUP BY handle_id")?;
+    for row in statement.query_map([], |row| Ok((row.get::<_, i32>(0)?, row.get::<_, i64>(1)?, row.get(2)?)))? {
+        let (handle, count, last): (i32, i64, Option<i64>) = row?;
+        activity.insert(handle, (count, last.map(imessage_ns).unwrap_or_default()));
+    }
+
+    let contacts = ContactBook::load().unwrap_or_else(|e| {
+        warn!(error = %e, "couldn't read the AddressBook, listing people without their names");
+        ContactBook::default()
+    });
+    let infos: Vec<HandleInfo> = handles
+        .iter()
+        .map(|h| HandleInfo { rowid: h.rowid, id: h.id.clone(), person_centric_id: h.person_centric_id.clone() })
+        .collect();
+    let merger = HandleMerger::new(&infos, Some(&contacts));
+
+    // By canonical handle: the person's handles by key, message count and last message
+    let mut people: BTreeMap<String, (BTreeMap<String, String>, i64, i64)> = BTreeMap::new();
+    for handle in &handles {
+        let person = people.entry(handle_key(&merger.canonical(&handle.id))).or_default();
+        // The same number is a handle once for iMessage and again for SMS
+        person.0.entry(handle_key(&handle.id)).or_insert_with(|| handle.id.clone());
+        if let Some((count, last)) = activity.get(&handle.rowid) {
+            person.1 += count;
+            person.2 = person.2.max(*last);
+        }
+    }
+
+    let aliases: HashMap<String, &String> = aliases.iter().map(|(handle, name)| (handle_key(handle), name)).collect();
+    let mut people: Vec<Person> = people
+        .into_values()
+        .map(|(handles, message_count, last)| {
+            let mut handles: Vec<String> = handles.into_values().collect();
+            handles.sort_by_key(|h| h.contains('@'));
+            let name = handles.iter().find_map(|h| {
+                aliases.get(&handle_key(h)).map(|name| name.to_string()).or_else(|| contacts.name_for(h).map(String::from))
+            });
+            Person {
+                name,
+                handles,
+                message_count,
+                last_message_date: (last != 0).then(|| timezone.localize(from_imessage_ns(last))),
+            }
+        })
+        .collect();
+    people.sort_by(|a, b| b.message_count.cmp(&a.message_count).then(b.last_message_date.cmp(&a.last_message_date)));
+    Ok(people)
+}
+
+/// Escape a vCard property value
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(',', "\\,").replace(';', "\\;").replace('\n', "\\n")
+}
+
+/// Write a content line, folded to 75 octets as vCard requires
+fn write_line(out: &mut impl Write, line: &str) -> Result<(), AppError> {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            out.write_all(b"\r\n ")?;
+            width = 1;
+        }
+        write!(out, "{}", c)?;
+        width += c.len_utf8();
+    }
+    out.write_all(b"\r\n")?;
+    Ok(())
+}
+
+/// Write `people` as vCard 3.0 cards. People without a name are named by
+/// their first handle, so every card shows up in an address book.
+pub fn write_vcards(out: &mut impl Write, people: &[Person]) -> Result<(), AppError> {
+    for person in people {
+        let Some(first) = person.handles.first() else {
+            continue;
+        };
+        let name = person.name.as_deref().unwrap_or(first);
+        write_line(out, "BEGIN:VCARD")?;
+        write_line(out, "VERSION:3.0")?;
+        write_line(out, &format!("FN:{}", escape(name)))?;
+        // Only the whole name is known, so guess its last word is the family name
+        let n = match person.name.as_deref().and_then(|n| n.rsplit_once(' ')) {
+            Some((given, family)) => format!("N:{};{};;;", escape(family), escape(given)),
+            None => format!("N:;{};;;", escape(name)),
+        };
+        write_line(out, &n)?;
+        for handle in &person.handles {
+            if handle.contains('@') {
+                write_line(out, &format!("EMAIL;TYPE=INTERNET:{}", escape(handle)))?;
+            } else {
+                write_line(out, &format!("TEL:{}", escape(handle)))?;
+            }
+        }
+        write_line(out, "END:VCARD")?;
+    }
+    Ok(())
+}
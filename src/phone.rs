@@ -0,0 +1,103 @@
+use phonenumber::{country, metadata::DATABASE, Mode, Type};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+
+/// What kind of line a handle reaches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LineType {
+    Mobile,
+    Landline,
+    /// Numbering in some countries, like the US, doesn't tell mobiles and landlines apart
+    MobileOrLandline,
+    TollFree,
+    Voip,
+    /// A short number businesses send SMS from, like `12345`
+    Shortcode,
+    Email,
+    /// A valid number of some other kind, like premium rate or a pager
+    Other,
+    Unknown,
+}
+
+/// Where a handle's number is from and what kind of line it is, from the
+/// `phonenumber` crate's copy of libphonenumber's metadata
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct NumberInfo {
+    /// Two-letter country code, like `US`
+    pub country: Option<String>,
+    /// International calling code, like 1 or 44
+    pub calling_code: Option<u16>,
+    #[serde(rename = "type")]
+    pub line_type: LineType,
+}
+
+/// Rewrites phone number handles in E.164 form (`+15551234567`), so
+/// `(555) 123-4567` and `+1 555 123 4567` come out the same
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberNormalizer {
+    /// Country assumed for numbers written without a country code
+    region: country::Id,
+}
+
+impl NumberNormalizer {
+    /// `region` is a two-letter country code like `US` or `GB`
+    pub fn new(region: &str) -> Result<Self, AppError> {
+        let region = region.trim().to_uppercase().parse().map_err(|_| {
+            AppError::Args(format!("Unknown region: {}. Expected a two-letter country code like US", region))
+        })?;
+        Ok(NumberNormalizer { region })
+    }
+
+    /// The E.164 form of a phone number handle. Emails and anything that
+    /// isn't a complete, valid number (like `555-1234`) are left as they are.
+    pub fn normalize(&self, handle: &str) -> String {
+        if handle.contains('@') {
+            return handle.to_string();
+        }
+        match phonenumber::parse(Some(self.region), handle) {
+            Ok(number) if number.is_valid() => number.format().mode(Mode::E164).to_string(),
+            _ => handle.to_string(),
+        }
+    }
+
+    /// Describe a handle's country and line type. Short codes are only
+    /// national, so they're taken to be in the default region.
+    pub fn describe(&self, handle: &str) -> NumberInfo {
+        let unknown = |line_type| NumberInfo { country: None, calling_code: None, line_type };
+        if handle.contains('@') {
+            return unknown(LineType::Email);
+        }
+        let digits = handle.chars().filter(char::is_ascii_digit).count();
+        let only_digits = handle.chars().all(|c| c.is_ascii_digit() || c == '-' || c == ' ');
+        if only_digits && (3..=8).contains(&digits) {
+            return NumberInfo {
+                country: Some(self.region.as_ref().to_string()),
+                calling_code: None,
+                line_type: LineType::Shortcode,
+            };
+        }
+        let Ok(number) = phonenumber::parse(Some(self.region), handle) else {
+            return unknown(LineType::Unknown);
+        };
+        // An invalid number's country code can still say where it's from
+        let line_type = match number.number_type(&DATABASE) {
+            _ if !number.is_valid() => LineType::Unknown,
+            Type::Mobile => LineType::Mobile,
+            Type::FixedLine => LineType::Landline,
+            Type::FixedLineOrMobile => LineType::MobileOrLandline,
+            Type::TollFree => LineType::TollFree,
+            Type::Voip => LineType::Voip,
+            Type::ShortCode => LineType::Shortcode,
+            Type::Unknown => LineType::Unknown,
+            _ => LineType::Other,
+        };
+        NumberInfo {
+            country: number.country().id().map(|id| id.as_ref().to_string()),
+            calling_code: Some(number.country().code()),
+            line_type,
+        }
+    }
+}
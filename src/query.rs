@@ -0,0 +1,330 @@
+use imessage_database::tables::table::{
+    CHAT_MESSAGE_JOIN, MESSAGE, MESSAGE_ATTACHMENT_JOIN, RECENTLY_DELETED,
+};
+use rusqlite::types::Value;
+use rusqlite::Connection;
+use std::collections::HashSet;
+
+use crate::error::AppError;
+use crate::export::SECONDS_LIMIT;
+
+/// Columns read by `Message::from_row`
+const COLS: &str = "rowid, guid, text, service, handle_id, destination_caller_id, subject, date, date_read, date_delivered, is_from_me, is_read, item_type, other_handle, share_status, share_direction, group_title, group_action_type, associated_message_guid, associated_message_type, balloon_bundle_id, expressive_send_style_id, thread_originator_guid, thread_originator_part, date_edited, associated_message_emoji";
+
+/// The SELECT ... FROM part of the message query, newest schema first
+fn message_heads() -> [String; 3] {
+    [
+        // macOS Ventura+
+        format!(
+            "SELECT
+                {COLS},
+                c.chat_id,
+                (SELECT COUNT(*) FROM {MESSAGE_ATTACHMENT_JOIN} a WHERE m.ROWID = a.message_id) as num_attachments,
+                d.chat_id as deleted_from,
+                (SELECT COUNT(*) FROM {MESSAGE} m2 WHERE m2.thread_originator_guid = m.guid) as num_replies
+            FROM {MESSAGE} as m
+            LEFT JOIN {CHAT_MESSAGE_JOIN} as c ON m.ROWID = c.message_id
+            LEFT JOIN {RECENTLY_DELETED} as d ON m.ROWID = d.message_id"
+        ),
+        // macOS Big Sur to Monterey
+        format!(
+            "SELECT
+                *,
+                c.chat_id,
+                (SELECT COUNT(*) FROM {MESSAGE_ATTACHMENT_JOIN} a WHERE m.ROWID = a.message_id) as num_attachments,
+                NULL as deleted_from,
+                (SELECT COUNT(*) FROM {MESSAGE} m2 WHERE m2.thread_originator_guid = m.guid) as num_replies
+            FROM {MESSAGE} as m
+            LEFT JOIN {CHAT_MESSAGE_JOIN} as c ON m.ROWID = c.message_id"
+        ),
+        // macOS Catalina and older
+        format!(
+            "SELECT
+                *,
+                c.chat_id,
+                (SELECT COUNT(*) FROM {MESSAGE_ATTACHMENT_JOIN} a WHERE m.ROWID = a.message_id) as num_attachments,
+                NULL as deleted_from,
+                0 as num_replies
+            FROM {MESSAGE} as m
+            LEFT JOIN {CHAT_MESSAGE_JOIN} as c ON m.ROWID = c.message_id"
+        ),
+    ]
+}
+
+/// Pick the message query head that the database schema supports
+pub(crate) fn message_head(db: &Connection) -> Result<String, AppError> {
+    let mut last_err = None;
+    for head in message_heads() {
+        match db.prepare(&format!("{head} LIMIT 0")) {
+            Ok(_) => return Ok(head),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap().into())
+}
+
+/// The GUIDs of messages deleted on any of the account's devices, kept for
+/// syncing the deletion
+pub(crate) const DELETED_MESSAGES: &str = "deleted_messages";
+
+/// Every GUID in [`DELETED_MESSAGES`]
+pub(crate) fn deleted_guids(db: &Connection) -> Result<HashSet<String>, AppError> {
+    let mut statement = db.prepare(&format!("SELECT guid FROM {DELETED_MESSAGES}"))?;
+    let guids = statement.query_map([], |row| row.get(0))?.collect::<Result<_, _>>()?;
+    Ok(guids)
+}
+
+/// Whether the database has a table, for ones only newer schemas have
+pub(crate) fn has_table(db: &Connection, table: &str) -> Result<bool, AppError> {
+    let count: i64 =
+        db.query_row("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1", [table], |row| row.get(0))?;
+    Ok(count > 0)
+}
+
+/// SQL predicates ANDed together, with the values bound to their `?` placeholders
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Filters {
+    clauses: Vec<String>,
+    params: Vec<Value>,
+}
+
+impl Filters {
+    pub(crate) fn push(&mut self, clause: impl Into<String>, params: impl IntoIterator<Item = Value>) {
+        self.clauses.push(clause.into());
+        self.params.extend(params);
+    }
+
+    /// Add `column IN (...)`, which matches nothing when `values` is empty
+    pub(crate) fn push_in(&mut self, column: &str, values: Vec<Value>) {
+        if values.is_empty() {
+            self.push("0", []);
+            return;
+        }
+        let placeholders = vec!["?"; values.len()].join(", ");
+        self.push(format!("{column} IN ({placeholders})"), values);
+    }
+
+    /// Compare a chat.db date column to `ns`, matching rows stored in
+    /// seconds as well as nanoseconds (see [`SECONDS_LIMIT`])
+    pub(crate) fn push_date(&mut self, column: &str, op: &str, ns: i64) {
+        self.push(
+            format!(
+                "((({column} <= -{SECONDS_LIMIT} OR {column} >= {SECONDS_LIMIT}) AND {column} {op} ?)
+                  OR ({column} > -{SECONDS_LIMIT} AND {column} < {SECONDS_LIMIT} AND {column} {op} ?))"
+            ),
+            [Value::Integer(ns), Value::Integer(ns.div_euclid(1_000_000_000))],
+        );
+    }
+
+    pub(crate) fn params(&self) -> &[Value] {
+        &self.params
+    }
+}
+
+/// Count the message rows matching `filters`, bound with the filter parameters
+pub(crate) fn message_count(filters: &Filters) -> String {
+    let clauses = if filters.clauses.is_empty() { "1".to_string() } else { filters.clauses.join(" AND ") };
+    format!("SELECT COUNT(*) FROM {MESSAGE} as m WHERE {clauses}")
+}
+
+/// Raw chat.db dates, from and to inclusive, and whether they're in seconds,
+/// as [`imessage_ns`](crate::export::imessage_ns) reads them: nanoseconds
+/// before 2001, seconds, and nanoseconds after
+const DATE_RANGES: [(i64, i64, bool); 3] = [
+    (i64::MIN, -SECONDS_LIMIT, false),
+    (-SECONDS_LIMIT + 1, SECONDS_LIMIT - 1, true),
+    (SECONDS_LIMIT, i64::MAX, false),
+];
+
+/// A query for the next page of messages after a `(date in nanoseconds,
+/// rowid)` cursor
+#[derive(Debug, Clone)]
+pub(crate) struct PageQuery {
+    pub(crate) sql: String,
+    /// The raw dates it reads, or `None` for a page in ROWID order
+    range: Option<(i64, i64, bool)>,
+}
+
+impl PageQuery {
+    /// The parameters bound after the filters' for the page after `cursor`,
+    /// before the page size
+    pub(crate) fn cursor_params(&self, (ns, rowid): (i64, i32)) -> Vec<Value> {
+        let Some((from, to, seconds)) = self.range else {
+            return vec![Value::Integer(rowid.into())];
+        };
+        // A row in seconds at the cursor's second is only at the cursor if
+        // that's a whole second; otherwise it's before
+        let (date, rowid) = if seconds {
+            let rowid = if ns.rem_euclid(1_000_000_000) == 0 { rowid.into() } else { i64::MAX };
+            (ns.div_euclid(1_000_000_000), rowid)
+        } else {
+            (ns, rowid.into())
+        };
+        vec![Value::Integer(from.max(date)), Value::Integer(to), Value::Integer(date), Value::Integer(rowid)]
+    }
+}
+
+/// Build the queries that return pages of messages, bound with the filter
+/// parameters, then [`PageQuery::cursor_params`], then the page size
+///
+/// With `by_rowid` there's one, in ROWID order. Otherwise there's one for
+/// each of [`DATE_RANGES`], in order of the raw date so each walks the date
+/// index, and their pages are merged by date in nanoseconds, so databases
+/// that mix seconds and nanoseconds come out in the order messages were sent.
+pub(crate) fn message_pages(head: &str, filters: &Filters, by_rowid: bool) -> Vec<PageQuery> {
+    let query = |clause: &str, order: &str| {
+        let mut clauses = filters.clauses.clone();
+        clauses.push(clause.to_string());
+        format!(
+            "{head}
+        WHERE {}
+        ORDER BY {order}
+        LIMIT ?",
+            clauses.join(" AND ")
+        )
+    };
+    if by_rowid {
+        return vec![PageQuery { sql: query("m.ROWID > ?", "m.ROWID"), range: None }];
+    }
+    DATE_RANGES
+        .into_iter()
+        .map(|range| PageQuery {
+            sql: query("m.date BETWEEN ? AND ? AND (m.date > ? OR m.ROWID > ?)", "m.date, m.ROWID"),
+            range: Some(range),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::imessage_ns;
+    use rusqlite::params_from_iter;
+
+    const HEAD: &str = "SELECT m.ROWID, m.date FROM message as m";
+
+    /// Raw dates from each of [`DATE_RANGES`], with ties across units
+    const DATES: [i64; 10] = [
+        -700_000_000_000_000_000,
+        -SECONDS_LIMIT,
+        -5,
+        0,
+        700_000_000,
+        700_000_000,
+        700_000_000_000_000_000,
+        700_000_000_000_000_001,
+        1_000_000,
+        SECONDS_LIMIT,
+    ];
+
+    fn database() -> Connection {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE message (ROWID INTEGER PRIMARY KEY, date INTEGER);
+             CREATE INDEX message_idx_date ON message(date);",
+        )
+        .unwrap();
+        for date in DATES {
+            db.execute("INSERT INTO message (date) VALUES (?1)", [date]).unwrap();
+        }
+        db
+    }
+
+    /// Every ROWID the queries return, paging and merging the way the exporter does
+    fn walk(db: &Connection, filters: &Filters, by_rowid: bool, page_size: i64) -> Vec<i32> {
+        let queries = message_pages(HEAD, filters, by_rowid);
+        let mut cursor = (i64::MIN, 0);
+        let mut seen = Vec::new();
+        loop {
+            let mut page: Vec<(i32, i64)> = Vec::new();
+            for query in &queries {
+                let mut params = filters.params().to_vec();
+                params.extend(query.cursor_params(cursor));
+                params.push(Value::Integer(page_size));
+                let mut statement = db.prepare(&query.sql).unwrap();
+                let rows = statement.query_map(params_from_iter(params), |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+                page.extend(rows.map(Result::unwrap));
+            }
+            if queries.len() > 1 {
+                page.sort_by_key(|(rowid, date)| (imessage_ns(*date), *rowid));
+                page.truncate(page_size as usize);
+            }
+            if let Some((rowid, date)) = page.last() {
+                cursor = (imessage_ns(*date), *rowid);
+            }
+            seen.extend(page.iter().map(|(rowid, _)| *rowid));
+            if (page.len() as i64) < page_size {
+                return seen;
+            }
+        }
+    }
+
+    fn by_date(dates: impl IntoIterator<Item = (i32, i64)>) -> Vec<i32> {
+        let mut rows: Vec<(i32, i64)> = dates.into_iter().collect();
+        rows.sort_by_key(|(rowid, date)| (imessage_ns(*date), *rowid));
+        rows.into_iter().map(|(rowid, _)| rowid).collect()
+    }
+
+    fn all_rows() -> impl Iterator<Item = (i32, i64)> {
+        DATES.into_iter().enumerate().map(|(i, date)| (i as i32 + 1, date))
+    }
+
+    #[test]
+    fn pages_come_in_date_order_across_units() {
+        let db = database();
+        let expected = by_date(all_rows());
+        for page_size in [1, 2, 3, 100] {
+            assert_eq!(walk(&db, &Filters::default(), false, page_size), expected, "pages of {page_size}");
+        }
+    }
+
+    #[test]
+    fn pages_by_rowid_come_in_rowid_order() {
+        let db = database();
+        let expected: Vec<i32> = (1..=DATES.len() as i32).collect();
+        assert_eq!(walk(&db, &Filters::default(), true, 3), expected);
+    }
+
+    #[test]
+    fn page_queries_walk_the_date_index() {
+        let db = database();
+        let mut filters = Filters::default();
+        filters.push_date("m.date", ">=", 0);
+        for query in message_pages(HEAD, &filters, false) {
+            let mut plan = String::new();
+            let mut statement = db.prepare(&format!("EXPLAIN QUERY PLAN {}", query.sql)).unwrap();
+            let mut rows = statement.query([0; 7]).unwrap();
+            while let Some(row) = rows.next().unwrap() {
+                plan.push_str(&row.get::<_, String>(3).unwrap());
+                plan.push('\n');
+            }
+            assert!(plan.contains("INDEX message_idx_date (date>? AND date<?)"), "{plan}");
+            assert!(!plan.contains("TEMP B-TREE"), "{plan}");
+        }
+    }
+
+    #[test]
+    fn date_filters_read_both_units() {
+        let db = database();
+        for ns in [-SECONDS_LIMIT * 1_000_000_000, -5_000_000_000, 0, 700_000_000_000_000_000, i64::MAX] {
+            for op in [">=", "<="] {
+                let mut filters = Filters::default();
+                filters.push_date("m.date", op, ns);
+                let expected = by_date(all_rows().filter(|(_, date)| match op {
+                    ">=" => imessage_ns(*date) >= ns,
+                    _ => imessage_ns(*date) <= ns,
+                }));
+                assert_eq!(walk(&db, &filters, false, 4), expected, "{op} {ns}");
+            }
+        }
+    }
+
+    #[test]
+    fn seconds_always_fit_in_nanoseconds() {
+        assert_eq!(imessage_ns(SECONDS_LIMIT - 1), (SECONDS_LIMIT - 1) * 1_000_000_000);
+        assert_eq!(imessage_ns(-SECONDS_LIMIT + 1), (-SECONDS_LIMIT + 1) * 1_000_000_000);
+        assert_eq!(imessage_ns(SECONDS_LIMIT), SECONDS_LIMIT);
+        assert_eq!(imessage_ns(-SECONDS_LIMIT), -SECONDS_LIMIT);
+        assert_eq!(imessage_ns(i64::MIN), i64::MIN);
+    }
+}
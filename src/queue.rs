@@ -0,0 +1,211 @@
+use chrono::{DateTime, Utc};
+use imessage_database::util::dirs::home;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::dates::TimeWindow;
+use crate::error::AppError;
+use crate::optout::{self, SuppressionList};
+use crate::quiet::QuietHours;
+use crate::send::{self, OutgoingMessage, SendResult};
+use crate::timezone::Zone;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS jobs (
+    id INTEGER PRIMARY KEY,
+    recipient TEXT NOT NULL,
+    text TEXT NOT NULL,
+    attachments TEXT NOT NULL,
+    account TEXT,
+    send_at INTEGER NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    error TEXT,
+    created_at INTEGER NOT NULL,
+    sent_at INTEGER
+);
+CREATE INDEX IF NOT EXISTS jobs_due ON jobs(status, send_at);
+";
+
+/// A send waiting in the queue
+#[derive(Debug, Clone, Serialize)]
+pub struct QueuedSend {
+    pub id: i64,
+    pub recipient: String,
+    pub send_at: DateTime<Utc>,
+    #[serde(skip)]
+    pub message: OutgoingMessage,
+}
+
+/// Sends scheduled for later, stored in SQLite so they survive restarts
+pub struct SendQueue {
+    db: Connection,
+}
+
+impl SendQueue {
+    /// `~/.imessage-blaster/queue.sqlite`
+    pub fn default_path() -> PathBuf {
+        PathBuf::from(home()).join(".imessage-blaster").join("queue.sqlite")
+    }
+
+    pub fn open(path: &Path) -> Result<Self, AppError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let db = Connection::open(path)?;
+        db.execute_batch(SCHEMA)?;
+        // Queues from before --from-account don't have the column
+        if !db.prepare("SELECT 1 FROM pragma_table_info('jobs') WHERE name = 'account'")?.exists([])? {
+            db.execute("ALTER TABLE jobs ADD COLUMN account TEXT", [])?;
+        }
+        Ok(SendQueue { db })
+    }
+
+    /// Schedule `message` to go out at `send_at`
+    pub fn enqueue(&self, message: &OutgoingMessage, send_at: DateTime<Utc>) -> Result<QueuedSend, AppError> {
+        let attachments: Vec<String> =
+            message.attachments.iter().map(|p| p.to_string_lossy().to_string()).collect();
+        self.db.execute(
+            "INSERT INTO jobs (recipient, text, attachments, account, send_at, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                message.recipient,
+                message.text,
+                serde_json::to_string(&attachments)?,
+                message.account,
+                send_at.timestamp(),
+                Utc::now().timestamp()
+            ],
+        )?;
+        Ok(QueuedSend {
+            id: self.db.last_insert_rowid(),
+            recipient: message.recipient.clone(),
+            send_at,
+            message: message.clone(),
+        })
+    }
+
+    /// Pending sends whose time has come, oldest first
+    pub fn due(&self, now: DateTime<Utc>) -> Result<Vec<QueuedSend>, AppError> {
+        let mut statement = self.db.prepare(
+            "SELECT id, recipient, text, attachments, send_at, account FROM jobs
+             WHERE status = 'pending' AND send_at <= ?1
+             ORDER BY send_at, id",
+        )?;
+        let rows = statement.query_map([now.timestamp()], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, Option<String>>(5)?,
+            ))
+        })?;
+
+        let mut due = Vec::new();
+        for row in rows {
+            let (id, recipient, text, attachments, send_at, account) = row?;
+            let attachments: Vec<PathBuf> = serde_json::from_str::<Vec<String>>(&attachments)?
+                .into_iter()
+                .map(PathBuf::from)
+                .collect();
+            due.push(QueuedSend {
+                id,
+                recipient: recipient.clone(),
+                send_at: DateTime::from_timestamp(send_at, 0).unwrap_or_default(),
+                message: OutgoingMessage { recipient, text, attachments, account },
+            });
+        }
+        Ok(due)
+    }
+
+    /// Move a pending send to `send_at`
+    pub fn reschedule(&self, id: i64, send_at: DateTime<Utc>) -> Result<(), AppError> {
+        self.db.execute("UPDATE jobs SET send_at = ?1 WHERE id = ?2", params![send_at.timestamp(), id])?;
+        Ok(())
+    }
+
+    fn set_status(&self, id: i64, status: &str, error: Option<&str>) -> Result<(), AppError> {
+        self.db.execute(
+            "UPDATE jobs SET status = ?1, error = ?2, sent_at = ?3 WHERE id = ?4",
+            params![status, error, Utc::now().timestamp(), id],
+        )?;
+        Ok(())
+    }
+
+    /// Sends left mid-flight by a crash may or may not have gone out; fail
+    /// them rather than risk sending twice
+    pub fn fail_interrupted(&self) -> Result<usize, AppError> {
+        Ok(self.db.execute(
+            "UPDATE jobs SET status = 'failed', error = 'interrupted while sending' WHERE status = 'sending'",
+            [],
+        )?)
+    }
+
+    /// Send one queued message and record the outcome, skipping recipients
+    /// who opted out after it was queued
+    pub fn send(&self, job: &QueuedSend, optouts: &SuppressionList) -> Result<SendResult, AppError> {
+        if optouts.contains(&job.recipient)? {
+            let result = optout::suppressed_result(&job.recipient);
+            self.set_status(job.id, "suppressed", result.error.as_deref())?;
+            return Ok(result);
+        }
+        self.set_status(job.id, "sending", None)?;
+        let result = send::send_one(&job.message);
+        let error = result.error.clone().or_else(|| result.attachments.iter().find_map(|a| a.error.clone()));
+        let status = if result.success { "sent" } else { "failed" };
+        self.set_status(job.id, status, error.as_deref())?;
+        Ok(result)
+    }
+
+    /// Send queued messages as they come due, forever, calling `on_sent` after each
+    ///
+    /// With a `window`, sends that come due outside it wait until it opens,
+    /// in local time, and a batch still going out when it closes stops there.
+    /// Sends that come due in their recipient's `quiet` hours are moved to
+    /// when those end.
+    pub fn run<F>(
+        &self,
+        interval: Duration,
+        delay: Duration,
+        window: Option<&TimeWindow>,
+        quiet: Option<&QuietHours>,
+        mut on_sent: F,
+    ) -> Result<(), AppError>
+    where
+        F: FnMut(&QueuedSend, &SendResult) -> Result<(), AppError>,
+    {
+        let optouts = SuppressionList::open_default()?;
+        let interrupted = self.fail_interrupted()?;
+        if interrupted > 0 {
+            warn!(jobs = interrupted, "marked sends interrupted by a restart as failed");
+        }
+        loop {
+            let mut sent = 0;
+            for job in self.due(Utc::now())? {
+                if let Some(quiet) = quiet.filter(|quiet| quiet.is_quiet(&job.recipient, Utc::now())) {
+                    let send_at = quiet.next_allowed(&job.recipient, Utc::now());
+                    info!(id = job.id, recipient = %job.recipient, %send_at, "quiet hours for recipient; holding send");
+                    self.reschedule(job.id, send_at)?;
+                    continue;
+                }
+                if sent > 0 {
+                    thread::sleep(delay);
+                }
+                if window.is_some_and(|window| !window.contains(Zone::Local.localize(Utc::now()))) {
+                    info!("outside the send window; holding due sends");
+                    break;
+                }
+                info!(id = job.id, recipient = %job.recipient, "sending queued message");
+                let result = self.send(&job, &optouts)?;
+                on_sent(&job, &result)?;
+                sent += 1;
+            }
+            thread::sleep(interval);
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use chrono::{DateTime, NaiveTime, Utc};
+use chrono_tz::{Africa, America, Asia, Europe, Pacific, Tz};
+use phonenumber::country;
+
+use crate::dates::TimeWindow;
+use crate::error::AppError;
+use crate::send::{is_chat_guid, SendResult};
+use crate::timezone::Zone;
+
+/// North American area codes by the timezone most of each one's callers
+/// are in. Area codes that straddle a line go with the larger side.
+const AREA_CODES: &[(Tz, &[u16])] = &[
+    (
+        America::New_York,
+        &[
+            201, 202, 203, 207, 212, 215, 216, 220, 223, 227, 229, 231, 234, 239, 240, 248, 252, 260, 267, 269, 272, 276,
+            283, 301, 302, 304, 305, 313, 315, 317, 321, 324, 326, 329, 330, 332, 336, 339, 347, 351, 352, 363, 380, 386,
+            401, 404, 407, 410, 412, 413, 419, 423, 434, 436, 440, 443, 445, 448, 463, 470, 472, 475, 478, 484, 502, 508,
+            513, 516, 517, 518, 540, 551, 561, 567, 570, 571, 574, 582, 585, 586, 603, 606, 607, 609, 610, 614, 616, 617,
+            624, 631, 640, 645, 646, 656, 667, 678, 679, 680, 681, 689, 703, 704, 706, 716, 717, 718, 724, 727, 728, 732,
+            734, 740, 743, 754, 757, 762, 765, 770, 771, 772, 774, 781, 786, 802, 803, 804, 810, 812, 813, 814, 821, 826,
+            828, 835, 838, 839, 843, 845, 848, 850, 854, 856, 857, 859, 860, 862, 863, 864, 865, 878, 904, 906, 908, 910,
+            912, 914, 917, 919, 929, 930, 934, 937, 941, 943, 947, 948, 954, 959, 973, 978, 980, 984, 989,
+        ],
+    ),
+    (
+        America::Chicago,
+        &[
+            205, 210, 214, 217, 218, 219, 224, 225, 228, 235, 251, 254, 256, 262, 270, 274, 281, 308, 309, 312, 314, 316,
+            318, 319, 320, 325, 327, 331, 334, 337, 346, 361, 364, 402, 405, 409, 414, 417, 430, 432, 447, 464, 469, 479,
+            501, 504, 507, 512, 515, 531, 534, 539, 557, 563, 572, 573, 580, 601, 605, 608, 612, 615, 618, 620, 629, 630,
+            636, 641, 651, 659, 660, 662, 682, 701, 708, 712, 713, 715, 726, 730, 731, 737, 763, 769, 773, 779, 785, 806,
+            815, 816, 817, 830, 832, 847, 861, 870, 872, 901, 903, 913, 918, 920, 924, 931, 936, 938, 940, 945, 952, 956,
+            972, 975, 979, 985,
+        ],
+    ),
+    (
+        America::Denver,
+        &[
+            303, 307, 385, 406, 435, 505, 575, 719, 720, 801, 915, 970, 983,
+        ],
+    ),
+    (
+        America::Phoenix,
+        &[
+            480, 520, 602, 623, 928,
+        ],
+    ),
+    (
+        America::Los_Angeles,
+        &[
+            206, 209, 213, 253, 279, 310, 323, 341, 350, 360, 408, 415, 424, 425, 442, 458, 503, 509, 510, 530, 541, 559,
+            562, 564, 619, 626, 628, 650, 657, 661, 669, 702, 707, 714, 725, 747, 760, 775, 805, 818, 820, 831, 840, 858,
+            909, 916, 925, 949, 951, 971,
+        ],
+    ),
+    (
+        America::Boise,
+        &[
+            208, 986,
+        ],
+    ),
+    (
+        America::Anchorage,
+        &[
+            907,
+        ],
+    ),
+    (
+        Pacific::Honolulu,
+        &[
+            808,
+        ],
+    ),
+    (
+        America::Puerto_Rico,
+        &[
+            787, 939,
+        ],
+    ),
+    (
+        America::St_Johns,
+        &[
+            709, 879,
+        ],
+    ),
+    (
+        America::Halifax,
+        &[
+            506, 782, 902,
+        ],
+    ),
+    (
+        America::Toronto,
+        &[
+            226, 249, 263, 289, 343, 354, 365, 367, 382, 416, 418, 437, 438, 450, 468, 514, 519, 548, 579, 581, 613, 647,
+            683, 705, 742, 753, 807, 819, 873, 905,
+        ],
+    ),
+    (
+        America::Winnipeg,
+        &[
+            204, 431, 584,
+        ],
+    ),
+    (
+        America::Regina,
+        &[
+            306, 474, 639,
+        ],
+    ),
+    (
+        America::Edmonton,
+        &[
+            368, 403, 587, 780, 825,
+        ],
+    ),
+    (
+        America::Vancouver,
+        &[
+            236, 250, 604, 672, 778,
+        ],
+    ),
+];
+
+/// Countries that keep a single timezone, by their two-letter code
+const COUNTRIES: &[(&str, Tz)] = &[
+    ("AE", Asia::Dubai),
+    ("AT", Europe::Vienna),
+    ("BE", Europe::Brussels),
+    ("CH", Europe::Zurich),
+    ("CN", Asia::Shanghai),
+    ("CZ", Europe::Prague),
+    ("DE", Europe::Berlin),
+    ("DK", Europe::Copenhagen),
+    ("EG", Africa::Cairo),
+    ("FI", Europe::Helsinki),
+    ("FR", Europe::Paris),
+    ("GB", Europe::London),
+    ("GR", Europe::Athens),
+    ("HK", Asia::Hong_Kong),
+    ("HU", Europe::Budapest),
+    ("IE", Europe::Dublin),
+    ("IL", Asia::Jerusalem),
+    ("IN", Asia::Kolkata),
+    ("IT", Europe::Rome),
+    ("JP", Asia::Tokyo),
+    ("KE", Africa::Nairobi),
+    ("KR", Asia::Seoul),
+    ("NG", Africa::Lagos),
+    ("NL", Europe::Amsterdam),
+    ("NO", Europe::Oslo),
+    ("NZ", Pacific::Auckland),
+    ("PH", Asia::Manila),
+    ("PL", Europe::Warsaw),
+    ("RO", Europe::Bucharest),
+    ("SA", Asia::Riyadh),
+    ("SE", Europe::Stockholm),
+    ("SG", Asia::Singapore),
+    ("TR", Europe::Istanbul),
+    ("ZA", Africa::Johannesburg),
+];
+
+/// The timezone a phone number is most likely in: from its area code in
+/// the US and Canada, or its country where the whole country keeps one.
+/// Numbers without a country code are taken to be North American.
+pub fn likely_timezone(handle: &str) -> Option<Tz> {
+    if handle.contains('@') || is_chat_guid(handle) {
+        return None;
+    }
+    let number = phonenumber::parse(Some(country::Id::US), handle).ok()?;
+    if number.country().code() == 1 {
+        let area_code = u16::try_from(number.national().value() / 10_000_000).ok()?;
+        return AREA_CODES.iter().find(|(_, codes)| codes.contains(&area_code)).map(|(tz, _)| *tz);
+    }
+    let id = number.country().id()?;
+    COUNTRIES.iter().find(|(code, _)| *code == id.as_ref()).map(|(_, tz)| *tz)
+}
+
+/// Hours, like 21:00-08:00, that nobody is messaged in, going by the
+/// timezone their number puts them in
+#[derive(Debug, Clone)]
+pub struct QuietHours {
+    /// The rest of the day, when sending is fine
+    open: TimeWindow,
+    /// Where recipients whose number doesn't say are taken to be
+    fallback: Zone,
+}
+
+impl QuietHours {
+    /// Parse `--quiet-hours 21:00-08:00`
+    pub fn new(range: &str, fallback: Zone) -> Result<Self, AppError> {
+        let invalid = || AppError::Args(format!("Invalid quiet hours: {}. Expected HH:MM-HH:MM, like 21:00-08:00", range));
+        let (from, until) = range.split_once('-').ok_or_else(invalid)?;
+        let time = |t: &str| NaiveTime::parse_from_str(t.trim(), "%H:%M").map_err(|_| invalid());
+        let (from, until) = (time(from)?, time(until)?);
+        if from == until {
+            return Err(invalid());
+        }
+        Ok(QuietHours { open: TimeWindow::daily(until, from), fallback })
+    }
+
+    /// The timezone `recipient`'s quiet hours are kept in
+    pub fn zone(&self, recipient: &str) -> Zone {
+        likely_timezone(recipient).map_or(self.fallback, Zone::Named)
+    }
+
+    /// Whether it's quiet hours for `recipient` at `at`
+    pub fn is_quiet(&self, recipient: &str, at: DateTime<Utc>) -> bool {
+        !self.open.contains(self.zone(recipient).localize(at))
+    }
+
+    /// The first instant at or after `at` that isn't in `recipient`'s quiet hours
+    pub fn next_allowed(&self, recipient: &str, at: DateTime<Utc>) -> DateTime<Utc> {
+        self.open.next_open(at, self.zone(recipient))
+    }
+}
+
+/// The result reported for a recipient whose message was held for quiet
+/// hours and queued to go out at `send_at`
+pub fn held_result(recipient: &str, send_at: DateTime<Utc>) -> SendResult {
+    SendResult {
+        recipient: recipient.to_string(),
+        success: false,
+        error: Some(format!("held for quiet hours; queued to send at {}", send_at.to_rfc3339())),
+        attachments: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, TimeZone};
+
+    const UTC: Zone = Zone::Named(chrono_tz::UTC);
+
+    fn time(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    fn utc(d: u32, h: u32, m: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, d, h, m, 0).unwrap()
+    }
+
+    #[test]
+    fn overnight_window_wraps_past_midnight() {
+        let night = TimeWindow::daily(time(22, 0), time(8, 0));
+        let contains = |h, m| night.contains(UTC.localize(utc(15, h, m)));
+        assert!(contains(22, 0));
+        assert!(contains(23, 59));
+        assert!(contains(0, 0));
+        assert!(contains(7, 59));
+        assert!(!contains(8, 0));
+        assert!(!contains(12, 0));
+        assert!(!contains(21, 59));
+    }
+
+    #[test]
+    fn overnight_window_opens_at_its_start() {
+        let night = TimeWindow::daily(time(22, 0), time(8, 0));
+        assert_eq!(night.next_open(utc(15, 12, 0), UTC), utc(15, 22, 0));
+        assert_eq!(night.next_open(utc(15, 23, 0), UTC), utc(15, 23, 0));
+        assert_eq!(night.next_open(utc(16, 3, 0), UTC), utc(16, 3, 0));
+    }
+
+    #[test]
+    fn quiet_hours_end_the_next_morning() {
+        let quiet = QuietHours::new("22:00-08:00", UTC).unwrap();
+        let someone = "friend@example.com";
+        assert!(!quiet.is_quiet(someone, utc(15, 21, 59)));
+        assert!(quiet.is_quiet(someone, utc(15, 22, 0)));
+        assert!(quiet.is_quiet(someone, utc(16, 0, 0)));
+        assert!(!quiet.is_quiet(someone, utc(16, 8, 0)));
+        // Before midnight waits for tomorrow, after it for later the same day
+        assert_eq!(quiet.next_allowed(someone, utc(15, 23, 30)), utc(16, 8, 0));
+        assert_eq!(quiet.next_allowed(someone, utc(16, 1, 0)), utc(16, 8, 0));
+        assert_eq!(quiet.next_allowed(someone, utc(15, 12, 0)), utc(15, 12, 0));
+    }
+
+    #[test]
+    fn quiet_hours_follow_the_recipients_area_code() {
+        let quiet = QuietHours::new("22:00-08:00", UTC).unwrap();
+        let new_yorker = "+12125550100";
+        assert_eq!(quiet.zone(new_yorker), Zone::Named(America::New_York));
+        // 23:00 in UTC is 19:00 in New York, and 03:00 UTC is 23:00 there
+        assert!(!quiet.is_quiet(new_yorker, utc(15, 23, 0)));
+        assert!(quiet.is_quiet(new_yorker, utc(16, 3, 0)));
+        let morning = NaiveDate::from_ymd_opt(2024, 3, 16).unwrap().and_time(time(8, 0));
+        assert_eq!(quiet.next_allowed(new_yorker, utc(16, 3, 0)), Zone::Named(America::New_York).from_local(morning));
+    }
+
+    #[test]
+    fn rejects_empty_or_malformed_ranges() {
+        for range in ["22:00-22:00", "22:00", "10pm-8am", "25:00-08:00"] {
+            assert!(QuietHours::new(range, UTC).is_err(), "{range}");
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use chrono::{DateTime, FixedOffset};
+use clap::ValueEnum;
+use imessage_database::{
+    message_types::variants::{Tapback, TapbackAction, Variant},
+    tables::messages::Message,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// What to do with tapback rows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ReactionMode {
+    /// Attach reactions to the message they react to
+    #[default]
+    Attach,
+    /// Attach reactions and also export each one as its own message
+    Include,
+    /// Drop reactions entirely
+    Exclude,
+}
+
+/// A tapback on a message
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct ReactionRecord {
+    /// `loved`, `liked`, `disliked`, `laughed`, `emphasized`, `questioned`, `emoji` or `sticker`
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emoji: Option<String>,
+    pub from: Option<String>,
+    pub from_me: bool,
+    pub date: DateTime<FixedOffset>,
+}
+
+impl ReactionRecord {
+    /// The reaction as one emoji: its own for emoji tapbacks, otherwise the
+    /// closest to the tapback, for messengers that only react with emoji
+    pub fn as_emoji(&self) -> String {
+        if let Some(emoji) = &self.emoji {
+            return emoji.clone();
+        }
+        match self.kind.as_str() {
+            "loved" => "❤️",
+            "liked" => "👍",
+            "disliked" => "👎",
+            "laughed" => "😂",
+            "emphasized" => "‼️",
+            "questioned" => "❓",
+            _ => "👀",
+        }
+        .to_string()
+    }
+}
+
+fn kind_name(tapback: &Tapback) -> &'static str {
+    match tapback {
+        Tapback::Loved => "loved",
+        Tapback::Liked => "liked",
+        Tapback::Disliked => "disliked",
+        Tapback::Laughed => "laughed",
+        Tapback::Emphasized => "emphasized",
+        Tapback::Questioned => "questioned",
+        Tapback::Emoji(_) => "emoji",
+        Tapback::Sticker => "sticker",
+    }
+}
+
+/// Resolve the tapbacks on one message (as cached by `Message::cache`) into the
+/// reactions still standing, applying removals in date order
+pub fn collect_reactions<F>(
+    tapbacks: &HashMap<usize, Vec<Message>>,
+    date_of: impl Fn(i64) -> DateTime<FixedOffset>,
+    sender: F,
+) -> Vec<ReactionRecord>
+where
+    F: Fn(&Message) -> Option<String>,
+{
+    let mut rows: Vec<&Message> = tapbacks.values().flatten().collect();
+    rows.sort_by_key(|msg| (msg.date, msg.rowid));
+
+    let mut reactions: Vec<ReactionRecord> = Vec::new();
+    for msg in rows {
+        let Variant::Tapback(_, action, tapback) = msg.variant() else {
+            continue;
+        };
+        let from = sender(msg);
+        let kind = kind_name(&tapback).to_string();
+        match action {
+            TapbackAction::Added => reactions.push(ReactionRecord {
+                emoji: match tapback {
+                    Tapback::Emoji(emoji) => emoji.map(String::from),
+                    _ => None,
+                },
+                kind,
+                from,
+                from_me: msg.is_from_me,
+                date: date_of(msg.date),
+            }),
+            TapbackAction::Removed => {
+                if let Some(pos) = reactions
+                    .iter()
+                    .rposition(|r| r.kind == kind && r.from == from && r.from_me == msg.is_from_me)
+                {
+                    reactions.remove(pos);
+                }
+            }
+        }
+    }
+    reactions
+}
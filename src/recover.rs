@@ -0,0 +1,349 @@
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use tracing::{debug, info};
+
+use crate::body;
+use crate::error::AppError;
+use crate::export::wal_file;
+
+/// The first bytes of a write-ahead log, in either checksum byte order
+const WAL_MAGIC: [u32; 2] = [0x377f_0682, 0x377f_0683];
+const WAL_HEADER_LEN: usize = 32;
+const FRAME_HEADER_LEN: usize = 24;
+/// The page type byte of a table b-tree leaf, where rows live
+const TABLE_LEAF: u8 = 0x0D;
+
+/// A message row found in the write-ahead log that's no longer in the table
+#[derive(Debug, Clone, Default)]
+pub struct CarvedMessage {
+    pub rowid: i64,
+    pub guid: String,
+    pub text: Option<String>,
+    pub service: Option<String>,
+    pub handle_id: Option<i64>,
+    pub destination_caller_id: Option<String>,
+    /// As stored, in seconds or nanoseconds (see [`crate::export::imessage_ns`])
+    pub date: i64,
+    pub is_from_me: bool,
+}
+
+/// A column value from a record, as far as carving needs them
+#[derive(Debug, Clone)]
+enum Field<'a> {
+    Null,
+    Int(i64),
+    Text(&'a [u8]),
+    Blob(&'a [u8]),
+    Other,
+}
+
+impl Field<'_> {
+    fn int(&self) -> Option<i64> {
+        match self {
+            Field::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    fn text(&self) -> Option<String> {
+        match self {
+            Field::Text(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+            _ => None,
+        }
+    }
+}
+
+/// Read an SQLite varint at the start of `data`, returning it and its length
+fn varint(data: &[u8]) -> Option<(i64, usize)> {
+    let mut value: u64 = 0;
+    for i in 0..9 {
+        let byte = *data.get(i)?;
+        if i == 8 {
+            return Some(((value << 8 | u64::from(byte)) as i64, 9));
+        }
+        value = value << 7 | u64::from(byte & 0x7F);
+        if byte & 0x80 == 0 {
+            return Some((value as i64, i + 1));
+        }
+    }
+    None
+}
+
+/// Decode a record's columns. Those that run past `payload`, like the
+/// parts of a long row kept on overflow pages, are left out.
+fn record_fields(payload: &[u8]) -> Option<Vec<Field<'_>>> {
+    let (header_len, mut offset) = varint(payload)?;
+    let header_len = usize::try_from(header_len).ok()?;
+    if header_len > payload.len() || header_len < offset {
+        return None;
+    }
+    let mut body = header_len;
+    let mut fields = Vec::new();
+    while offset < header_len {
+        let (serial, len) = varint(&payload[offset..header_len])?;
+        offset += len;
+        let size = match serial {
+            0 | 8 | 9 => 0,
+            1..=4 => serial as usize,
+            5 => 6,
+            6 | 7 => 8,
+            10 | 11 => return None,
+            // Anything left is 12 or more, or negative in a corrupt log
+            n => (usize::try_from(n).ok()? - 12) / 2,
+        };
+        let Some(bytes) = payload.get(body..body.checked_add(size)?) else {
+            break;
+        };
+        body += size;
+        fields.push(match serial {
+            0 => Field::Null,
+            8 => Field::Int(0),
+            9 => Field::Int(1),
+            1..=6 => {
+                // Big-endian two's complement, sign-extended from its first byte
+                let mut value = if bytes[0] & 0x80 != 0 { -1i64 } else { 0 };
+                for &b in bytes {
+                    value = value << 8 | i64::from(b);
+                }
+                Field::Int(value)
+            }
+            7 => Field::Other,
+            n if n % 2 == 0 => Field::Blob(bytes),
+            _ => Field::Text(bytes),
+        });
+    }
+    Some(fields)
+}
+
+/// Each (rowid, record payload) on a table leaf page, with only the part of
+/// each payload stored on the page itself
+fn leaf_cells(page: &[u8], header_offset: usize) -> Vec<(i64, &[u8])> {
+    let mut cells = Vec::new();
+    let Some(header) = page.get(header_offset..header_offset + 8) else {
+        return cells;
+    };
+    // Pages are at least 512 bytes, which the overflow arithmetic below relies on
+    if header[0] != TABLE_LEAF || page.len() < 512 {
+        return cells;
+    }
+    let usable = page.len();
+    let count = usize::from(u16::from_be_bytes([header[3], header[4]]));
+    for i in 0..count {
+        let at = header_offset + 8 + i * 2;
+        let Some(pointer) = page.get(at..at + 2) else {
+            break;
+        };
+        let cell = usize::from(u16::from_be_bytes([pointer[0], pointer[1]]));
+        let Some(data) = page.get(cell..) else {
+            continue;
+        };
+        let Some((size, a)) = varint(data) else {
+            continue;
+        };
+        let Some((rowid, b)) = data.get(a..).and_then(varint) else {
+            continue;
+        };
+        let Ok(size) = usize::try_from(size) else {
+            continue;
+        };
+        // How much of the payload is on this page, per the file format's overflow rule
+        let max_local = usable - 35;
+        let local = if size <= max_local {
+            size
+        } else {
+            let min_local = (usable - 12) * 32 / 255 - 23;
+            let k = min_local + (size - min_local) % (usable - 4);
+            if k <= max_local { k } else { min_local }
+        };
+        let start = a + b;
+        if let Some(payload) = data.get(start..start + local.min(data.len() - start)) {
+            cells.push((rowid, payload));
+        }
+    }
+    cells
+}
+
+/// Whether `text` looks like a message GUID, `8-4-4-4-12` hex digits
+fn is_guid(text: &[u8]) -> bool {
+    text.len() == 36
+        && text.iter().enumerate().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => *c == b'-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+/// Where the columns carving reads sit in a `message` row
+struct Columns {
+    count: usize,
+    guid: usize,
+    text: usize,
+    service: Option<usize>,
+    handle_id: Option<usize>,
+    destination_caller_id: Option<usize>,
+    date: usize,
+    is_from_me: Option<usize>,
+    associated_message_type: Option<usize>,
+    attributed_body: Option<usize>,
+}
+
+impl Columns {
+    fn read(db: &Connection) -> Result<Option<Self>, AppError> {
+        let names: Vec<String> = db
+            .prepare("SELECT name FROM pragma_table_info('message') ORDER BY cid")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        let find = |name: &str| names.iter().position(|n| n.eq_ignore_ascii_case(name));
+        let (Some(guid), Some(text), Some(date)) = (find("guid"), find("text"), find("date")) else {
+            return Ok(None);
+        };
+        Ok(Some(Columns {
+            count: names.len(),
+            guid,
+            text,
+            date,
+            service: find("service"),
+            handle_id: find("handle_id"),
+            destination_caller_id: find("destination_caller_id"),
+            is_from_me: find("is_from_me"),
+            associated_message_type: find("associated_message_type"),
+            attributed_body: find("attributedBody"),
+        }))
+    }
+
+    /// The message in `fields`, if it looks like a row of the message table
+    fn message(&self, rowid: i64, fields: &[Field]) -> Option<CarvedMessage> {
+        // Rows written before a column was added have fewer, but never more
+        if fields.len() > self.count {
+            return None;
+        }
+        let Field::Text(guid) = fields.get(self.guid)? else {
+            return None;
+        };
+        if !is_guid(guid) {
+            return None;
+        }
+        let get = |i: Option<usize>| i.and_then(|i| fields.get(i));
+        // Tapbacks say nothing by themselves
+        if get(self.associated_message_type).and_then(Field::int).is_some_and(|t| t != 0) {
+            return None;
+        }
+        let text = fields.get(self.text).and_then(Field::text).or_else(|| match get(self.attributed_body) {
+            Some(Field::Blob(blob)) => body::scan_attributed_body(blob),
+            _ => None,
+        });
+        Some(CarvedMessage {
+            rowid,
+            guid: String::from_utf8_lossy(guid).into_owned(),
+            text,
+            service: get(self.service).and_then(Field::text),
+            handle_id: get(self.handle_id).and_then(Field::int).filter(|id| *id != 0),
+            destination_caller_id: get(self.destination_caller_id).and_then(Field::text).filter(|d| !d.is_empty()),
+            date: fields.get(self.date).and_then(Field::int)?,
+            is_from_me: get(self.is_from_me).and_then(Field::int) == Some(1),
+        })
+    }
+}
+
+/// Find messages deleted from the table whose rows are still in the
+/// write-ahead log next to `db_file`
+///
+/// The log keeps earlier versions of every page it has written since the
+/// last checkpoint, so a row that's since been deleted can survive in one.
+/// Each table leaf page in it is read for rows shaped like `message` rows
+/// whose ROWID `live` no longer has. Parts of a long row kept on overflow
+/// pages are lost.
+pub fn carve_wal(db_file: &Path, live: &Connection) -> Result<Vec<CarvedMessage>, AppError> {
+    let wal_path = wal_file(db_file);
+    let Ok(wal) = fs::read(&wal_path) else {
+        debug!(path = ?wal_path, "no write-ahead log to recover from");
+        return Ok(Vec::new());
+    };
+    let Some(columns) = Columns::read(live)? else {
+        return Ok(Vec::new());
+    };
+    let Some(header) = wal.get(..WAL_HEADER_LEN) else {
+        return Ok(Vec::new());
+    };
+    let magic = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    let page_size = u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as usize;
+    if !WAL_MAGIC.contains(&magic) || !(512..=65536).contains(&page_size) {
+        return Ok(Vec::new());
+    }
+
+    // Later frames hold newer versions of a page, so the last copy of a row wins
+    let mut found: BTreeMap<i64, CarvedMessage> = BTreeMap::new();
+    let mut frame = WAL_HEADER_LEN;
+    while let Some(data) = wal.get(frame..frame + FRAME_HEADER_LEN + page_size) {
+        let page_number = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let page = &data[FRAME_HEADER_LEN..];
+        // The first page starts with the 100-byte database header
+        let header_offset = if page_number == 1 { 100 } else { 0 };
+        for (rowid, payload) in leaf_cells(page, header_offset) {
+            if let Some(message) = record_fields(payload).and_then(|fields| columns.message(rowid, &fields)) {
+                found.insert(rowid, message);
+            }
+        }
+        frame += FRAME_HEADER_LEN + page_size;
+    }
+
+    let mut exists = live.prepare("SELECT 1 FROM message WHERE ROWID = ?1 OR guid = ?2")?;
+    let mut deleted = Vec::new();
+    for message in found.into_values() {
+        let live_row: Option<i32> =
+            exists.query_row(rusqlite::params![message.rowid, message.guid], |row| row.get(0)).optional()?;
+        if live_row.is_none() {
+            deleted.push(message);
+        }
+    }
+    info!(messages = deleted.len(), "recovered deleted messages from the write-ahead log");
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varint_reads_one_to_nine_bytes() {
+        assert_eq!(varint(&[0x05]), Some((5, 1)));
+        assert_eq!(varint(&[0x81, 0x00]), Some((128, 2)));
+        // The ninth byte contributes all eight of its bits
+        let mut nine = [0xFF; 9];
+        nine[8] = 0x01;
+        assert_eq!(varint(&nine), Some((-255, 9)));
+        assert_eq!(varint(&[0x81, 0x80]), None);
+    }
+
+    #[test]
+    fn truncated_record_keeps_the_fields_before_the_cut() {
+        // An int 42, then 5 bytes of text of which only 2 are there
+        let payload = [3, 0x01, 0x17, 42, b'h', b'e'];
+        let fields = record_fields(&payload).unwrap();
+        assert_eq!(fields.len(), 1);
+        assert!(matches!(fields[0], Field::Int(42)));
+    }
+
+    #[test]
+    fn negative_serial_type_is_rejected() {
+        let mut payload = vec![10];
+        payload.extend_from_slice(&[0xFF; 8]);
+        payload.push(0x01);
+        assert!(record_fields(&payload).is_none());
+    }
+
+    #[test]
+    fn leaf_cells_skip_pointers_past_the_page() {
+        let mut page = vec![0u8; 512];
+        page[0] = TABLE_LEAF;
+        page[3..5].copy_from_slice(&2u16.to_be_bytes());
+        page[8..10].copy_from_slice(&0xFFFFu16.to_be_bytes());
+        page[10..12].copy_from_slice(&100u16.to_be_bytes());
+        // Payload size 2, rowid 7, then a record holding the integer 1
+        page[100..104].copy_from_slice(&[2, 7, 2, 0x09]);
+        let cells = leaf_cells(&page, 0);
+        assert_eq!(cells, vec![(7, &[2u8, 0x09][..])]);
+        assert!(matches!(record_fields(cells[0].1).unwrap()[..], [Field::Int(1)]));
+    }
+}
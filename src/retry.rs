@@ -0,0 +1,297 @@
+use chrono::{DateTime, Duration, Utc};
+use imessage_database::util::dirs::home;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use crate::error::AppError;
+use crate::optout::{self, SuppressionList};
+use crate::send::{self, OutgoingMessage, SendResult};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS failures (
+    id INTEGER PRIMARY KEY,
+    recipient TEXT NOT NULL,
+    text TEXT NOT NULL,
+    attachments TEXT NOT NULL,
+    account TEXT,
+    status TEXT NOT NULL DEFAULT 'pending',
+    error TEXT,
+    attempts INTEGER NOT NULL,
+    next_attempt_at INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    last_attempt_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS failures_due ON failures(status, next_attempt_at);
+";
+
+/// Wait before the first retry; each later one waits twice as long
+const FIRST_BACKOFF_SECS: i64 = 60;
+/// Longest wait between retries
+const MAX_BACKOFF_SECS: i64 = 24 * 60 * 60;
+/// Give up on a send after this many failed attempts, the first included
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// How long to wait after the `attempts`th failure before trying again
+pub fn backoff(attempts: u32) -> Duration {
+    let secs = FIRST_BACKOFF_SECS.saturating_mul(1 << attempts.saturating_sub(1).min(20));
+    Duration::seconds(secs.min(MAX_BACKOFF_SECS))
+}
+
+/// A failed send waiting to be tried again
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedSend {
+    pub id: i64,
+    pub recipient: String,
+    pub error: Option<String>,
+    pub attempts: u32,
+    pub next_attempt_at: DateTime<Utc>,
+    #[serde(skip)]
+    pub message: OutgoingMessage,
+}
+
+/// The parts of `message` that didn't go out: the text if it failed, and
+/// the attachments that weren't sent. `None` when everything was sent.
+pub fn unsent_part(message: &OutgoingMessage, result: &SendResult) -> Option<OutgoingMessage> {
+    if result.success {
+        return None;
+    }
+    let text_failed = result.error.is_some();
+    let attachments = message
+        .attachments
+        .iter()
+        .filter(|path| {
+            let path = path.to_string_lossy();
+            !result.attachments.iter().any(|a| a.path == path && a.success)
+        })
+        .cloned()
+        .collect();
+    Some(OutgoingMessage {
+        recipient: message.recipient.clone(),
+        text: if text_failed { message.text.clone() } else { String::new() },
+        attachments,
+        account: message.account.clone(),
+    })
+}
+
+/// Sends that failed, stored in SQLite so they can be replayed with backoff
+pub struct RetryQueue {
+    db: Connection,
+}
+
+impl RetryQueue {
+    /// `~/.imessage-blaster/retries.sqlite`
+    pub fn default_path() -> PathBuf {
+        PathBuf::from(home()).join(".imessage-blaster").join("retries.sqlite")
+    }
+
+    pub fn open(path: &Path) -> Result<Self, AppError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let db = Connection::open(path)?;
+        db.execute_batch(SCHEMA)?;
+        // Queues from before --from-account don't have the column
+        if !db.prepare("SELECT 1 FROM pragma_table_info('failures') WHERE name = 'account'")?.exists([])? {
+            db.execute("ALTER TABLE failures ADD COLUMN account TEXT", [])?;
+        }
+        Ok(RetryQueue { db })
+    }
+
+    pub fn open_default() -> Result<Self, AppError> {
+        Self::open(&Self::default_path())
+    }
+
+    /// Remember whatever part of `message` failed to send, returning its ID.
+    /// Successful sends aren't recorded.
+    pub fn record(&self, message: &OutgoingMessage, result: &SendResult) -> Result<Option<i64>, AppError> {
+        let Some(unsent) = unsent_part(message, result) else {
+            return Ok(None);
+        };
+        let attachments: Vec<String> = unsent.attachments.iter().map(|p| p.to_string_lossy().to_string()).collect();
+        let error = result.error.clone().or_else(|| result.attachments.iter().find_map(|a| a.error.clone()));
+        let now = Utc::now();
+        self.db.execute(
+            "INSERT INTO failures (recipient, text, attachments, account, error, attempts, next_attempt_at, created_at, last_attempt_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, 1, ?6, ?7, ?7)",
+            params![
+                unsent.recipient,
+                unsent.text,
+                serde_json::to_string(&attachments)?,
+                unsent.account,
+                error,
+                (now + backoff(1)).timestamp(),
+                now.timestamp()
+            ],
+        )?;
+        Ok(Some(self.db.last_insert_rowid()))
+    }
+
+    /// Failed sends still to be retried, oldest first; only those whose
+    /// backoff has passed unless `include_waiting` is set
+    pub fn pending(&self, now: DateTime<Utc>, include_waiting: bool) -> Result<Vec<FailedSend>, AppError> {
+        let mut statement = self.db.prepare(
+            "SELECT id, recipient, text, attachments, error, attempts, next_attempt_at, account FROM failures
+             WHERE status = 'pending' AND (?1 OR next_attempt_at <= ?2)
+             ORDER BY next_attempt_at, id",
+        )?;
+        let rows = statement.query_map(params![include_waiting, now.timestamp()], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, Option<String>>(4)?,
+                row.get::<_, u32>(5)?,
+                row.get::<_, i64>(6)?,
+                row.get::<_, Option<String>>(7)?,
+            ))
+        })?;
+
+        let mut pending = Vec::new();
+        for row in rows {
+            let (id, recipient, text, attachments, error, attempts, next_attempt_at, account) = row?;
+            let attachments: Vec<PathBuf> = serde_json::from_str::<Vec<String>>(&attachments)?
+                .into_iter()
+                .map(PathBuf::from)
+                .collect();
+            pending.push(FailedSend {
+                id,
+                recipient: recipient.clone(),
+                error,
+                attempts,
+                next_attempt_at: DateTime::from_timestamp(next_attempt_at, 0).unwrap_or_default(),
+                message: OutgoingMessage { recipient, text, attachments, account },
+            });
+        }
+        Ok(pending)
+    }
+
+    /// Try a failed send again. Another failure pushes the next attempt back,
+    /// or abandons the send once it has failed `max_attempts` times.
+    pub fn retry(&self, job: &FailedSend, optouts: &SuppressionList, max_attempts: u32) -> Result<SendResult, AppError> {
+        let now = Utc::now();
+        if optouts.contains(&job.recipient)? {
+            let result = optout::suppressed_result(&job.recipient);
+            self.db.execute(
+                "UPDATE failures SET status = 'suppressed', error = ?1, last_attempt_at = ?2 WHERE id = ?3",
+                params![result.error, now.timestamp(), job.id],
+            )?;
+            return Ok(result);
+        }
+        let result = send::send_one(&job.message);
+        let attempts = job.attempts + 1;
+        match unsent_part(&job.message, &result) {
+            None => {
+                self.db.execute(
+                    "UPDATE failures SET status = 'sent', error = NULL, attempts = ?1, last_attempt_at = ?2 WHERE id = ?3",
+                    params![attempts, now.timestamp(), job.id],
+                )?;
+            }
+            Some(unsent) => {
+                let status = if attempts >= max_attempts { "abandoned" } else { "pending" };
+                let error = result.error.clone().or_else(|| result.attachments.iter().find_map(|a| a.error.clone()));
+                let attachments: Vec<String> =
+                    unsent.attachments.iter().map(|p| p.to_string_lossy().to_string()).collect();
+                self.db.execute(
+                    "UPDATE failures SET status = ?1, error = ?2, attempts = ?3, text = ?4, attachments = ?5,
+                     next_attempt_at = ?6, last_attempt_at = ?7 WHERE id = ?8",
+                    params![
+                        status,
+                        error,
+                        attempts,
+                        unsent.text,
+                        serde_json::to_string(&attachments)?,
+                        (now + backoff(attempts)).timestamp(),
+                        now.timestamp(),
+                        job.id
+                    ],
+                )?;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Retry every pending send in turn, pausing `delay` between them
+    pub fn retry_all(
+        &self,
+        jobs: &[FailedSend],
+        max_attempts: u32,
+        delay: std::time::Duration,
+    ) -> Result<Vec<SendResult>, AppError> {
+        let optouts = SuppressionList::open_default()?;
+        let mut results = Vec::new();
+        for (i, job) in jobs.iter().enumerate() {
+            if i > 0 {
+                thread::sleep(delay);
+            }
+            results.push(self.retry(job, &optouts, max_attempts)?);
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::send::AttachmentResult;
+
+    fn message() -> OutgoingMessage {
+        OutgoingMessage {
+            recipient: "+15551234567".to_string(),
+            text: "Hi".to_string(),
+            attachments: vec![PathBuf::from("/tmp/a.jpg"), PathBuf::from("/tmp/b.jpg")],
+            account: None,
+        }
+    }
+
+    fn attachment(path: &str, success: bool) -> AttachmentResult {
+        AttachmentResult {
+            path: path.to_string(),
+            success,
+            error: (!success).then(|| "failed".to_string()),
+        }
+    }
+
+    fn result(error: Option<&str>, attachments: Vec<AttachmentResult>) -> SendResult {
+        SendResult {
+            recipient: "+15551234567".to_string(),
+            success: error.is_none() && attachments.iter().all(|a| a.success),
+            error: error.map(str::to_string),
+            attachments,
+        }
+    }
+
+    #[test]
+    fn nothing_is_left_when_everything_went() {
+        let sent = result(None, vec![attachment("/tmp/a.jpg", true), attachment("/tmp/b.jpg", true)]);
+        assert!(unsent_part(&message(), &sent).is_none());
+    }
+
+    #[test]
+    fn only_failed_attachments_are_left() {
+        let sent = result(None, vec![attachment("/tmp/a.jpg", true), attachment("/tmp/b.jpg", false)]);
+        let unsent = unsent_part(&message(), &sent).unwrap();
+        assert_eq!(unsent.text, "");
+        assert_eq!(unsent.attachments, [PathBuf::from("/tmp/b.jpg")]);
+    }
+
+    #[test]
+    fn a_failed_text_leaves_the_text_and_unsent_attachments() {
+        let unsent = unsent_part(&message(), &result(Some("text failed"), vec![attachment("/tmp/a.jpg", false)])).unwrap();
+        assert_eq!(unsent.text, "Hi");
+        assert_eq!(unsent.attachments, message().attachments);
+        // Attachments that did go out aren't sent again
+        let sent = result(Some("text failed"), vec![attachment("/tmp/a.jpg", true), attachment("/tmp/b.jpg", false)]);
+        assert_eq!(unsent_part(&message(), &sent).unwrap().attachments, [PathBuf::from("/tmp/b.jpg")]);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        assert!(backoff(1) < backoff(2));
+        assert_eq!(backoff(2), backoff(1) * 2);
+        assert_eq!(backoff(60), Duration::seconds(MAX_BACKOFF_SECS));
+    }
+}
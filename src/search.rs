@@ -0,0 +1,219 @@
+use chrono::{DateTime, FixedOffset};
+use imessage_database::util::dirs::home;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::info;
+
+use crate::error::AppError;
+use crate::export::{ExportOptions, MessageExporter};
+use crate::reactions::ReactionMode;
+use crate::timezone::Zone;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS messages (
+    id INTEGER PRIMARY KEY,
+    date INTEGER NOT NULL,
+    sender TEXT,
+    sender_name TEXT,
+    from_me INTEGER NOT NULL,
+    chat_id INTEGER,
+    chat_name TEXT,
+    text TEXT NOT NULL
+);
+CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
+    text,
+    content = 'messages',
+    content_rowid = 'id',
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+CREATE TABLE IF NOT EXISTS state (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+";
+
+/// What an `index` run did
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexReport {
+    /// Messages added this run
+    pub indexed: usize,
+    /// Messages in the index now
+    pub total: i64,
+    pub path: PathBuf,
+}
+
+/// A message matching a search, best match first
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub id: i64,
+    pub date: DateTime<FixedOffset>,
+    /// The sender's handle, or the user's own for their messages
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_name: Option<String>,
+    pub from_me: bool,
+    pub chat_id: Option<i64>,
+    pub chat_name: Option<String>,
+    pub text: String,
+    /// The matching part of the text, with matches in `[brackets]`
+    pub snippet: String,
+    /// BM25 relevance; lower is a better match
+    pub rank: f64,
+}
+
+/// A full-text index of message text, kept in SQLite beside chat.db's copy
+///
+/// Messages are added in ROWID order, so each `update` only reads the ones
+/// that arrived since the last. Edits and deletions made after a message
+/// was indexed aren't picked up until it's rebuilt.
+pub struct SearchIndex {
+    db: Connection,
+    path: PathBuf,
+}
+
+impl SearchIndex {
+    /// `~/.imessage-blaster/search.sqlite`
+    pub fn default_path() -> PathBuf {
+        PathBuf::from(home()).join(".imessage-blaster").join("search.sqlite")
+    }
+
+    pub fn open(path: &Path) -> Result<Self, AppError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let db = Connection::open(path)?;
+        db.execute_batch(SCHEMA)?;
+        Ok(SearchIndex { db, path: path.to_path_buf() })
+    }
+
+    fn state(&self, key: &str) -> Result<Option<String>, AppError> {
+        Ok(self.db.query_row("SELECT value FROM state WHERE key = ?1", [key], |row| row.get(0)).optional()?)
+    }
+
+    fn set_state(&self, key: &str, value: &str) -> Result<(), AppError> {
+        self.db.execute(
+            "INSERT INTO state (key, value) VALUES (?1, ?2) ON CONFLICT(key) DO UPDATE SET value = ?2",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    /// Drop everything indexed so far
+    pub fn clear(&self) -> Result<(), AppError> {
+        self.db.execute_batch(
+            "DELETE FROM messages;
+             INSERT INTO messages_fts (messages_fts) VALUES ('delete-all');
+             DELETE FROM state;",
+        )?;
+        Ok(())
+    }
+
+    /// Add the messages `options` would export that aren't indexed yet.
+    /// Starts over if the index was built from a different database.
+    pub fn update(&mut self, options: ExportOptions) -> Result<IndexReport, AppError> {
+        let started = Instant::now();
+        let source = options.db_path.to_string_lossy().into_owned();
+        if self.state("db_path")?.is_some_and(|indexed| indexed != source) {
+            info!(path = %source, "index was built from another database; rebuilding it");
+            self.clear()?;
+        }
+        let last_rowid = self.state("last_rowid")?.and_then(|v| v.parse().ok());
+        let options = ExportOptions {
+            start_date: None,
+            end_date: None,
+            after_rowid: last_rowid,
+            // Only what people wrote is worth searching
+            clean: true,
+            reactions: ReactionMode::Exclude,
+            attachments_dir: None,
+            transcribe_audio: None,
+            limit: None,
+            ..options
+        };
+        let mut exporter = MessageExporter::new(options)?;
+
+        let tx = self.db.transaction()?;
+        let mut indexed = 0;
+        {
+            let mut insert = tx.prepare(
+                "INSERT OR REPLACE INTO messages (id, date, sender, sender_name, from_me, chat_id, chat_name, text)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            let mut insert_fts = tx.prepare("INSERT INTO messages_fts (rowid, text) VALUES (?1, ?2)")?;
+            for record in exporter.by_ref() {
+                let record = record?;
+                let Some(text) = record.text.as_deref().filter(|t| !t.trim().is_empty()) else {
+                    continue;
+                };
+                insert.execute(params![
+                    record.id,
+                    record.date.timestamp(),
+                    record.from,
+                    record.from_name,
+                    record.from_me,
+                    record.chat_id,
+                    record.chat_name,
+                    text,
+                ])?;
+                insert_fts.execute(params![record.id, text])?;
+                indexed += 1;
+            }
+        }
+        tx.commit()?;
+        if let Some((rowid, _)) = exporter.last_seen() {
+            self.set_state("last_rowid", &rowid.to_string())?;
+        }
+        self.set_state("db_path", &source)?;
+
+        let total = self.db.query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0))?;
+        info!(indexed, total, elapsed = ?started.elapsed(), "updated search index");
+        Ok(IndexReport { indexed, total, path: self.path.clone() })
+    }
+
+    /// Messages matching `query`, best first. Plain words all have to match,
+    /// in any order; with `raw`, `query` is FTS5 syntax, which takes `OR`,
+    /// `NEAR(...)`, `"exact phrases"` and `prefix*`.
+    pub fn search(&self, query: &str, raw: bool, limit: usize, timezone: Zone) -> Result<Vec<SearchHit>, AppError> {
+        let indexed: i64 = self.db.query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0))?;
+        if indexed == 0 {
+            return Err(AppError::Args("The search index is empty; build it with the index command".to_string()));
+        }
+        let query = if raw {
+            query.to_string()
+        } else {
+            query.split_whitespace().map(|word| format!("\"{}\"", word.replace('"', "\"\""))).collect::<Vec<_>>().join(" ")
+        };
+        let mut statement = self.db.prepare(
+            "SELECT m.id, m.date, m.sender, m.sender_name, m.from_me, m.chat_id, m.chat_name, m.text,
+                    snippet(messages_fts, 0, '[', ']', '…', 16), bm25(messages_fts)
+             FROM messages_fts JOIN messages m ON m.id = messages_fts.rowid
+             WHERE messages_fts MATCH ?1
+             ORDER BY bm25(messages_fts), m.date DESC
+             LIMIT ?2",
+        )?;
+        let rows = statement.query_map(params![query, limit as i64], |row| {
+            Ok(SearchHit {
+                id: row.get(0)?,
+                date: timezone.localize(DateTime::from_timestamp(row.get(1)?, 0).unwrap_or_default()),
+                from: row.get(2)?,
+                from_name: row.get(3)?,
+                from_me: row.get(4)?,
+                chat_id: row.get(5)?,
+                chat_name: row.get(6)?,
+                text: row.get(7)?,
+                snippet: row.get(8)?,
+                rank: row.get(9)?,
+            })
+        })?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| match e {
+            // A bad FTS5 query only shows up when it runs
+            rusqlite::Error::SqliteFailure(_, Some(message)) if raw => {
+                AppError::Args(format!("Invalid search query: {}", message))
+            }
+            e => e.into(),
+        })
+    }
+}
@@ -0,0 +1,374 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::contacts::handle_key;
+use crate::error::AppError;
+
+/// Sends `sendText` to `targetHandle` from the account whose ID is
+/// `accountId`, or the first iMessage account when it's empty, reusing an
+/// existing buddy if there is one and otherwise starting a new chat
+const SEND_SCRIPT: &str = r#"
+on run {targetHandle, sendText, accountId}
+    tell application "Messages"
+        if accountId is "" then
+            -- pick the first iMessage account (works for most setups)
+            set targetService to first service whose service type = iMessage
+        else
+            set targetService to service id accountId
+        end if
+
+        -- try to reuse an existing chat, otherwise create one
+        if (exists (buddy targetHandle of targetService)) then
+            set targetBuddy to buddy targetHandle of targetService
+            send sendText to targetBuddy
+        else
+            set newChat to make new text chat with properties {service:targetService, participants:{targetHandle}}
+            send sendText to newChat
+        end if
+    end tell
+end run
+"#;
+
+/// Sends the file at `filePath` to `targetHandle` the same way
+const SEND_FILE_SCRIPT: &str = r#"
+on run {targetHandle, filePath, accountId}
+    tell application "Messages"
+        if accountId is "" then
+            set targetService to first service whose service type = iMessage
+        else
+            set targetService to service id accountId
+        end if
+        set theFile to POSIX file filePath
+
+        if (exists (buddy targetHandle of targetService)) then
+            send theFile to buddy targetHandle of targetService
+        else
+            set newChat to make new text chat with properties {service:targetService, participants:{targetHandle}}
+            send theFile to newChat
+        end if
+    end tell
+end run
+"#;
+
+/// Sends `sendText` to the existing chat whose ID is `chatId`, for group chats
+const SEND_CHAT_SCRIPT: &str = r#"
+on run {chatId, sendText}
+    tell application "Messages"
+        send sendText to chat id chatId
+    end tell
+end run
+"#;
+
+/// Sends the file at `filePath` to the existing chat whose ID is `chatId`
+const SEND_CHAT_FILE_SCRIPT: &str = r#"
+on run {chatId, filePath}
+    tell application "Messages"
+        send (POSIX file filePath) to chat id chatId
+    end tell
+end run
+"#;
+
+/// Lists every account Messages is set up with, a line each of its ID,
+/// service type, description and whether it's enabled, separated by tabs
+const ACCOUNTS_SCRIPT: &str = r#"
+on run
+    set output to ""
+    tell application "Messages"
+        repeat with anAccount in services
+            set output to output & (id of anAccount) & tab & (service type of anAccount as text) & tab & (description of anAccount) & tab & (enabled of anAccount as text) & linefeed
+        end repeat
+    end tell
+    return output
+end run
+"#;
+
+/// Largest attachment iMessage will deliver
+pub const MAX_ATTACHMENT_BYTES: u64 = 100 * 1024 * 1024;
+
+/// MIME types Messages can send, by file extension
+const ATTACHMENT_TYPES: &[(&str, &str)] = &[
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("png", "image/png"),
+    ("gif", "image/gif"),
+    ("heic", "image/heic"),
+    ("heif", "image/heif"),
+    ("webp", "image/webp"),
+    ("tiff", "image/tiff"),
+    ("mov", "video/quicktime"),
+    ("mp4", "video/mp4"),
+    ("m4v", "video/x-m4v"),
+    ("m4a", "audio/mp4"),
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("caf", "audio/x-caf"),
+    ("pdf", "application/pdf"),
+    ("txt", "text/plain"),
+    ("csv", "text/csv"),
+    ("vcf", "text/vcard"),
+    ("zip", "application/zip"),
+    ("pages", "application/x-iwork-pages-sffpages"),
+    ("numbers", "application/x-iwork-numbers-sffnumbers"),
+    ("key", "application/x-iwork-keynote-sffkey"),
+    ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+    ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+    ("pptx", "application/vnd.openxmlformats-officedocument.presentationml.presentation"),
+];
+
+/// Outcome of sending one attachment
+#[derive(Debug, Clone, Serialize)]
+pub struct AttachmentResult {
+    pub path: String,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+/// Outcome of sending to a single recipient
+#[derive(Debug, Clone, Serialize)]
+pub struct SendResult {
+    pub recipient: String,
+    pub success: bool,
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<AttachmentResult>,
+}
+
+/// The MIME type of a file Messages can send, from its extension
+pub fn attachment_mime_type(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    ATTACHMENT_TYPES.iter().find(|(e, _)| *e == ext).map(|(_, mime)| *mime)
+}
+
+/// Check that `path` is a file Messages can send before trying to send it
+pub fn validate_attachment(path: &Path) -> Result<(), AppError> {
+    let invalid = |why: String| AppError::Send(format!("{}: {}", path.display(), why));
+    let metadata = fs::metadata(path).map_err(|e| invalid(e.to_string()))?;
+    if !metadata.is_file() {
+        return Err(invalid("not a file".to_string()));
+    }
+    if metadata.len() > MAX_ATTACHMENT_BYTES {
+        return Err(invalid(format!(
+            "{} MB is over the {} MB limit",
+            metadata.len() / (1024 * 1024),
+            MAX_ATTACHMENT_BYTES / (1024 * 1024)
+        )));
+    }
+    if attachment_mime_type(path).is_none() {
+        return Err(invalid("unsupported file type".to_string()));
+    }
+    Ok(())
+}
+
+/// Run an AppleScript with `osascript`, passing `args` through to its `run` handler
+pub(crate) fn run_applescript(script: &str, args: &[&str]) -> Result<String, AppError> {
+    // Pass the script on stdin and the arguments on argv so nothing needs escaping
+    let mut child = Command::new("osascript")
+        .arg("-")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    child.stdin.take().unwrap().write_all(script.as_bytes())?;
+    let output = child.wait_with_output()?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        Err(AppError::Send(if stderr.is_empty() {
+            format!("osascript exited with {}", output.status)
+        } else {
+            stderr
+        }))
+    }
+}
+
+/// Whether a recipient is a chat's GUID, like `iMessage;+;chat123456`, rather
+/// than a handle. Phone numbers and emails never contain a `;`.
+pub fn is_chat_guid(recipient: &str) -> bool {
+    recipient.contains(';')
+}
+
+/// An account Messages can send from
+#[derive(Debug, Clone, Serialize)]
+pub struct Account {
+    pub id: String,
+    /// `iMessage`, or `SMS` for Text Message Forwarding from an iPhone
+    pub service_type: String,
+    /// The Apple ID or phone number the account is signed in with
+    pub description: String,
+    pub enabled: bool,
+}
+
+impl Account {
+    /// Whether `login` names this account: by its email or phone number in
+    /// any format, its ID, or its service type, like `sms`
+    pub fn is(&self, login: &str) -> bool {
+        let login = login.trim();
+        self.id.eq_ignore_ascii_case(login)
+            || self.service_type.eq_ignore_ascii_case(login)
+            || (!self.description.is_empty() && handle_key(&self.description) == handle_key(login))
+    }
+}
+
+/// Every account Messages is set up with
+pub fn accounts() -> Result<Vec<Account>, AppError> {
+    let listing = run_applescript(ACCOUNTS_SCRIPT, &[])?;
+    Ok(listing
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            Some(Account {
+                id: fields.next()?.to_string(),
+                service_type: fields.next()?.to_string(),
+                description: fields.next()?.to_string(),
+                enabled: fields.next()? == "true",
+            })
+        })
+        .collect())
+}
+
+/// The enabled account `login` names, for `send --from-account`
+pub fn find_account(login: &str) -> Result<Account, AppError> {
+    let accounts = accounts()?;
+    let Some(account) = accounts.iter().find(|a| a.is(login)) else {
+        let known: Vec<String> = accounts.iter().map(|a| format!("{} ({})", a.description, a.service_type)).collect();
+        return Err(AppError::Args(format!(
+            "No Messages account matches '{}'. Accounts: {}",
+            login,
+            if known.is_empty() { "none".to_string() } else { known.join(", ") }
+        )));
+    };
+    if !account.enabled {
+        return Err(AppError::Args(format!(
+            "The {} account {} is turned off in Messages settings",
+            account.service_type, account.description
+        )));
+    }
+    Ok(account.clone())
+}
+
+/// Send an iMessage to a phone number, email or chat GUID via Messages.app
+pub fn send_message(recipient: &str, text: &str) -> Result<(), AppError> {
+    send_message_from(recipient, text, None)
+}
+
+/// Send a message from the account with ID `account`, or the first iMessage
+/// account. Chat GUIDs always send from the account the chat is on.
+pub fn send_message_from(recipient: &str, text: &str, account: Option<&str>) -> Result<(), AppError> {
+    if is_chat_guid(recipient) {
+        return run_applescript(SEND_CHAT_SCRIPT, &[recipient, text]).map(|_| ());
+    }
+    run_applescript(SEND_SCRIPT, &[recipient, text, account.unwrap_or_default()]).map(|_| ())
+}
+
+/// Send a file to a phone number, email or chat GUID via Messages.app
+pub fn send_file(recipient: &str, path: &Path) -> Result<(), AppError> {
+    send_file_from(recipient, path, None)
+}
+
+/// Send a file from the account with ID `account`, like [`send_message_from`]
+pub fn send_file_from(recipient: &str, path: &Path, account: Option<&str>) -> Result<(), AppError> {
+    validate_attachment(path)?;
+    // Messages resolves the POSIX file itself, so hand it an absolute path
+    let path = fs::canonicalize(path)?;
+    let path = path.to_string_lossy();
+    if is_chat_guid(recipient) {
+        return run_applescript(SEND_CHAT_FILE_SCRIPT, &[recipient, &path]).map(|_| ());
+    }
+    run_applescript(SEND_FILE_SCRIPT, &[recipient, &path, account.unwrap_or_default()]).map(|_| ())
+}
+
+/// A rendered message ready to go to one recipient
+#[derive(Debug, Clone)]
+pub struct OutgoingMessage {
+    /// A phone number or email, or the GUID of a group chat
+    pub recipient: String,
+    /// Empty when only sending attachments
+    pub text: String,
+    pub attachments: Vec<PathBuf>,
+    /// ID of the account to send from; the first iMessage account if `None`
+    pub account: Option<String>,
+}
+
+/// Send a message and its attachments, reporting how each part went. The
+/// attachments aren't sent if the text fails, so they don't arrive on their own.
+pub fn send_one(message: &OutgoingMessage) -> SendResult {
+    let result = if message.text.is_empty() {
+        Ok(())
+    } else {
+        send_message_from(&message.recipient, &message.text, message.account.as_deref())
+    };
+    let attachments: Vec<AttachmentResult> = message
+        .attachments
+        .iter()
+        .map(|path| {
+            if result.is_err() {
+                return AttachmentResult {
+                    path: path.to_string_lossy().to_string(),
+                    success: false,
+                    error: Some("not sent, as the text failed".to_string()),
+                };
+            }
+            let result = send_file_from(&message.recipient, path, message.account.as_deref());
+            AttachmentResult {
+                path: path.to_string_lossy().to_string(),
+                success: result.is_ok(),
+                error: result.err().map(|e| e.to_string()),
+            }
+        })
+        .collect();
+    SendResult {
+        recipient: message.recipient.clone(),
+        success: result.is_ok() && attachments.iter().all(|a| a.success),
+        error: result.err().map(|e| e.to_string()),
+        attachments,
+    }
+}
+
+/// A message `send --dry-run` would send, and when
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedSend {
+    pub recipient: String,
+    pub text: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<String>,
+    pub send_at: DateTime<Utc>,
+}
+
+/// When each message would go out if sending started at `start`, pausing
+/// `delay` between sends like [`send_all`]
+pub fn plan(messages: &[OutgoingMessage], start: DateTime<Utc>, delay: std::time::Duration) -> Vec<PlannedSend> {
+    let delay = chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::MAX);
+    messages
+        .iter()
+        .enumerate()
+        .map(|(i, message)| PlannedSend {
+            recipient: message.recipient.clone(),
+            text: message.text.clone(),
+            attachments: message.attachments.iter().map(|p| p.to_string_lossy().to_string()).collect(),
+            send_at: delay
+                .checked_mul(i as i32)
+                .and_then(|offset| start.checked_add_signed(offset))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+        })
+        .collect()
+}
+
+/// Send each message in turn, pausing `delay` between sends
+pub fn send_all(messages: &[OutgoingMessage], delay: std::time::Duration) -> Vec<SendResult> {
+    let mut results = Vec::new();
+    for (i, message) in messages.iter().enumerate() {
+        if i > 0 {
+            std::thread::sleep(delay);
+        }
+        results.push(send_one(message));
+    }
+    results
+}
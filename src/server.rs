@@ -0,0 +1,310 @@
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{info, warn};
+
+use crate::chats;
+use crate::dates;
+use crate::error::AppError;
+use crate::export::{ExportOptions, MessageExporter};
+use crate::optout::{self, SuppressionList};
+use crate::output;
+use crate::send::{self, OutgoingMessage};
+
+/// Most messages `GET /messages` returns when no `limit` is given
+const DEFAULT_LIMIT: usize = 1000;
+
+/// Largest request body the servers read, in bytes
+pub(crate) const MAX_BODY: u64 = 1024 * 1024;
+
+/// Shortest token the servers accept, so it can't be guessed
+pub const MIN_TOKEN_LEN: usize = 16;
+
+/// Body of `POST /send`
+#[derive(Debug, Deserialize)]
+struct SendRequest {
+    /// One recipient or a list of them
+    to: Recipients,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    attachments: Vec<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Recipients {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// An HTTP API over the export and send functions, for dashboards and home
+/// automation. Every request needs `Authorization: Bearer <token>`.
+pub struct ApiServer {
+    /// Database, timezone and other settings each request starts from
+    defaults: ExportOptions,
+    token: String,
+    /// The only directory `POST /send` takes attachments from, resolved
+    attachments_from: Option<PathBuf>,
+}
+
+/// Compare without stopping at the first difference, so response times don't leak the token
+pub(crate) fn same_token(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+pub(crate) fn json_response(status: u16, body: Vec<u8>) -> Response<std::io::Cursor<Vec<u8>>> {
+    let content_type = Header::from_bytes("Content-Type", "application/json").expect("header is valid");
+    Response::from_data(body).with_status_code(status).with_header(content_type)
+}
+
+pub(crate) fn error_body(message: &str) -> Vec<u8> {
+    json!({ "error": message }).to_string().into_bytes()
+}
+
+/// Read a request's body, refusing one over [`MAX_BODY`] rather than
+/// holding however much a client sends in memory
+pub(crate) fn read_body(request: &mut Request) -> Result<String, AppError> {
+    if request.body_length().is_some_and(|len| len as u64 > MAX_BODY) {
+        return Err(AppError::BodyTooLarge(MAX_BODY));
+    }
+    let mut body = String::new();
+    request.as_reader().take(MAX_BODY + 1).read_to_string(&mut body)?;
+    if body.len() as u64 > MAX_BODY {
+        return Err(AppError::BodyTooLarge(MAX_BODY));
+    }
+    Ok(body)
+}
+
+impl ApiServer {
+    /// A server answering requests with `token`, sending attachments only
+    /// from inside `attachments_from`, if given
+    pub fn new(defaults: ExportOptions, token: String, attachments_from: Option<&Path>) -> Result<Self, AppError> {
+        let attachments_from = attachments_from.map(Path::canonicalize).transpose()?;
+        Ok(ApiServer { defaults, token, attachments_from })
+    }
+
+    /// Serve requests on `addr`, like `127.0.0.1:8080`, until the process is stopped
+    pub fn run(&self, addr: &str) -> Result<(), AppError> {
+        let server = Server::http(addr).map_err(|e| AppError::Http(format!("Couldn't listen on {}: {}", addr, e)))?;
+        info!(%addr, "listening");
+        for mut request in server.incoming_requests() {
+            let (status, body) = if self.authorized(&request) {
+                match self.handle(&mut request) {
+                    Ok(Some(body)) => (200, body),
+                    Ok(None) => (404, error_body("no such endpoint")),
+                    Err(AppError::Args(e)) => (400, error_body(&e)),
+                    Err(e @ AppError::BodyTooLarge(_)) => (413, error_body(&e.to_string())),
+                    Err(e) => {
+                        warn!(error = %e, url = request.url().split('?').next(), "request failed");
+                        (500, error_body(&e.to_string()))
+                    }
+                }
+            } else {
+                (401, error_body("missing or wrong bearer token"))
+            };
+            // Only the path, as the query string can hold phone numbers and message text
+            info!(method = %request.method(), url = request.url().split('?').next(), status, "handled request");
+            if let Err(e) = request.respond(json_response(status, body)) {
+                warn!(error = %e, "couldn't send response");
+            }
+        }
+        Ok(())
+    }
+
+    fn authorized(&self, request: &Request) -> bool {
+        request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Authorization"))
+            .and_then(|h| h.value.as_str().strip_prefix("Bearer "))
+            .is_some_and(|token| same_token(token.trim(), &self.token))
+    }
+
+    /// The response body for a request, or `None` if nothing is at its path
+    fn handle(&self, request: &mut Request) -> Result<Option<Vec<u8>>, AppError> {
+        let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+        let params: HashMap<String, String> = form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+        let body = match (request.method(), path) {
+            (Method::Get, "/messages") => find_messages(&self.defaults, &params)?,
+            (Method::Get, "/chats") => {
+                let chats = chats::list_chats(&self.defaults.db_path, self.defaults.timezone)?;
+                serde_json::to_vec(&chats)?
+            }
+            (Method::Post, "/send") => {
+                let body = read_body(request)?;
+                let send_request: SendRequest = serde_json::from_str(&body)
+                    .map_err(|e| AppError::Args(format!("Invalid send request: {}", e)))?;
+                let recipients = match send_request.to {
+                    Recipients::One(recipient) => vec![recipient],
+                    Recipients::Many(recipients) => recipients,
+                };
+                let attachments = allowed_attachments(&send_request.attachments, self.attachments_from.as_deref())?;
+                send_all(recipients, &send_request.message, &attachments)?
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(body))
+    }
+}
+
+/// The messages for `GET /messages?start=7d&end=today&contact=+15551234567&search=dinner&limit=100`,
+/// as a JSON array
+pub(crate) fn find_messages(defaults: &ExportOptions, params: &HashMap<String, String>) -> Result<Vec<u8>, AppError> {
+    let now = Utc::now();
+    let zone = defaults.timezone;
+    let date = |name: &str| params.get(name).map(|d| dates::parse_date(d, zone, now)).transpose();
+    let handles = |name: &str| params.get(name).map(|h| vec![h.clone()]).unwrap_or_default();
+    let limit = match params.get("limit") {
+        Some(limit) => limit.parse().map_err(|_| AppError::Args(format!("Invalid limit: {}", limit)))?,
+        None => DEFAULT_LIMIT,
+    };
+
+    let options = ExportOptions {
+        start_date: Some(date("start")?.unwrap_or_else(|| now - Duration::days(7))),
+        end_date: Some(date("end")?.unwrap_or(now)),
+        with: handles("contact"),
+        from: handles("from"),
+        to: handles("to"),
+        search: params.get("search").cloned(),
+        ..defaults.clone()
+    };
+    let records = MessageExporter::new(options)?.take(limit).collect::<Result<Vec<_>, _>>()?;
+    let mut body = Vec::new();
+    output::write_json(&mut body, &records)?;
+    Ok(body)
+}
+
+/// The real paths of the attachments a request names, if every one is a
+/// file inside `dir`, the server's `--attachments-from`. Callers could
+/// otherwise send any file the user can read, like their SSH keys. With no
+/// `dir`, requests can't attach anything.
+pub(crate) fn allowed_attachments(paths: &[PathBuf], dir: Option<&Path>) -> Result<Vec<PathBuf>, AppError> {
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    }
+    let Some(dir) = dir else {
+        return Err(AppError::Args("This server doesn't send attachments; start it with --attachments-from".to_string()));
+    };
+    paths
+        .iter()
+        .map(|path| {
+            // Resolved, so neither `..` nor a symlink can lead out of `dir`
+            let real = path
+                .canonicalize()
+                .map_err(|_| AppError::Args(format!("No attachment at {}", path.display())))?;
+            if !real.starts_with(dir) {
+                return Err(AppError::Args(format!("{} isn't in the attachments directory", path.display())));
+            }
+            Ok(real)
+        })
+        .collect()
+}
+
+/// Send `message` and `attachments` to each recipient not on the opt-out
+/// list, returning their results as a JSON array
+pub(crate) fn send_all(recipients: Vec<String>, message: &str, attachments: &[PathBuf]) -> Result<Vec<u8>, AppError> {
+    if recipients.is_empty() || (message.is_empty() && attachments.is_empty()) {
+        return Err(AppError::Args("A send needs \"to\" and a \"message\" or \"attachments\"".to_string()));
+    }
+    for path in attachments {
+        send::validate_attachment(path).map_err(|e| AppError::Args(e.to_string()))?;
+    }
+
+    let optouts = SuppressionList::open_default()?;
+    let mut results = Vec::new();
+    for recipient in recipients {
+        if optouts.contains(&recipient)? {
+            results.push(optout::suppressed_result(&recipient));
+            continue;
+        }
+        let message = OutgoingMessage {
+            recipient,
+            text: message.to_string(),
+            attachments: attachments.to_vec(),
+            account: None,
+        };
+        results.push(send::send_one(&message));
+    }
+    Ok(serde_json::to_vec(&results)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tiny_http::TestRequest;
+
+    const TOKEN: &str = "0123456789abcdef0123";
+
+    fn server(attachments_from: Option<&Path>) -> ApiServer {
+        ApiServer::new(ExportOptions::default(), TOKEN.to_string(), attachments_from).unwrap()
+    }
+
+    fn bearer(token: &str) -> Header {
+        Header::from_bytes("Authorization", format!("Bearer {}", token)).unwrap()
+    }
+
+    #[test]
+    fn tokens_must_match_exactly() {
+        assert!(same_token(TOKEN, TOKEN));
+        assert!(!same_token(TOKEN, "0123456789abcdef0124"));
+        assert!(!same_token(TOKEN, "0123456789abcdef012"));
+        assert!(!same_token(TOKEN, ""));
+    }
+
+    #[test]
+    fn requests_need_the_bearer_token() {
+        let server = server(None);
+        assert!(server.authorized(&TestRequest::new().with_header(bearer(TOKEN)).into()));
+        assert!(!server.authorized(&TestRequest::new().with_header(bearer("0123456789abcdef0124")).into()));
+        assert!(!server.authorized(&TestRequest::new().with_header(bearer("")).into()));
+        assert!(!server.authorized(&TestRequest::new().with_path(&format!("/chats?token={}", TOKEN)).into()));
+        assert!(!server.authorized(&TestRequest::new().into()));
+    }
+
+    #[test]
+    fn bodies_over_the_limit_are_refused() {
+        let mut request: Request = TestRequest::new().with_method(Method::Post).with_body("{}").into();
+        assert_eq!(read_body(&mut request).unwrap(), "{}");
+        let length = Header::from_bytes("Content-Length", (MAX_BODY + 1).to_string()).unwrap();
+        let mut request: Request = TestRequest::new().with_method(Method::Post).with_header(length).into();
+        assert!(matches!(read_body(&mut request), Err(AppError::BodyTooLarge(MAX_BODY))));
+    }
+
+    #[test]
+    fn attachments_must_be_in_the_allowed_directory() {
+        let dir = std::env::temp_dir().join(format!("imessagedump-server-{}", std::process::id()));
+        let allowed = dir.join("allowed");
+        fs::create_dir_all(&allowed).unwrap();
+        fs::write(allowed.join("photo.jpg"), "jpeg").unwrap();
+        fs::write(dir.join("secret.txt"), "secret").unwrap();
+        let allowed = allowed.canonicalize().unwrap();
+
+        assert!(allowed_attachments(&[], None).unwrap().is_empty());
+        assert!(allowed_attachments(&[allowed.join("photo.jpg")], None).is_err());
+        assert_eq!(allowed_attachments(&[allowed.join("photo.jpg")], Some(&allowed)).unwrap(), [allowed.join("photo.jpg")]);
+        assert!(allowed_attachments(&[allowed.join("../secret.txt")], Some(&allowed)).is_err());
+        assert!(allowed_attachments(&[dir.join("secret.txt")], Some(&allowed)).is_err());
+        assert!(allowed_attachments(&[allowed.join("missing.jpg")], Some(&allowed)).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sends_with_attachments_are_refused_without_a_directory() {
+        let mut request: Request = TestRequest::new()
+            .with_method(Method::Post)
+            .with_path("/send")
+            .with_header(bearer(TOKEN))
+            .with_body(r#"{"to":["+15551234567"],"message":"hi","attachments":["/etc/passwd"]}"#)
+            .into();
+        let Err(AppError::Args(e)) = server(None).handle(&mut request) else {
+            panic!("the send wasn't refused");
+        };
+        assert!(e.contains("--attachments-from"), "{e}");
+    }
+}
@@ -0,0 +1,23 @@
+use clap::ValueEnum;
+
+/// The network a message went over, as recorded in chat.db's `service` column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Service {
+    #[value(name = "imessage")]
+    IMessage,
+    /// Plain text messages relayed through the user's iPhone
+    Sms,
+    /// RCS chats with Android phones, relayed through the user's iPhone
+    Rcs,
+}
+
+impl Service {
+    /// The value chat.db stores for this service
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Service::IMessage => "iMessage",
+            Service::Sms => "SMS",
+            Service::Rcs => "RCS",
+        }
+    }
+}
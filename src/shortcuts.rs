@@ -0,0 +1,350 @@
+use imessage_database::util::dirs::home;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use tiny_http::{Header, Request, Response, Server};
+use tracing::{info, warn};
+
+use crate::chats;
+use crate::error::AppError;
+use crate::export::ExportOptions;
+use crate::server::{
+    allowed_attachments, error_body, find_messages, json_response, read_body, same_token, send_all, MIN_TOKEN_LEN,
+};
+
+/// Path prefix of actions that answer by opening `x-success` or `x-error`
+const CALLBACK_PREFIX: &str = "/x-callback-url/";
+
+/// A localhost endpoint for macOS Shortcuts. Actions take their parameters
+/// from the query string, a form or a JSON body, so "Get Contents of URL"
+/// can call them and get JSON back; under `/x-callback-url/` they redirect
+/// to the caller's `x-success` URL with the JSON as `result`, or to
+/// `x-error` with an `errorMessage`.
+///
+/// Web pages can reach localhost too, so every request needs the token,
+/// as `token=` or `Authorization: Bearer <token>`.
+pub struct ShortcutServer {
+    defaults: ExportOptions,
+    token: String,
+    /// The only directory `send` takes attachments from, resolved
+    attachments_from: Option<PathBuf>,
+}
+
+/// Parameters given once or more, like `to=+15551234567&to=+15559876543`
+type Params = HashMap<String, Vec<String>>;
+
+/// Read the token at `path`, creating a random one the first time
+pub fn load_token(path: &Path) -> Result<String, AppError> {
+    if path.exists() {
+        let token = fs::read_to_string(path)?.trim().to_string();
+        if token.len() < MIN_TOKEN_LEN {
+            return Err(AppError::Config(format!(
+                "{} holds a token shorter than {} characters; delete it to have a new one made",
+                path.display(),
+                MIN_TOKEN_LEN
+            )));
+        }
+        return Ok(token);
+    }
+    let mut bytes = [0u8; 24];
+    fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // Anyone with the token can send messages as you
+    let mut file = fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?;
+    file.write_all(token.as_bytes())?;
+    Ok(token)
+}
+
+/// `~/.imessage-blaster/shortcuts.token`
+pub fn default_token_path() -> PathBuf {
+    PathBuf::from(home()).join(".imessage-blaster").join("shortcuts.token")
+}
+
+/// Add the query string's parameters, and a form or JSON object body's, to `params`
+fn parse_params(request: &mut Request) -> Result<Params, AppError> {
+    let mut params = Params::new();
+    let query = request.url().split_once('?').map_or("", |(_, query)| query).to_string();
+    let mut add = |key: String, value: String| params.entry(key).or_default().push(value);
+    for (key, value) in form_urlencoded::parse(query.as_bytes()).into_owned() {
+        add(key, value);
+    }
+    let body = read_body(request)?;
+    let body = body.trim();
+    if body.starts_with('{') {
+        let Value::Object(fields) = serde_json::from_str(body)
+            .map_err(|e| AppError::Args(format!("Invalid JSON body: {}", e)))?
+        else {
+            unreachable!("a body starting with {{ is an object")
+        };
+        for (key, value) in fields {
+            // Shortcuts sends lists for multiple recipients or files
+            let values = match value {
+                Value::Array(items) => items,
+                value => vec![value],
+            };
+            for value in values {
+                match value {
+                    Value::String(s) => add(key.clone(), s),
+                    Value::Null => {}
+                    value => add(key.clone(), value.to_string()),
+                }
+            }
+        }
+    } else if !body.is_empty() {
+        for (key, value) in form_urlencoded::parse(body.as_bytes()).into_owned() {
+            add(key, value);
+        }
+    }
+    Ok(params)
+}
+
+/// The last value of a parameter given more than once
+fn single(params: &Params) -> HashMap<String, String> {
+    params.iter().filter_map(|(key, values)| Some((key.clone(), values.last()?.clone()))).collect()
+}
+
+/// Every value of a parameter, with commas separating more, as in `to=+1555...,+1555...`
+fn list(params: &Params, key: &str) -> Vec<String> {
+    params
+        .get(key)
+        .into_iter()
+        .flatten()
+        .flat_map(|value| value.split(','))
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+/// `url` with `key=value` added to its query string
+fn with_param(url: &str, key: &str, value: &str) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let value: String = form_urlencoded::byte_serialize(value.as_bytes()).collect();
+    format!("{}{}{}={}", url, separator, key, value)
+}
+
+/// A redirect to a callback URL, or a 400 for one that can't go in a
+/// `Location` header as it is, like one with spaces, line breaks or
+/// non-ASCII characters
+fn redirect(location: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    let invalid = || json_response(400, error_body("x-success and x-error must be URLs with special characters percent-encoded"));
+    if !location.bytes().all(|b| b.is_ascii_graphic()) {
+        return invalid();
+    }
+    match Header::from_bytes("Location", location) {
+        Ok(header) => Response::from_data(Vec::new()).with_status_code(302).with_header(header),
+        Err(()) => invalid(),
+    }
+}
+
+impl ShortcutServer {
+    /// A server answering requests with `token`, sending attachments only
+    /// from inside `attachments_from`, if given
+    pub fn new(defaults: ExportOptions, token: String, attachments_from: Option<&Path>) -> Result<Self, AppError> {
+        let attachments_from = attachments_from.map(Path::canonicalize).transpose()?;
+        Ok(ShortcutServer { defaults, token, attachments_from })
+    }
+
+    /// Serve requests on `127.0.0.1:<port>` until the process is stopped
+    pub fn run(&self, port: u16) -> Result<(), AppError> {
+        let addr = format!("127.0.0.1:{}", port);
+        let server = Server::http(&addr).map_err(|e| AppError::Http(format!("Couldn't listen on {}: {}", addr, e)))?;
+        info!(%addr, "listening for Shortcuts");
+        for mut request in server.incoming_requests() {
+            let url = request.url().to_string();
+            let response = self.respond(&mut request);
+            info!(method = %request.method(), url = url.split('?').next(), status = response.status_code().0, "handled request");
+            if let Err(e) = request.respond(response) {
+                warn!(error = %e, "couldn't send response");
+            }
+        }
+        Ok(())
+    }
+
+    fn respond(&self, request: &mut Request) -> Response<std::io::Cursor<Vec<u8>>> {
+        let params = match parse_params(request) {
+            Ok(params) => params,
+            Err(e @ AppError::BodyTooLarge(_)) => return json_response(413, error_body(&e.to_string())),
+            Err(e) => return json_response(400, error_body(&e.to_string())),
+        };
+        let path = request.url().split('?').next().unwrap_or_default().to_string();
+        let given = single(&params);
+        let bearer = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Authorization"))
+            .and_then(|h| h.value.as_str().strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+        let authorized = bearer.or_else(|| given.get("token").cloned()).is_some_and(|t| same_token(&t, &self.token));
+
+        // Callbacks are only followed for the token's holder, so the server
+        // can't be used to redirect anyone anywhere
+        let (action, callback) = match path.strip_prefix(CALLBACK_PREFIX) {
+            Some(action) => (action, authorized),
+            None => (path.trim_start_matches('/'), false),
+        };
+        let result = if authorized {
+            self.action(action, &params, &given)
+        } else {
+            Err(AppError::Args("missing or wrong token".to_string()))
+        };
+
+        match (result, callback) {
+            (Ok(Some(body)), true) => match given.get("x-success") {
+                Some(success) => redirect(&with_param(success, "result", &String::from_utf8_lossy(&body))),
+                None => json_response(200, body),
+            },
+            (Ok(Some(body)), false) => json_response(200, body),
+            (Ok(None), _) => json_response(404, error_body("no such action")),
+            (Err(e), true) if given.contains_key("x-error") => {
+                let location = with_param(&given["x-error"], "errorMessage", &e.to_string());
+                redirect(&with_param(&location, "errorCode", e.kind()))
+            }
+            (Err(e), _) => {
+                let status = match &e {
+                    AppError::Args(_) if !authorized => 401,
+                    AppError::Args(_) => 400,
+                    _ => {
+                        warn!(error = %e, action, "action failed");
+                        500
+                    }
+                };
+                json_response(status, error_body(&e.to_string()))
+            }
+        }
+    }
+
+    /// Run an action: `export` (see [`find_messages`] for its parameters),
+    /// `chats`, or `send` with `to`, `message` and `attachment`s from the
+    /// attachments directory. `None` for actions there aren't.
+    fn action(&self, action: &str, params: &Params, given: &HashMap<String, String>) -> Result<Option<Vec<u8>>, AppError> {
+        let body = match action {
+            "export" => find_messages(&self.defaults, given)?,
+            "chats" => serde_json::to_vec(&chats::list_chats(&self.defaults.db_path, self.defaults.timezone)?)?,
+            "send" => {
+                let attachments: Vec<PathBuf> = params.get("attachment").into_iter().flatten().map(PathBuf::from).collect();
+                let attachments = allowed_attachments(&attachments, self.attachments_from.as_deref())?;
+                let message = given.get("message").map(String::as_str).unwrap_or_default();
+                send_all(list(params, "to"), message, &attachments)?
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiny_http::{Method, TestRequest};
+
+    const TOKEN: &str = "0123456789abcdef0123";
+
+    fn server() -> ShortcutServer {
+        ShortcutServer::new(ExportOptions::default(), TOKEN.to_string(), None).unwrap()
+    }
+
+    fn respond(request: TestRequest) -> Response<std::io::Cursor<Vec<u8>>> {
+        server().respond(&mut request.into())
+    }
+
+    fn post(path: &str, body: &'static str) -> TestRequest {
+        TestRequest::new().with_method(Method::Post).with_path(path).with_body(body)
+    }
+
+    fn location(response: &Response<std::io::Cursor<Vec<u8>>>) -> Option<String> {
+        response.headers().iter().find(|h| h.field.equiv("Location")).map(|h| h.value.to_string())
+    }
+
+    /// A fresh path under the system temp directory for a test's files
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("imessagedump-shortcuts-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn requests_without_the_token_are_refused() {
+        let response = respond(TestRequest::new().with_path("/chats"));
+        assert_eq!(response.status_code().0, 401);
+        let response = respond(TestRequest::new().with_path("/chats?token=0123456789abcdef0124"));
+        assert_eq!(response.status_code().0, 401);
+        let response = respond(TestRequest::new().with_path("/chats?token="));
+        assert_eq!(response.status_code().0, 401);
+    }
+
+    #[test]
+    fn callbacks_are_ignored_without_the_token() {
+        let response = respond(post("/x-callback-url/send", r#"{"x-error":"https://example.com/"}"#));
+        assert_eq!(response.status_code().0, 401);
+        assert_eq!(location(&response), None);
+        // Used to panic building the header
+        let response = respond(post("/x-callback-url/send", r#"{"x-error":"é"}"#));
+        assert_eq!(response.status_code().0, 401);
+    }
+
+    #[test]
+    fn errors_redirect_to_x_error() {
+        let request = post(
+            "/x-callback-url/send",
+            r#"{"token":"0123456789abcdef0123","x-error":"shortcuts://x-callback-url/error?from=blaster"}"#,
+        );
+        let response = respond(request);
+        assert_eq!(response.status_code().0, 302);
+        let location = location(&response).unwrap();
+        assert!(location.starts_with("shortcuts://x-callback-url/error?from=blaster&errorMessage="), "{location}");
+        assert!(location.ends_with("&errorCode=args"), "{location}");
+    }
+
+    #[test]
+    fn the_bearer_header_is_a_token_too() {
+        let header = Header::from_bytes("Authorization", format!("Bearer {}", TOKEN)).unwrap();
+        let request = TestRequest::new().with_path("/x-callback-url/send?x-error=shortcuts://error").with_header(header);
+        assert_eq!(respond(request).status_code().0, 302);
+    }
+
+    #[test]
+    fn callback_urls_that_cant_be_a_header_are_refused() {
+        for body in [
+            r#"{"token":"0123456789abcdef0123","x-error":"shortcuts://é"}"#,
+            r#"{"token":"0123456789abcdef0123","x-error":"shortcuts://a\r\nSet-Cookie: a=b"}"#,
+            r#"{"token":"0123456789abcdef0123","x-error":"shortcuts://a b"}"#,
+        ] {
+            let response = respond(post("/x-callback-url/send", body));
+            assert_eq!(response.status_code().0, 400, "{body}");
+            assert_eq!(location(&response), None);
+        }
+    }
+
+    #[test]
+    fn unknown_actions_are_not_found() {
+        let response = respond(TestRequest::new().with_path("/nothing?token=0123456789abcdef0123"));
+        assert_eq!(response.status_code().0, 404);
+    }
+
+    #[test]
+    fn load_token_makes_one_and_keeps_it() {
+        let path = scratch("made").join("shortcuts.token");
+        let token = load_token(&path).unwrap();
+        assert_eq!(token.len(), 48);
+        assert!(token.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_eq!(load_token(&path).unwrap(), token);
+    }
+
+    #[test]
+    fn load_token_refuses_a_short_one() {
+        let path = scratch("short").join("shortcuts.token");
+        for contents in ["", " \n", "short"] {
+            fs::write(&path, contents).unwrap();
+            assert!(matches!(load_token(&path), Err(AppError::Config(_))), "{contents:?}");
+        }
+        fs::write(&path, "  0123456789abcdef0123\n").unwrap();
+        assert_eq!(load_token(&path).unwrap(), TOKEN);
+    }
+}
@@ -0,0 +1,47 @@
+use clap::ValueEnum;
+
+use crate::error::AppError;
+use crate::export::MessageRecord;
+
+pub mod elasticsearch;
+pub mod postgres;
+
+/// Records sent to a sink in one request
+pub const BATCH_SIZE: usize = 500;
+
+/// A store `--sink` sends messages to instead of writing an output file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SinkKind {
+    /// Bulk-index into an Elasticsearch or OpenSearch index, given with --url and --index
+    Elasticsearch,
+    /// Upsert into PostgreSQL tables of messages, handles, chats and
+    /// attachments, given with --dsn
+    Postgres,
+}
+
+/// Somewhere records are stored by id, so sending one again replaces it
+pub trait Sink {
+    fn write_batch(&mut self, records: &[MessageRecord]) -> Result<(), AppError>;
+}
+
+/// Send every record to `sink`, [`BATCH_SIZE`] at a time, returning how many were sent
+pub fn write_all<I>(sink: &mut dyn Sink, records: I) -> Result<u64, AppError>
+where
+    I: IntoIterator<Item = Result<MessageRecord, AppError>>,
+{
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut sent = 0;
+    for record in records {
+        batch.push(record?);
+        if batch.len() == BATCH_SIZE {
+            sink.write_batch(&batch)?;
+            sent += batch.len() as u64;
+            batch.clear();
+        }
+    }
+    if !batch.is_empty() {
+        sink.write_batch(&batch)?;
+        sent += batch.len() as u64;
+    }
+    Ok(sent)
+}
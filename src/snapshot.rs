@@ -0,0 +1,85 @@
+use rusqlite::{Connection, OpenFlags};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+use crate::error::AppError;
+use crate::export::wal_file;
+
+/// A private copy of chat.db to export from, deleted when dropped
+///
+/// The copy is made with `VACUUM INTO`, which reads the live database in one
+/// transaction, write-ahead log included, so the copy is consistent even while
+/// Messages is writing. It's then opened `immutable`, taking no locks at all.
+#[derive(Debug)]
+pub struct Snapshot {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl Snapshot {
+    fn create_dir() -> Result<Self, AppError> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+        let dir = std::env::temp_dir().join(format!("imessage-blaster-snapshot-{}-{}", std::process::id(), nanos));
+        fs::create_dir_all(&dir)?;
+        Ok(Snapshot { path: dir.join("chat.db"), dir })
+    }
+
+    /// Copy the database `live` is connected to into a new temporary directory
+    pub fn take(live: &Connection) -> Result<Self, AppError> {
+        let started = Instant::now();
+        let snapshot = Self::create_dir()?;
+        live.execute("VACUUM INTO ?1", [snapshot.path.to_string_lossy()])?;
+        info!(path = %snapshot.path.display(), elapsed = ?started.elapsed(), "took database snapshot");
+        Ok(snapshot)
+    }
+
+    /// Copy `db_file` and its write-ahead log into a new temporary directory
+    /// and checkpoint the log into the copy, for databases whose log can't be
+    /// read where they are. This copy is the only connection to it, so a
+    /// passive checkpoint moves every frame.
+    pub fn checkpointed(db_file: &Path) -> Result<Self, AppError> {
+        let started = Instant::now();
+        let snapshot = Self::create_dir()?;
+        // Copies keep the originals' permissions, so may need making writable
+        for (from, to) in [(db_file.to_path_buf(), snapshot.path.clone()), (wal_file(db_file), wal_file(&snapshot.path))] {
+            fs::copy(from, &to)?;
+            fs::set_permissions(&to, fs::Permissions::from_mode(0o600))?;
+        }
+        let db = Connection::open(&snapshot.path)?;
+        let (busy, frames, checkpointed): (i64, i64, i64) =
+            db.query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        if busy != 0 || checkpointed < frames {
+            return Err(AppError::WalUnreadable(db_file.to_path_buf()));
+        }
+        info!(path = %snapshot.path.display(), frames, elapsed = ?started.elapsed(), "checkpointed a copy of the database");
+        Ok(snapshot)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// A read-only connection to the snapshot. Nothing else writes to it,
+    /// so it's opened immutable.
+    pub fn open(&self) -> Result<Connection, AppError> {
+        let mut uri = String::from("file:");
+        for c in self.path.to_string_lossy().chars() {
+            match c {
+                '?' | '#' | '%' => uri.push_str(&format!("%{:02X}", c as u32)),
+                c => uri.push(c),
+            }
+        }
+        uri.push_str("?immutable=1");
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        Ok(Connection::open_with_flags(uri, flags)?)
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
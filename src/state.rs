@@ -0,0 +1,37 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::error::AppError;
+
+/// Where the previous incremental export stopped
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportState {
+    /// Highest message ROWID read so far; the next run starts after it
+    pub last_rowid: i32,
+    /// Date of that message
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub last_date: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl ExportState {
+    /// Read the state file, or `None` if this is the first run
+    pub fn load(path: &Path) -> Result<Option<Self>, AppError> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(path)?;
+        Ok(Some(serde_json::from_str(&contents)?))
+    }
+
+    /// Write the state file, replacing it atomically so a crash can't leave it half-written
+    pub fn save(&self, path: &Path) -> Result<(), AppError> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
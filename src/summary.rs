@@ -0,0 +1,50 @@
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::error::AppError;
+use crate::export::MessageRecord;
+
+/// What an export would contain, without its messages
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ExportSummary {
+    pub messages: usize,
+    pub sent: usize,
+    pub received: usize,
+    pub first_date: Option<DateTime<FixedOffset>>,
+    pub last_date: Option<DateTime<FixedOffset>>,
+    /// How many distinct handles the user exchanged messages with
+    pub contacts: usize,
+    pub handles: BTreeSet<String>,
+    /// Messages by the user's own number or email they went through; more
+    /// than one with Messages in iCloud
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub accounts: BTreeMap<String, usize>,
+}
+
+/// Count `records` and note their date range and the handles in them
+pub fn summarize<I>(records: I) -> Result<ExportSummary, AppError>
+where
+    I: IntoIterator<Item = Result<MessageRecord, AppError>>,
+{
+    let mut summary = ExportSummary::default();
+    for record in records {
+        let record = record?;
+        summary.messages += 1;
+        if record.from_me {
+            summary.sent += 1;
+        } else {
+            summary.received += 1;
+        }
+        summary.first_date = Some(summary.first_date.map_or(record.date, |d| d.min(record.date)));
+        summary.last_date = Some(summary.last_date.map_or(record.date, |d| d.max(record.date)));
+        if let Some(account) = &record.account {
+            *summary.accounts.entry(account.clone()).or_default() += 1;
+        }
+        // The user's own handle is `from` on sent messages and `to` on received ones
+        let other = if record.from_me { record.to } else { record.from };
+        summary.handles.extend(other.into_iter().chain(record.participants));
+    }
+    summary.contacts = summary.handles.len();
+    Ok(summary)
+}
@@ -0,0 +1,80 @@
+use minijinja::{Environment, UndefinedBehavior};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::error::AppError;
+
+/// One row of a recipient CSV
+#[derive(Debug, Clone)]
+pub struct Recipient {
+    /// Phone number or email to send to
+    pub handle: String,
+    /// Every column of the row, available to the template by header name
+    pub fields: BTreeMap<String, String>,
+}
+
+impl Recipient {
+    /// A recipient with no fields other than `handle`
+    pub fn from_handle(handle: &str) -> Self {
+        let mut fields = BTreeMap::new();
+        fields.insert("handle".to_string(), handle.to_string());
+        Recipient {
+            handle: handle.to_string(),
+            fields,
+        }
+    }
+}
+
+/// Read recipients from a CSV with a header row, taking the handle from `handle_column`
+pub fn load_recipients(path: &Path, handle_column: &str) -> Result<Vec<Recipient>, AppError> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let headers = reader.headers()?.clone();
+    if !headers.iter().any(|h| h == handle_column) {
+        return Err(AppError::Args(format!(
+            "{} has no `{}` column",
+            path.display(),
+            handle_column
+        )));
+    }
+
+    let mut recipients = Vec::new();
+    for row in reader.records() {
+        let row = row?;
+        let mut fields: BTreeMap<String, String> = headers
+            .iter()
+            .zip(row.iter())
+            .map(|(h, v)| (h.to_string(), v.trim().to_string()))
+            .collect();
+        let handle = fields.get(handle_column).cloned().unwrap_or_default();
+        if handle.is_empty() {
+            continue;
+        }
+        fields.entry("handle".to_string()).or_insert_with(|| handle.clone());
+        recipients.push(Recipient { handle, fields });
+    }
+    Ok(recipients)
+}
+
+/// Renders a message template (minijinja syntax, e.g. `Hi {{ first_name }}!`)
+/// for each recipient
+pub struct MessageTemplate {
+    env: Environment<'static>,
+}
+
+impl MessageTemplate {
+    pub fn new(source: &str) -> Result<Self, AppError> {
+        let mut env = Environment::new();
+        // A typo'd placeholder should stop a campaign, not send "Hi !"
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        env.add_template_owned("message", source.to_string())
+            .map_err(|e| AppError::Template(e.to_string()))?;
+        Ok(MessageTemplate { env })
+    }
+
+    pub fn render(&self, recipient: &Recipient) -> Result<String, AppError> {
+        self.env
+            .get_template("message")
+            .and_then(|t| t.render(&recipient.fields))
+            .map_err(|e| AppError::Template(format!("{} (recipient {})", e, recipient.handle)))
+    }
+}
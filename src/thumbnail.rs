@@ -0,0 +1,135 @@
+use clap::ValueEnum;
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageDecoder, ImageReader, Rgb, RgbImage, RgbaImage};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::error::AppError;
+use crate::media::run_tool;
+
+/// Name of the directory thumbnails are written to, next to the transcripts
+pub const THUMBNAIL_DIR: &str = "thumbnails";
+
+/// What thumbnails are encoded as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ThumbnailFormat {
+    #[default]
+    Jpeg,
+    /// Smaller than JPEG at the same quality; made with ffmpeg
+    Webp,
+}
+
+impl ThumbnailFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ThumbnailFormat::Jpeg => "jpg",
+            ThumbnailFormat::Webp => "webp",
+        }
+    }
+}
+
+/// Decode an image file, turned upright as its EXIF orientation says
+pub fn load_upright(path: &Path) -> Result<DynamicImage, image::ImageError> {
+    let mut decoder = ImageReader::open(path)?.with_guessed_format()?.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+/// Blend an image onto white, for formats without transparency
+pub fn flatten(rgba: &RgbaImage) -> RgbImage {
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |c: u8| ((u16::from(c) * u16::from(a) + 255 * (255 - u16::from(a))) / 255) as u8;
+        Rgb([blend(r), blend(g), blend(b)])
+    })
+}
+
+/// Writes small previews of image and video attachments for transcripts to
+/// show in place of the originals
+#[derive(Debug, Clone)]
+pub struct Thumbnailer {
+    dir: PathBuf,
+    /// Longest side, in pixels
+    size: u32,
+    format: ThumbnailFormat,
+}
+
+impl Thumbnailer {
+    /// Thumbnails go in the `thumbnails` directory under `output_dir`
+    pub fn new(output_dir: &Path, size: u32, format: ThumbnailFormat) -> Result<Self, AppError> {
+        if size == 0 {
+            return Err(AppError::Args("--thumbnails must be a size in pixels above 0".to_string()));
+        }
+        let dir = output_dir.join(THUMBNAIL_DIR);
+        fs::create_dir_all(&dir)?;
+        Ok(Thumbnailer { dir, size, format })
+    }
+
+    /// A thumbnail of `source`, as a path relative to the output directory.
+    /// `None` for attachments that aren't images or videos. Thumbnails made
+    /// by an earlier export are reused.
+    pub fn thumbnail(&self, source: &Path, mime_type: Option<&str>) -> Result<Option<String>, AppError> {
+        let mime_type = mime_type.unwrap_or_default();
+        let video = mime_type.starts_with("video/");
+        if !video && !mime_type.starts_with("image/") {
+            return Ok(None);
+        }
+        let mut hasher = Sha256::new();
+        hasher.update(source.to_string_lossy().as_bytes());
+        hasher.update(self.size.to_le_bytes());
+        let hash: String = hasher.finalize()[..6].iter().map(|b| format!("{:02x}", b)).collect();
+        let stem = source.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        let name = format!("{}-{}.{}", hash, stem, self.format.extension());
+        let dest = self.dir.join(&name);
+
+        if !dest.exists() {
+            // The image crate can't read HEIC, which is what iPhones take photos in
+            let resized = !video && self.format == ThumbnailFormat::Jpeg && self.resize(source, &dest).is_ok();
+            if !resized {
+                self.ffmpeg(source, &dest, video)?;
+            }
+        }
+        Ok(Some(format!("{}/{}", THUMBNAIL_DIR, name)))
+    }
+
+    fn resize(&self, source: &Path, dest: &Path) -> Result<(), image::ImageError> {
+        let image = load_upright(source)?;
+        let image = if image.width().max(image.height()) > self.size {
+            image.thumbnail(self.size, self.size)
+        } else {
+            image
+        };
+        let out = BufWriter::new(File::create(dest)?);
+        JpegEncoder::new_with_quality(out, 80).encode_image(&flatten(&image.to_rgba8()))
+    }
+
+    /// Scale `source` down with ffmpeg. For a video, that's a frame a second
+    /// in, or its first frame if it's shorter.
+    fn ffmpeg(&self, source: &Path, dest: &Path, video: bool) -> Result<(), AppError> {
+        let scale = format!(
+            "scale='min({size},iw)':'min({size},ih)':force_original_aspect_ratio=decrease",
+            size = self.size
+        );
+        let starts: &[&str] = if video { &["1", "0"] } else { &["0"] };
+        let mut result = Ok(());
+        for start in starts {
+            result = run_tool(
+                Command::new("ffmpeg")
+                    .args(["-y", "-loglevel", "error", "-ss", start, "-i"])
+                    .arg(source)
+                    .args(["-frames:v", "1", "-vf", &scale])
+                    .arg(dest),
+            );
+            if result.is_ok() && dest.exists() {
+                return Ok(());
+            }
+        }
+        let _ = fs::remove_file(dest);
+        result.and(Err(AppError::Media("ffmpeg didn't write a thumbnail".to_string())))
+    }
+}
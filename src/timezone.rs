@@ -0,0 +1,64 @@
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, SubsecRound, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::str::FromStr;
+
+use crate::error::AppError;
+
+/// The timezone dates are parsed and written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Zone {
+    /// Whatever the system is set to
+    #[default]
+    Local,
+    /// An IANA timezone such as `America/Chicago` or `UTC`
+    Named(Tz),
+}
+
+impl FromStr for Zone {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("local") {
+            return Ok(Zone::Local);
+        }
+        s.parse::<Tz>()
+            .map(Zone::Named)
+            .map_err(|_| AppError::Args(format!("Unknown timezone: {}. Expected an IANA name like America/New_York", s)))
+    }
+}
+
+/// Resolve a wall-clock time, taking the earlier instant when it's ambiguous
+/// and skipping forward over a DST gap
+fn resolve<T: TimeZone>(tz: &T, naive: NaiveDateTime) -> DateTime<Utc> {
+    let mut naive = naive;
+    loop {
+        if let Some(date) = tz.from_local_datetime(&naive).earliest() {
+            return date.with_timezone(&Utc);
+        }
+        naive += chrono::Duration::minutes(30);
+    }
+}
+
+impl Zone {
+    /// Express an instant in this timezone, to the second
+    pub fn localize(&self, date: DateTime<Utc>) -> DateTime<FixedOffset> {
+        let date = date.trunc_subsecs(0);
+        match self {
+            Zone::Local => date.with_timezone(&Local).fixed_offset(),
+            Zone::Named(tz) => date.with_timezone(tz).fixed_offset(),
+        }
+    }
+
+    /// The instant a wall-clock time happens in this timezone
+    pub fn from_local(&self, naive: NaiveDateTime) -> DateTime<Utc> {
+        match self {
+            Zone::Local => resolve(&Local, naive),
+            Zone::Named(tz) => resolve(tz, naive),
+        }
+    }
+
+    /// The instant `date` begins in this timezone
+    pub fn start_of_day(&self, date: NaiveDate) -> DateTime<Utc> {
+        self.from_local(date.and_hms_opt(0, 0, 0).unwrap())
+    }
+}
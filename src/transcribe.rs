@@ -0,0 +1,107 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::error::AppError;
+
+/// Transcribes the audio file named by the first argument with the Speech
+/// framework and prints the best transcription
+const SPEECH_SCRIPT: &str = r#"
+import Foundation
+import Speech
+
+let url = URL(fileURLWithPath: CommandLine.arguments[1])
+let done = DispatchSemaphore(value: 0)
+
+SFSpeechRecognizer.requestAuthorization { status in
+    guard status == .authorized else {
+        FileHandle.standardError.write("Speech recognition is not authorized\n".data(using: .utf8)!)
+        exit(1)
+    }
+    guard let recognizer = SFSpeechRecognizer(), recognizer.isAvailable else {
+        FileHandle.standardError.write("Speech recognition is unavailable\n".data(using: .utf8)!)
+        exit(1)
+    }
+    let request = SFSpeechURLRecognitionRequest(url: url)
+    request.shouldReportPartialResults = false
+    recognizer.recognitionTask(with: request) { result, error in
+        if let error = error {
+            FileHandle.standardError.write("\(error.localizedDescription)\n".data(using: .utf8)!)
+            exit(1)
+        }
+        if let result = result, result.isFinal {
+            print(result.bestTranscription.formattedString)
+            done.signal()
+        }
+    }
+}
+
+while done.wait(timeout: .now()) == .timedOut {
+    RunLoop.current.run(until: Date(timeIntervalSinceNow: 0.1))
+}
+"#;
+
+/// How audio messages are turned into text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transcriber {
+    /// The macOS Speech framework, via `swift`
+    Speech,
+    /// whisper.cpp's `whisper-cli` with this model file
+    Whisper { model: PathBuf },
+}
+
+/// Run `program`, optionally feeding `stdin`, and return its trimmed stdout
+fn run(program: &str, args: &[&str], stdin: Option<&str>) -> Result<String, AppError> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| AppError::Transcribe(format!("Couldn't run {}: {}", program, e)))?;
+
+    let mut input = child.stdin.take().unwrap();
+    if let Some(text) = stdin {
+        input.write_all(text.as_bytes())?;
+    }
+    drop(input);
+    let output = child.wait_with_output()?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        Err(AppError::Transcribe(if stderr.is_empty() {
+            format!("{} exited with {}", program, output.status)
+        } else {
+            stderr
+        }))
+    }
+}
+
+/// Whether an attachment's MIME type is something we can transcribe
+pub fn is_audio(mime_type: Option<&str>) -> bool {
+    mime_type.is_some_and(|m| m.starts_with("audio/"))
+}
+
+impl Transcriber {
+    /// Transcribe the audio file at `path`, returning `None` if nothing was said
+    pub fn transcribe(&self, path: &Path) -> Result<Option<String>, AppError> {
+        let path_str = path.to_string_lossy();
+        let text = match self {
+            Transcriber::Speech => run("swift", &["-", &path_str], Some(SPEECH_SCRIPT))?,
+            Transcriber::Whisper { model } => {
+                // whisper.cpp only reads 16 kHz WAV, and audio messages are CAF
+                let wav = std::env::temp_dir().join(format!("imessagedump-{}.wav", std::process::id()));
+                let wav_str = wav.to_string_lossy();
+                run("afconvert", &["-f", "WAVE", "-d", "LEI16@16000", "-c", "1", &path_str, &wav_str], None)?;
+                let model = model.to_string_lossy();
+                let text = run("whisper-cli", &["-m", &model, "-f", &wav_str, "--no-timestamps", "--no-prints"], None);
+                let _ = fs::remove_file(&wav);
+                text?
+            }
+        };
+        Ok((!text.is_empty()).then_some(text))
+    }
+}
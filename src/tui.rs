@@ -0,0 +1,431 @@
+use chrono::{Duration, Utc};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use tracing::info;
+
+use crate::chats::{self, ChatSummary};
+use crate::dates;
+use crate::error::AppError;
+use crate::export::{ExportOptions, MessageExporter, MessageRecord};
+use crate::output::{self, writer, OutputFormat};
+
+const HELP: &str = "↑↓ move  Enter open  Tab switch  / search  n next  d go to date  v select  e export  q quit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Chats,
+    Messages,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PromptKind {
+    Search,
+    Date,
+    Export,
+}
+
+impl PromptKind {
+    fn label(&self) -> &'static str {
+        match self {
+            PromptKind::Search => "/",
+            PromptKind::Date => "Go to date: ",
+            PromptKind::Export => "Export to: ",
+        }
+    }
+}
+
+/// A line of input being typed at the bottom of the screen
+struct Prompt {
+    kind: PromptKind,
+    input: String,
+    /// Where the cursor was when the prompt opened, to go back to on Esc
+    started_at: Option<usize>,
+}
+
+/// Chats on the left, the open chat's messages on the right
+struct App {
+    options: ExportOptions,
+    chats: Vec<ChatSummary>,
+    /// Indexes into `chats` of those matching `chat_filter`
+    visible: Vec<usize>,
+    chat_filter: String,
+    chat_list: ListState,
+    /// The chat `messages` are from
+    open_chat: Option<usize>,
+    messages: Vec<MessageRecord>,
+    message_list: ListState,
+    /// The other end of the selection, from `v`
+    anchor: Option<usize>,
+    search: String,
+    focus: Focus,
+    prompt: Option<Prompt>,
+    status: String,
+    quit: bool,
+}
+
+/// Run the browser until the user quits. Messages are read and exported with
+/// `options`, narrowed to the chat and selection.
+pub fn run(options: ExportOptions) -> Result<(), AppError> {
+    let chats = chats::list_chats(&options.db_path, options.timezone)?;
+    let mut app = App {
+        visible: (0..chats.len()).collect(),
+        chats,
+        options,
+        chat_filter: String::new(),
+        chat_list: ListState::default().with_selected(Some(0)),
+        open_chat: None,
+        messages: Vec::new(),
+        message_list: ListState::default(),
+        anchor: None,
+        search: String::new(),
+        focus: Focus::Chats,
+        prompt: None,
+        status: HELP.to_string(),
+        quit: false,
+    };
+
+    let mut terminal = ratatui::try_init()?;
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+impl App {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), AppError> {
+        while !self.quit {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    self.key(key)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn key(&mut self, key: KeyEvent) -> Result<(), AppError> {
+        if self.prompt.is_some() {
+            return self.prompt_key(key);
+        }
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Char('c') if ctrl => self.quit = true,
+            KeyCode::Tab | KeyCode::BackTab => self.switch_focus(),
+            KeyCode::Left | KeyCode::Char('h') => self.focus = Focus::Chats,
+            KeyCode::Right | KeyCode::Char('l') if self.open_chat.is_some() => self.focus = Focus::Messages,
+            KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+            KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+            KeyCode::PageDown => self.move_by(20),
+            KeyCode::PageUp => self.move_by(-20),
+            KeyCode::Home | KeyCode::Char('g') => self.move_by(isize::MIN / 2),
+            KeyCode::End | KeyCode::Char('G') => self.move_by(isize::MAX / 2),
+            KeyCode::Enter if self.focus == Focus::Chats => self.open_selected()?,
+            KeyCode::Char('/') => self.open_prompt(PromptKind::Search),
+            KeyCode::Char('n') if self.focus == Focus::Messages => self.find_next(1, true),
+            KeyCode::Char('N') if self.focus == Focus::Messages => self.find_next(-1, true),
+            KeyCode::Char('d') if self.focus == Focus::Messages => self.open_prompt(PromptKind::Date),
+            KeyCode::Char('v') if self.focus == Focus::Messages => {
+                self.anchor = match self.anchor {
+                    Some(_) => None,
+                    None => self.message_list.selected(),
+                };
+            }
+            KeyCode::Char('e') if self.open_chat.is_some() => self.open_prompt(PromptKind::Export),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn prompt_key(&mut self, key: KeyEvent) -> Result<(), AppError> {
+        let Some(prompt) = &mut self.prompt else {
+            return Ok(());
+        };
+        match key.code {
+            KeyCode::Esc => {
+                let prompt = self.prompt.take().expect("prompt is open");
+                // Searching moves as you type, so cancelling goes back
+                if prompt.kind == PromptKind::Search {
+                    match self.focus {
+                        Focus::Chats => {
+                            self.chat_filter.clear();
+                            self.filter_chats();
+                        }
+                        Focus::Messages => self.message_list.select(prompt.started_at),
+                    }
+                }
+                self.status = HELP.to_string();
+                return Ok(());
+            }
+            KeyCode::Enter => {
+                let prompt = self.prompt.take().expect("prompt is open");
+                self.status = HELP.to_string();
+                return self.submit(prompt);
+            }
+            KeyCode::Backspace => {
+                prompt.input.pop();
+            }
+            KeyCode::Char(c) => prompt.input.push(c),
+            _ => return Ok(()),
+        }
+        // Incremental search: the view follows every keystroke
+        if prompt.kind == PromptKind::Search {
+            let (input, started_at) = (prompt.input.clone(), prompt.started_at);
+            match self.focus {
+                Focus::Chats => {
+                    self.chat_filter = input;
+                    self.filter_chats();
+                }
+                Focus::Messages => {
+                    self.search = input;
+                    self.message_list.select(started_at);
+                    self.find_next(1, false);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn submit(&mut self, prompt: Prompt) -> Result<(), AppError> {
+        match prompt.kind {
+            PromptKind::Search => {}
+            PromptKind::Date => self.go_to_date(&prompt.input),
+            PromptKind::Export => self.export(prompt.input.trim())?,
+        }
+        Ok(())
+    }
+
+    fn open_prompt(&mut self, kind: PromptKind) {
+        let input = match kind {
+            PromptKind::Search if self.focus == Focus::Chats => self.chat_filter.clone(),
+            PromptKind::Export => "selection.json".to_string(),
+            _ => String::new(),
+        };
+        self.prompt = Some(Prompt { kind, input, started_at: self.message_list.selected() });
+    }
+
+    fn switch_focus(&mut self) {
+        self.focus = match self.focus {
+            Focus::Chats if self.open_chat.is_some() => Focus::Messages,
+            _ => Focus::Chats,
+        };
+    }
+
+    /// Move the cursor of the focused list, staying inside it
+    fn move_by(&mut self, delta: isize) {
+        let (list, len) = match self.focus {
+            Focus::Chats => (&mut self.chat_list, self.visible.len()),
+            Focus::Messages => (&mut self.message_list, self.messages.len()),
+        };
+        if len == 0 {
+            return;
+        }
+        let at = list.selected().unwrap_or(0) as isize;
+        list.select(Some(at.saturating_add(delta).clamp(0, len as isize - 1) as usize));
+    }
+
+    fn filter_chats(&mut self) {
+        let filter = self.chat_filter.to_lowercase();
+        self.visible = self
+            .chats
+            .iter()
+            .enumerate()
+            .filter(|(_, chat)| {
+                filter.is_empty()
+                    || chat.name.to_lowercase().contains(&filter)
+                    || chat.identifier.to_lowercase().contains(&filter)
+                    || chat.participants.iter().any(|p| p.to_lowercase().contains(&filter))
+            })
+            .map(|(i, _)| i)
+            .collect();
+        self.chat_list.select((!self.visible.is_empty()).then_some(0));
+    }
+
+    /// Read every message in the chat under the cursor
+    fn open_selected(&mut self) -> Result<(), AppError> {
+        let Some(&index) = self.chat_list.selected().and_then(|i| self.visible.get(i)) else {
+            return Ok(());
+        };
+        let options = ExportOptions {
+            chat_ids: vec![self.chats[index].id],
+            start_date: None,
+            end_date: None,
+            ..self.options.clone()
+        };
+        self.messages = MessageExporter::new(options)?.collect::<Result<_, _>>()?;
+        self.open_chat = Some(index);
+        self.anchor = None;
+        // Newest first in view, as Messages opens a chat
+        self.message_list.select(self.messages.len().checked_sub(1));
+        self.focus = Focus::Messages;
+        self.status = format!("{}: {} messages", self.chats[index].name, self.messages.len());
+        Ok(())
+    }
+
+    /// Move to the next message containing the search, forward or back,
+    /// skipping the one under the cursor if `skip_current`
+    fn find_next(&mut self, direction: isize, skip_current: bool) {
+        let query = self.search.to_lowercase();
+        if query.is_empty() || self.messages.is_empty() {
+            return;
+        }
+        let len = self.messages.len() as isize;
+        let start = self.message_list.selected().unwrap_or(0) as isize;
+        let first = if skip_current { 1 } else { 0 };
+        let found = (first..len).map(|step| (start + step * direction).rem_euclid(len) as usize).find(|&i| {
+            self.messages[i].text.as_deref().is_some_and(|t| t.to_lowercase().contains(&query))
+        });
+        match found {
+            Some(i) => {
+                self.message_list.select(Some(i));
+                self.status = HELP.to_string();
+            }
+            None => self.status = format!("No messages match \"{}\"", self.search),
+        }
+    }
+
+    /// Move to the first message on or after a date
+    fn go_to_date(&mut self, input: &str) {
+        let date = match dates::parse_date(input, self.options.timezone, Utc::now()) {
+            Ok(date) => date,
+            Err(e) => {
+                self.status = e.to_string();
+                return;
+            }
+        };
+        let at = self.messages.partition_point(|m| m.date < date);
+        self.message_list.select(Some(at.min(self.messages.len().saturating_sub(1))));
+        if at == self.messages.len() {
+            self.status = format!("No messages after {}", input.trim());
+        }
+    }
+
+    /// The messages to export: the selection, or the whole chat without one
+    fn selection(&self) -> Option<(usize, usize)> {
+        let cursor = self.message_list.selected()?;
+        Some(match self.anchor {
+            Some(anchor) => (anchor.min(cursor), anchor.max(cursor)),
+            None => (0, self.messages.len().checked_sub(1)?),
+        })
+    }
+
+    /// Export the selection to `path` through the export pipeline, in the
+    /// format its extension names: .csv, .ndjson, or JSON otherwise
+    fn export(&mut self, path: &str) -> Result<(), AppError> {
+        let (Some(chat), Some((first, last))) = (self.open_chat, self.selection()) else {
+            return Ok(());
+        };
+        if path.is_empty() {
+            return Ok(());
+        }
+        let format = match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("csv") => OutputFormat::Csv,
+            Some("ndjson" | "jsonl") => OutputFormat::Ndjson,
+            _ => OutputFormat::Json,
+        };
+        // Dates are exported to the second, so take in the whole last second
+        let options = ExportOptions {
+            chat_ids: vec![self.chats[chat].id],
+            start_date: Some(self.messages[first].date.to_utc()),
+            end_date: Some(self.messages[last].date.to_utc() + Duration::milliseconds(999)),
+            ..self.options.clone()
+        };
+        let columns: Vec<String> = output::DEFAULT_CSV_COLUMNS.iter().map(|c| c.to_string()).collect();
+        let mut out = BufWriter::new(File::create(path)?);
+        let mut writer = format.writer(&mut out, &columns).expect("json, csv and ndjson are streamed");
+        let mut exported = 0;
+        let records = MessageExporter::new(options)?.inspect(|record| exported += record.is_ok() as usize);
+        writer::write_records(writer.as_mut(), records)?;
+        info!(exported, path, "exported selection");
+        self.status = format!("Exported {} messages to {}", exported, path);
+        self.anchor = None;
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] = Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [left, right] = Layout::horizontal([Constraint::Percentage(30), Constraint::Percentage(70)]).areas(main);
+        self.draw_chats(frame, left);
+        self.draw_messages(frame, right);
+
+        let line = match &self.prompt {
+            Some(prompt) => Line::from(vec![Span::raw(prompt.kind.label()), Span::raw(&prompt.input), Span::raw("▏")]),
+            None => Line::from(Span::styled(self.status.as_str(), Style::new().fg(Color::DarkGray))),
+        };
+        frame.render_widget(Paragraph::new(line), status);
+    }
+
+    fn block(&self, title: String, focus: Focus) -> Block<'static> {
+        let style = if self.focus == focus { Style::new().fg(Color::Cyan) } else { Style::new() };
+        Block::new().borders(Borders::ALL).border_style(style).title(title)
+    }
+
+    fn draw_chats(&mut self, frame: &mut Frame, area: Rect) {
+        let items: Vec<ListItem> = self
+            .visible
+            .iter()
+            .map(|&i| {
+                let chat = &self.chats[i];
+                ListItem::new(Line::from(vec![
+                    Span::raw(chat.name.clone()),
+                    Span::styled(format!(" {}", chat.message_count), Style::new().fg(Color::DarkGray)),
+                ]))
+            })
+            .collect();
+        let title = if self.chat_filter.is_empty() {
+            format!(" Chats ({}) ", self.visible.len())
+        } else {
+            format!(" Chats matching \"{}\" ({}) ", self.chat_filter, self.visible.len())
+        };
+        let list = List::new(items)
+            .block(self.block(title, Focus::Chats))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, area, &mut self.chat_list);
+    }
+
+    fn draw_messages(&mut self, frame: &mut Frame, area: Rect) {
+        let selected = self.anchor.and(self.selection());
+        let items: Vec<ListItem> = self
+            .messages
+            .iter()
+            .enumerate()
+            .map(|(i, message)| {
+                let sender = output::sender_label(message);
+                let mut header = vec![
+                    Span::styled(message.date.format("%Y-%m-%d %H:%M ").to_string(), Style::new().fg(Color::DarkGray)),
+                    Span::styled(sender, Style::new().add_modifier(Modifier::BOLD)),
+                ];
+                if !message.attachments.is_empty() {
+                    header.push(Span::styled(
+                        format!(" [{} attachment(s)]", message.attachments.len()),
+                        Style::new().fg(Color::Yellow),
+                    ));
+                }
+                let mut text = Text::from(Line::from(header));
+                for line in message.text.as_deref().unwrap_or_default().lines() {
+                    text.push_line(Line::raw(format!("  {}", line)));
+                }
+                let item = ListItem::new(text);
+                match selected {
+                    Some((first, last)) if (first..=last).contains(&i) => item.style(Style::new().bg(Color::Blue)),
+                    _ => item,
+                }
+            })
+            .collect();
+        let title = match self.open_chat {
+            Some(chat) => format!(" {} ", self.chats[chat].name),
+            None => " Messages ".to_string(),
+        };
+        let list = List::new(items)
+            .block(self.block(title, Focus::Messages))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, area, &mut self.message_list);
+    }
+}
@@ -0,0 +1,182 @@
+use imessage_database::{tables::attachment::Attachment, util::platform::Platform};
+use rusqlite::Connection;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+use crate::attachments::hash_file;
+use crate::error::AppError;
+use crate::media::run_tool;
+
+/// How long to wait for iCloud to finish downloading an attachment
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What `--verify-attachments` found on disk for an attachment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AttachmentStatus {
+    /// On disk, and as big as chat.db says
+    Ok,
+    /// On disk, but a different size than chat.db says, as when a transfer
+    /// didn't finish
+    SizeMismatch,
+    /// Stored in iCloud, not downloaded
+    InIcloud,
+    /// Was only in iCloud, and downloaded with `--download-attachments`
+    Downloaded,
+    /// Not on disk and not in iCloud, or chat.db doesn't say where it is
+    Missing,
+}
+
+impl AttachmentStatus {
+    /// Whether the file couldn't be read
+    pub fn is_missing(&self) -> bool {
+        matches!(self, AttachmentStatus::InIcloud | AttachmentStatus::Missing)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttachmentStatus::Ok => "ok",
+            AttachmentStatus::SizeMismatch => "size_mismatch",
+            AttachmentStatus::InIcloud => "in_icloud",
+            AttachmentStatus::Downloaded => "downloaded",
+            AttachmentStatus::Missing => "missing",
+        }
+    }
+
+    /// What the status means, for warnings
+    pub fn describe(&self) -> &'static str {
+        match self {
+            AttachmentStatus::Ok => "ok",
+            AttachmentStatus::SizeMismatch => "a different size than chat.db says",
+            AttachmentStatus::InIcloud => "stored in iCloud, not downloaded",
+            AttachmentStatus::Downloaded => "downloaded from iCloud",
+            AttachmentStatus::Missing => "missing",
+        }
+    }
+}
+
+/// An attachment's status, and the SHA-256 of its file when it could be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    pub status: AttachmentStatus,
+    pub sha256: Option<String>,
+}
+
+/// Checks that the files attachments point to are there and hashes them,
+/// telling files Messages in iCloud has offloaded from ones that are gone
+#[derive(Debug, Clone, Default)]
+pub struct AttachmentVerifier {
+    /// Ask iCloud to download offloaded files, with `brctl` or `fileproviderctl`
+    download: bool,
+}
+
+impl AttachmentVerifier {
+    pub fn new(download: bool) -> Self {
+        AttachmentVerifier { download }
+    }
+
+    /// Check one attachment, downloading it first if it's only in iCloud and
+    /// downloads are on
+    pub fn verify(
+        &self,
+        db: &Connection,
+        attachment: &Attachment,
+        platform: &Platform,
+        db_path: &Path,
+    ) -> Result<Verification, AppError> {
+        let Some(path) = attachment.resolved_attachment_path(platform, db_path, None).map(PathBuf::from) else {
+            return Ok(Verification { status: AttachmentStatus::Missing, sha256: None });
+        };
+
+        let mut status = if is_present(&path) {
+            AttachmentStatus::Ok
+        } else if placeholder(&path).is_file() || is_dataless(&path) || in_cloudkit(db, attachment.rowid)? {
+            AttachmentStatus::InIcloud
+        } else {
+            AttachmentStatus::Missing
+        };
+        if status == AttachmentStatus::InIcloud && self.download {
+            match download(&path) {
+                Ok(()) => status = AttachmentStatus::Downloaded,
+                Err(e) => warn!(path = %path.display(), "couldn't download from iCloud: {}", e),
+            }
+        }
+        if status.is_missing() {
+            return Ok(Verification { status, sha256: None });
+        }
+
+        let size = fs::metadata(&path)?.len();
+        if status == AttachmentStatus::Ok && attachment.total_bytes > 0 && size != attachment.total_bytes as u64 {
+            status = AttachmentStatus::SizeMismatch;
+        }
+        Ok(Verification { status, sha256: Some(hash_file(&path)?) })
+    }
+}
+
+/// Whether the file is on disk with its contents, not just a stand-in for them
+fn is_present(path: &Path) -> bool {
+    path.is_file() && !is_dataless(path)
+}
+
+/// The `.name.icloud` file iCloud leaves in place of one it has evicted
+fn placeholder(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}.icloud", name))
+}
+
+/// Whether the file is there but its contents are only in the cloud, which
+/// APFS marks with the `SF_DATALESS` flag
+#[cfg(target_os = "macos")]
+fn is_dataless(path: &Path) -> bool {
+    use std::os::macos::fs::MetadataExt;
+    const SF_DATALESS: u32 = 0x4000_0000;
+    fs::symlink_metadata(path).is_ok_and(|m| m.st_flags() & SF_DATALESS != 0)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn is_dataless(_path: &Path) -> bool {
+    false
+}
+
+/// Whether Messages in iCloud has a copy of the attachment, so a file that
+/// isn't on disk was offloaded rather than lost. Older databases don't have
+/// the column.
+fn in_cloudkit(db: &Connection, rowid: i32) -> Result<bool, AppError> {
+    let has_column: bool = db.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('attachment') WHERE name = 'ck_record_id'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_column {
+        return Ok(false);
+    }
+    let record: Option<String> =
+        db.query_row("SELECT ck_record_id FROM attachment WHERE ROWID = ?1", [rowid], |row| row.get(0))?;
+    Ok(record.is_some_and(|r| !r.is_empty()))
+}
+
+/// Ask iCloud for the file, with `brctl`, or `fileproviderctl` if that
+/// fails, then wait for it to arrive
+fn download(path: &Path) -> Result<(), AppError> {
+    run_tool(Command::new("brctl").arg("download").arg(path))
+        .or_else(|_| run_tool(Command::new("fileproviderctl").arg("materialize").arg(path)))?;
+    let started = Instant::now();
+    while !is_present(path) {
+        if started.elapsed() > DOWNLOAD_TIMEOUT {
+            return Err(AppError::Media(format!(
+                "{} still wasn't downloaded after {}s",
+                path.display(),
+                DOWNLOAD_TIMEOUT.as_secs()
+            )));
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+    debug!(path = %path.display(), "downloaded from iCloud");
+    Ok(())
+}
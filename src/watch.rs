@@ -0,0 +1,92 @@
+use chrono::{DateTime, Utc};
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::error::AppError;
+use crate::export::{database_file, open_database, wal_file, ExportOptions, MessageExporter, MessageRecord};
+
+/// Modification times of chat.db and its write-ahead log, used to skip polls
+/// when nothing has been written
+fn db_fingerprint(db_path: &Path) -> (Option<SystemTime>, Option<SystemTime>) {
+    let mtime = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    (mtime(db_path), mtime(&wal_file(db_path)))
+}
+
+/// The highest message ROWID currently in the database
+pub fn max_rowid(db_path: &Path) -> Result<i32, AppError> {
+    let (db, _) = open_database(db_path)?;
+    let rowid: Option<i32> = db.query_row("SELECT MAX(ROWID) FROM message", [], |row| row.get(0))?;
+    Ok(rowid.unwrap_or(0))
+}
+
+/// Polls chat.db and reports each new message matching the options as it arrives
+pub struct Watcher {
+    options: ExportOptions,
+    interval: Duration,
+    last_rowid: i32,
+    last_date: Option<DateTime<Utc>>,
+}
+
+impl Watcher {
+    /// Watch for messages newer than `options.after_rowid`, or newer than
+    /// anything currently in the database if it isn't set
+    pub fn new(options: ExportOptions, interval: Duration) -> Result<Self, AppError> {
+        let last_rowid = match options.after_rowid {
+            Some(rowid) => rowid,
+            None => max_rowid(&options.db_path)?,
+        };
+        Ok(Watcher {
+            options,
+            interval,
+            last_rowid,
+            last_date: None,
+        })
+    }
+
+    /// ROWID and date of the newest message read so far, once one has been read
+    pub fn last_seen(&self) -> Option<(i32, DateTime<Utc>)> {
+        self.last_date.map(|date| (self.last_rowid, date))
+    }
+
+    /// Export anything that arrived since the last poll
+    pub fn poll<F>(&mut self, on_record: &mut F) -> Result<(), AppError>
+    where
+        F: FnMut(MessageRecord) -> Result<(), AppError>,
+    {
+        let options = ExportOptions {
+            after_rowid: Some(self.last_rowid),
+            ..self.options.clone()
+        };
+        let mut exporter = MessageExporter::new(options)?;
+        for record in exporter.by_ref() {
+            on_record(record?)?;
+        }
+        if let Some((rowid, date)) = exporter.last_seen() {
+            self.last_rowid = rowid;
+            self.last_date = Some(date);
+        }
+        Ok(())
+    }
+
+    /// Poll forever, calling `on_record` for each new message and `on_poll`
+    /// after each poll that read the database
+    pub fn run<F, P>(&mut self, mut on_record: F, mut on_poll: P) -> Result<(), AppError>
+    where
+        F: FnMut(MessageRecord) -> Result<(), AppError>,
+        P: FnMut(&Watcher) -> Result<(), AppError>,
+    {
+        let (db_file, _) = database_file(&self.options.db_path)?;
+        let mut fingerprint = None;
+        loop {
+            let current = db_fingerprint(&db_file);
+            if fingerprint != Some(current) {
+                self.poll(&mut on_record)?;
+                on_poll(self)?;
+                fingerprint = Some(current);
+            }
+            thread::sleep(self.interval);
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use serde::Serialize;
+use std::thread;
+use std::time::Duration;
+
+use crate::error::AppError;
+
+/// Delay before the first retry; doubles after each failed attempt
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// POSTs JSON payloads to an HTTP endpoint, retrying with exponential backoff
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: String,
+    retries: u32,
+}
+
+impl Webhook {
+    pub fn new(url: &str, retries: u32) -> Self {
+        Webhook {
+            url: url.to_string(),
+            retries,
+        }
+    }
+
+    /// Deliver one payload, giving up after the configured number of retries
+    pub fn deliver<T: Serialize>(&self, payload: &T) -> Result<(), AppError> {
+        self.deliver_body("application/json", serde_json::to_string(payload)?.as_bytes())
+    }
+
+    /// Deliver a body that's already encoded as `content_type`
+    pub fn deliver_body(&self, content_type: &str, body: &[u8]) -> Result<(), AppError> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let result = ureq::post(&self.url)
+                .header("Content-Type", content_type)
+                .send(body);
+            match result {
+                Ok(_) => return Ok(()),
+                Err(e) if attempt >= self.retries => {
+                    return Err(AppError::Http(format!(
+                        "POST {} failed after {} attempts: {}",
+                        self.url,
+                        attempt + 1,
+                        e
+                    )))
+                }
+                Err(_) => {
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}